@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use influx_writer::MemoryPoint;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(line) = std::str::from_utf8(data) {
+        // must not panic, however malformed the line
+        let _ = MemoryPoint::parse(line);
+    }
+});
@@ -0,0 +1,58 @@
+#![no_main]
+use std::str::FromStr;
+use libfuzzer_sys::fuzz_target;
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use decimal::d128;
+use uuid::Uuid;
+use influx_writer::{OwnedMeasurement, OwnedValue, serialize_owned};
+
+#[derive(Arbitrary, Debug)]
+enum Value {
+    String(String),
+    Float(f64),
+    Integer(i64),
+    Boolean(bool),
+    D128(String),
+    Uuid(u128),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    key: String,
+    tags: Vec<(String, String)>,
+    fields: Vec<(String, Value)>,
+    timestamp: Option<i64>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut m = OwnedMeasurement::new(input.key);
+    for (k, v) in input.tags {
+        m = m.add_tag(k, v);
+    }
+    for (k, v) in input.fields {
+        let v = match v {
+            Value::String(s) => OwnedValue::String(s),
+            Value::Float(f) => OwnedValue::Float(f),
+            Value::Integer(i) => OwnedValue::Integer(i),
+            Value::Boolean(b) => OwnedValue::Boolean(b),
+            Value::D128(s) => match d128::from_str(&s) {
+                Ok(d) => OwnedValue::D128(d),
+                Err(_) => continue,
+            },
+            Value::Uuid(u) => OwnedValue::Uuid(Uuid::from_u128(u)),
+        };
+        m = m.add_field(k, v);
+    }
+    if let Some(t) = input.timestamp {
+        m = m.set_timestamp(t);
+    }
+
+    let mut buf = String::new();
+    serialize_owned(&m, &mut buf);
+    assert!(!buf.is_empty());
+
+    // serializing onto a non-empty buffer only ever appends
+    let mut buf2 = String::from("prefix");
+    serialize_owned(&m, &mut buf2);
+    assert_eq!(&buf2["prefix".len()..], buf.as_str());
+});
@@ -0,0 +1,503 @@
+//! Optional http listener for controlling a running writer (see
+//! `InfluxWriterBuilder::admin` for the endpoints).
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use hyper::buffer::BufReader;
+use hyper::header::{Authorization, Bearer, Headers};
+use hyper::method::Method;
+use hyper::net::{HttpStream, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use slog::{Drain, FilterLevel, Logger, OwnedKVList, Record};
+use crate::{Counters, Error, WriterConfig};
+
+/// How long the listener waits for a connection's request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State shared between the admin listener (or config file watcher, see
+/// `watch`) and the writer thread.
+#[derive(Debug)]
+pub(crate) struct Control {
+    stats: Arc<Counters>,
+    paused: AtomicBool,
+    disabled: RwLock<HashSet<String>>,
+    log_level: Arc<AtomicUsize>,
+    flush_tx: Sender<()>,
+    buffer_size: AtomicUsize,
+    /// nanoseconds
+    flush_interval: AtomicU64,
+    sampling: RwLock<HashMap<String, u32>>,
+    /// incremented on each change to `sampling`
+    sampling_version: AtomicUsize,
+    /// kept up to date with the settings above
+    config: Arc<RwLock<WriterConfig>>,
+}
+
+impl Control {
+    /// Returns the shared state, and the receiver for flush requests (the
+    /// writer thread should select on it along with the measurement channel).
+    ///
+    /// The initial buffer size, flush interval and sampling rates are read
+    /// from `config`.
+    ///
+    pub fn new(stats: Arc<Counters>, config: Arc<RwLock<WriterConfig>>) -> (Arc<Self>, Receiver<()>) {
+        let (flush_tx, flush_rx) = bounded(1);
+        let (buffer_size, flush_interval, sampling) = config.read()
+            .map(|config| (config.buffer_size, config.flush_interval, config.sampling.iter().cloned().collect()))
+            .unwrap_or_default();
+        let ctl = Control {
+            stats,
+            paused: AtomicBool::new(false),
+            disabled: RwLock::new(HashSet::new()),
+            log_level: Arc::new(AtomicUsize::new(FilterLevel::Trace.as_usize())),
+            flush_tx,
+            buffer_size: AtomicUsize::new(buffer_size),
+            flush_interval: AtomicU64::new(flush_interval.as_nanos() as u64),
+            sampling: RwLock::new(sampling),
+            sampling_version: AtomicUsize::new(0),
+            config,
+        };
+        ctl.update_config();
+        (Arc::new(ctl), flush_rx)
+    }
+
+    /// Whether a measurement with `key` should be written (i.e. the writer
+    /// is not paused, and `key` has not been disabled).
+    ///
+    #[inline]
+    pub fn accepts(&self, key: &str) -> bool {
+        !self.paused.load(Ordering::Relaxed)
+            && !self.disabled.read().map(|disabled| disabled.contains(key)).unwrap_or(false)
+    }
+
+    /// Wraps `logger` so the max level of the writer's logging can be
+    /// changed at runtime. Records are still subject to the filtering of
+    /// `logger`'s drain.
+    ///
+    pub fn logger(&self, logger: Logger) -> Logger {
+        Logger::root(DynamicLevel { drain: logger, level: Arc::clone(&self.log_level) }, o!())
+    }
+
+    fn level(&self) -> FilterLevel {
+        FilterLevel::from_usize(self.log_level.load(Ordering::Relaxed)).unwrap_or(FilterLevel::Trace)
+    }
+
+    pub fn set_level(&self, level: FilterLevel) {
+        self.log_level.store(level.as_usize(), Ordering::Relaxed);
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn set_disabled_keys(&self, keys: &[String]) {
+        if let Ok(mut disabled) = self.disabled.write() {
+            *disabled = keys.iter().cloned().collect();
+        }
+    }
+
+    /// Measurements per batch, see `InfluxWriterBuilder::buffer_size`.
+    ///
+    #[inline]
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size.load(Ordering::Relaxed)
+    }
+
+    pub fn set_buffer_size(&self, n: usize) {
+        self.buffer_size.store(n, Ordering::Relaxed);
+    }
+
+    /// How long a partly filled batch may wait before it is sent.
+    ///
+    #[inline]
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_nanos(self.flush_interval.load(Ordering::Relaxed))
+    }
+
+    pub fn set_flush_interval(&self, interval: Duration) {
+        self.flush_interval.store(interval.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Keep one in every `n` measurements with `key` (1 keeps all of them).
+    ///
+    pub fn set_sample_rate(&self, key: &str, n: u32) {
+        if let Ok(mut sampling) = self.sampling.write() {
+            if sampling.get(key) == Some(&n) { return }
+            sampling.insert(key.to_string(), n);
+            self.sampling_version.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The sampling rates by key, if they changed since `version` (which is
+    /// updated).
+    ///
+    #[inline]
+    pub fn sampling_since(&self, version: &mut usize) -> Option<HashMap<String, u32>> {
+        let current = self.sampling_version.load(Ordering::Relaxed);
+        if current == *version { return None }
+        *version = current;
+        self.sampling.read().ok().map(|sampling| sampling.clone())
+    }
+
+    fn disabled_keys(&self) -> Vec<String> {
+        let mut disabled: Vec<String> = self.disabled.read()
+            .map(|disabled| disabled.iter().cloned().collect())
+            .unwrap_or_default();
+        disabled.sort();
+        disabled
+    }
+
+    /// Copies the current runtime settings to the shared `WriterConfig`.
+    ///
+    pub fn update_config(&self) {
+        let mut sampling: Vec<(String, u32)> = self.sampling.read()
+            .map(|sampling| sampling.iter().map(|(k, n)| (k.clone(), *n)).collect())
+            .unwrap_or_default();
+        sampling.sort();
+        if let Ok(mut config) = self.config.write() {
+            config.paused = self.paused.load(Ordering::Relaxed);
+            config.log_level = self.level().as_str().to_lowercase();
+            config.disabled_keys = self.disabled_keys();
+            config.buffer_size = self.buffer_size();
+            config.flush_interval = self.flush_interval();
+            config.sampling = sampling;
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let level = self.level();
+        let disabled: Vec<String> = self.disabled_keys().iter().map(|key| format!("\"{}\"", escape(key))).collect();
+        let stats = self.stats.snapshot();
+        format!("{{\"paused\":{},\"log_level\":\"{}\",\"disabled_keys\":[{}],\"n_rcvd\":{},\"n_dropped\":{},\
+                 \"n_batches_ok\":{},\"n_batches_failed\":{},\"n_request_errors\":{},\"n_bytes_sent\":{},\
+                 \"n_outstanding\":{},\"backlog_len\":{}}}",
+            self.paused.load(Ordering::Relaxed),
+            level.as_str().to_lowercase(),
+            disabled.join(","),
+            stats.n_rcvd,
+            stats.n_dropped,
+            stats.n_batches_ok,
+            stats.n_batches_failed,
+            stats.n_request_errors,
+            stats.n_bytes_sent,
+            stats.n_outstanding,
+            stats.backlog_len)
+    }
+
+    /// Performs the command at `path`, returning the response status and body.
+    ///
+    fn handle(&self, method: &Method, path: &str) -> (StatusCode, String) {
+        let response = self.perform(method, path);
+        self.update_config();
+        response
+    }
+
+    fn perform(&self, method: &Method, path: &str) -> (StatusCode, String) {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, &segments[..]) {
+            (&Method::Get, ["stats"]) => (StatusCode::Ok, self.to_json()),
+
+            (&Method::Post, ["pause"]) => {
+                self.paused.store(true, Ordering::Relaxed);
+                (StatusCode::Ok, self.to_json())
+            }
+
+            (&Method::Post, ["resume"]) => {
+                self.paused.store(false, Ordering::Relaxed);
+                (StatusCode::Ok, self.to_json())
+            }
+
+            (&Method::Post, ["flush"]) => {
+                // a full channel means a flush is already pending
+                let _ = self.flush_tx.try_send(());
+                (StatusCode::Accepted, self.to_json())
+            }
+
+            (&Method::Post, ["log-level", level]) => {
+                match FilterLevel::from_str(level) {
+                    Ok(level) => {
+                        self.log_level.store(level.as_usize(), Ordering::Relaxed);
+                        (StatusCode::Ok, self.to_json())
+                    }
+                    Err(_) => (StatusCode::BadRequest, format!("{{\"error\":\"invalid log level: {}\"}}", escape(level))),
+                }
+            }
+
+            (&Method::Post, ["keys", key, action @ "disable"]) | (&Method::Post, ["keys", key, action @ "enable"]) => {
+                let key = match percent_decode(key) {
+                    Some(key) => key,
+                    None => return (StatusCode::BadRequest, format!("{{\"error\":\"invalid key: {}\"}}", escape(key))),
+                };
+                if let Ok(mut disabled) = self.disabled.write() {
+                    if *action == "disable" {
+                        disabled.insert(key);
+                    } else {
+                        disabled.remove(&key);
+                    }
+                }
+                (StatusCode::Ok, self.to_json())
+            }
+
+            (&Method::Get, _) | (&Method::Post, _) => (StatusCode::NotFound, "{\"error\":\"not found\"}".to_string()),
+
+            _ => (StatusCode::MethodNotAllowed, "{\"error\":\"method not allowed\"}".to_string()),
+        }
+    }
+}
+
+/// The admin listener. Stops listening when dropped, i.e. when the writer
+/// thread exits.
+///
+pub(crate) struct Admin {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl Admin {
+    /// Starts the admin listener on `addr`. If `token` is given, requests
+    /// to the endpoints that change settings must carry it as a bearer
+    /// token (`Authorization: Bearer <token>`).
+    ///
+    pub fn listen(addr: &str, token: Option<String>, ctl: Arc<Control>, logger: &Logger) -> Result<Self, Error> {
+        let listener = TcpListener::bind(addr)
+            .map_err(|e| Error::Config(format!("failed to bind admin listener to {}: {}", addr, e)))?;
+        let local_addr = listener.local_addr()
+            .map_err(|e| Error::Config(format!("failed to bind admin listener to {}: {}", addr, e)))?;
+        let logger = logger.new(o!("thread" => "InfluxWriter:admin"));
+        info!(logger, "admin listener started"; "addr" => addr);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&shutdown);
+        let accept = thread::Builder::new().name("inflx-admin".to_string()).spawn(move || {
+            for stream in listener.incoming() {
+                // woken by `drop` connecting to the listener
+                if stop.load(Ordering::Acquire) { return }
+                match stream {
+                    Ok(stream) => serve(stream, token.as_deref(), &ctl, &logger),
+                    Err(e) => warn!(logger, "admin: failed to accept connection: {}", e),
+                }
+            }
+        }).map_err(Error::Spawn)?;
+        Ok(Admin { addr: local_addr, shutdown, accept: Some(accept) })
+    }
+
+    #[cfg(test)]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Admin {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Release);
+        // wake the accept thread, which closes the listener on returning
+        let mut wake = self.addr;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        if TcpStream::connect(wake).is_ok() {
+            if let Some(accept) = self.accept.take() {
+                let _ = accept.join();
+            }
+        }
+    }
+}
+
+/// Handles the one request read from `stream` (the listener doesn't keep
+/// connections alive), on the accept thread: requests are rare, and quick
+/// to handle.
+///
+fn serve(stream: TcpStream, token: Option<&str>, ctl: &Control, logger: &Logger) {
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer,
+        Err(_) => return,
+    };
+    // so a client that never sends its request can't hold up the listener
+    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+    let mut out = match stream.try_clone() {
+        Ok(out) => out,
+        Err(e) => {
+            warn!(logger, "admin: failed to accept connection: {}", e);
+            return
+        }
+    };
+    let mut stream = HttpStream(stream);
+    let mut reader = BufReader::new(&mut stream as &mut dyn NetworkStream);
+    let req = match Request::new(&mut reader, peer) {
+        Ok(req) => req,
+        Err(e) => {
+            warn!(logger, "admin: invalid request: {}", e; "peer" => %peer);
+            return
+        }
+    };
+    let path = match req.uri {
+        RequestUri::AbsolutePath(ref path) => path.split('?').next().unwrap_or("").to_string(),
+        _ => String::new(),
+    };
+    let before = ctl.config.read().map(|config| config.clone()).unwrap_or_default();
+    let (status, body) = if req.method != Method::Get && !authorized(&req.headers, token) {
+        (StatusCode::Unauthorized, "{\"error\":\"unauthorized\"}".to_string())
+    } else {
+        ctl.handle(&req.method, &path)
+    };
+    info!(logger, "admin request"; "method" => %req.method, "path" => &path, "status" => %status);
+    if let Ok(after) = ctl.config.read() {
+        for (setting, old, new) in before.diff(&after) {
+            info!(logger, "InfluxWriter: config changed"; "setting" => setting, "old" => old, "new" => new);
+        }
+    }
+    let mut headers = Headers::new();
+    let mut resp = Response::new(&mut out, &mut headers);
+    *resp.status_mut() = status;
+    resp.headers_mut().set(hyper::header::ContentType::json());
+    resp.headers_mut().set(hyper::header::Connection::close());
+    if status == StatusCode::Unauthorized {
+        resp.headers_mut().set_raw("WWW-Authenticate", vec![b"Bearer".to_vec()]);
+    }
+    match resp.start() {
+        Ok(mut resp) => {
+            let _ = resp.write_all(body.as_bytes()).and_then(|_| resp.end());
+        }
+        Err(e) => warn!(logger, "admin: failed to send response: {}", e),
+    };
+}
+
+/// Whether `headers` carry bearer token `token` (always, if there's no
+/// token).
+///
+fn authorized(headers: &Headers, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) => token,
+        None => return true,
+    };
+    let given = headers.get::<Authorization<Bearer>>().map(|auth| auth.0.token.as_str()).unwrap_or("");
+    // compared in constant time, so the token can't be guessed a byte at
+    // a time by timing responses
+    given.len() == token.len()
+        && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// `s` with its %-escapes decoded, or `None` if they're invalid or don't
+/// decode to utf8.
+///
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Drain that discards records above a level that can be changed at runtime.
+struct DynamicLevel {
+    drain: Logger,
+    level: Arc<AtomicUsize>,
+}
+
+impl Drain for DynamicLevel {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+        if record.level().as_usize() <= self.level.load(Ordering::Relaxed) {
+            Drain::log(&self.drain, record, values)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_pauses_disables_keys_and_sets_log_level() {
+        let config = Arc::new(RwLock::new(WriterConfig::default()));
+        let (ctl, flush_rx) = Control::new(Arc::new(Counters::default()), Arc::clone(&config));
+        assert!(ctl.accepts("a"));
+        assert_eq!(config.read().unwrap().log_level, "trace");
+
+        assert_eq!(ctl.handle(&Method::Post, "/keys/a/disable").0, StatusCode::Ok);
+        assert!(!ctl.accepts("a"));
+        assert!(ctl.accepts("b"));
+        assert!(ctl.to_json().contains("\"disabled_keys\":[\"a\"]"));
+        ctl.handle(&Method::Post, "/keys/a/enable");
+        assert!(ctl.accepts("a"));
+        assert_eq!(ctl.handle(&Method::Post, "/keys/a%2Fb%20c/disable").0, StatusCode::Ok);
+        assert!(!ctl.accepts("a/b c"));
+        ctl.handle(&Method::Post, "/keys/a%2fb%20c/enable");
+        assert!(ctl.accepts("a/b c"));
+        assert_eq!(ctl.handle(&Method::Post, "/keys/a%2/disable").0, StatusCode::BadRequest);
+
+        ctl.handle(&Method::Post, "/pause");
+        assert!(!ctl.accepts("b"));
+        assert!(config.read().unwrap().paused);
+        ctl.handle(&Method::Post, "/resume");
+        assert!(ctl.accepts("b"));
+
+        assert_eq!(ctl.handle(&Method::Post, "/log-level/warn").0, StatusCode::Ok);
+        assert_eq!(ctl.log_level.load(Ordering::Relaxed), FilterLevel::Warning.as_usize());
+        assert_eq!(ctl.handle(&Method::Post, "/log-level/loud").0, StatusCode::BadRequest);
+
+        assert_eq!(ctl.handle(&Method::Post, "/flush").0, StatusCode::Accepted);
+        assert!(flush_rx.try_recv().is_ok());
+
+        assert_eq!(ctl.handle(&Method::Get, "/nope").0, StatusCode::NotFound);
+        assert_eq!(ctl.handle(&Method::Get, "/pause").0, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn it_requires_the_token_to_change_settings_and_stops_listening_on_drop() {
+        use std::io::Read;
+
+        let config = Arc::new(RwLock::new(WriterConfig::default()));
+        let (ctl, _flush_rx) = Control::new(Arc::new(Counters::default()), config);
+        let logger = Logger::root(slog::Discard, o!());
+        let admin = Admin::listen("127.0.0.1:0", Some("s3cret".to_string()), Arc::clone(&ctl), &logger).unwrap();
+        let addr = admin.local_addr();
+        let request = |method: &str, path: &str, auth: Option<&str>| -> String {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let auth = auth.map(|token| format!("Authorization: Bearer {}\r\n", token)).unwrap_or_default();
+            write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: 0\r\n\r\n", method, path, auth).unwrap();
+            let mut resp = String::new();
+            stream.read_to_string(&mut resp).unwrap();
+            resp.lines().next().unwrap_or("").to_string()
+        };
+
+        assert_eq!(request("GET", "/stats", None), "HTTP/1.1 200 OK");
+        assert_eq!(request("POST", "/pause", None), "HTTP/1.1 401 Unauthorized");
+        assert_eq!(request("POST", "/pause", Some("wrong!")), "HTTP/1.1 401 Unauthorized");
+        assert!(ctl.accepts("a"));
+        assert_eq!(request("POST", "/pause", Some("s3cret")), "HTTP/1.1 200 OK");
+        assert!(!ctl.accepts("a"));
+
+        drop(admin);
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
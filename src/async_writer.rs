@@ -0,0 +1,333 @@
+//! An `InfluxWriter` for tokio applications (requires the "tokio" feature).
+//!
+//! Measurements are batched on a tokio task instead of a dedicated thread,
+//! and sent with an async http client.
+
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+use hyper014::{Body, Client, Request, StatusCode};
+use hyper014::client::HttpConnector;
+use slog::{Drain, Logger};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+use crate::{append_line, Error, InfluxWriterBuilder, OwnedMeasurement, RejectedBatch, RetryPolicy, MAX_BATCH_BYTES};
+use crate::dead_letter::DeadLetter;
+
+/// max concurrent requests to the server
+const MAX_OUTSTANDING_HTTP: usize = 64;
+
+const INITIAL_BUFFER_CAPACITY: usize = 4096;
+
+/// Sends measurements to influxdb from a tokio task. Created with
+/// `InfluxWriterBuilder::build_async`, which must be called from within a
+/// tokio runtime.
+///
+/// `send` doesn't block (or need to be awaited), so `measure!` works the
+/// same as with `InfluxWriter`:
+///
+/// ```no_run
+/// #[macro_use]
+/// extern crate influx_writer;
+///
+/// use influx_writer::InfluxWriter;
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() {
+///     let influx = InfluxWriter::builder("localhost", "test").build_async().unwrap();
+///     measure!(influx, requests, t(route, "/"), i(n, 1));
+///     influx.close().await.unwrap();
+/// }
+/// ```
+///
+/// Note: of the builder's options, only `logger`, `credentials`,
+/// `buffer_size`, `flush_interval`, `flush_bytes`, `queue_capacity`,
+/// `retry_policy`, `dead_letter_file`, `dead_letter_channel`,
+/// `encryption_key`, `retention_policy`, `float_precision`, `default_tag`,
+/// `with_standard_tags` and `key_prefix` apply. https is not supported.
+/// Unlike `InfluxWriter`, a batch rejected with any 4xx status (other than
+/// 429) goes to the dead letter destination without being retried.
+///
+#[derive(Debug)]
+pub struct AsyncInfluxWriter {
+    host: String,
+    db: String,
+    tx: mpsc::Sender<OwnedMeasurement>,
+    task: JoinHandle<()>,
+}
+
+impl AsyncInfluxWriter {
+    pub(crate) fn spawn(config: InfluxWriterBuilder) -> Result<Self, Error> {
+        if config.https || crate::host::is_https(&config.host) {
+            return Err(Error::Config("https is not supported by AsyncInfluxWriter".to_string()))
+        }
+        let mut url = crate::host::endpoint(&config.host, false, "write")?;
+        url.query_pairs_mut().append_pair("db", &config.db).append_pair("precision", "ns");
+        if let Some(ref rp) = config.retention_policy { url.query_pairs_mut().append_pair("rp", rp); }
+        Ok(Self::start(config, url.to_string()))
+    }
+
+    /// Spawns the writer task, which posts batches to `url`.
+    ///
+    fn start(config: InfluxWriterBuilder, url: String) -> Self {
+        let (host, db) = (config.host.clone(), config.db.clone());
+        let logger = config.logger.clone().unwrap_or_else(|| Logger::root(slog::Discard.fuse(), o!()));
+        let logger = logger.new(o!(
+            "host" => host.clone(),
+            "db" => db.clone(),
+            "task" => "AsyncInfluxWriter"));
+        let auth = config.creds.as_ref().map(|creds| BasicAuth(&creds.0).to_string());
+        let (tx, rx) = mpsc::channel(config.queue_capacity);
+        let dead_letter = match config.dead_letter {
+            Some(DeadLetter::File(ref path, _)) => Some(DeadLetter::File(path.clone(), config.encryption_key.clone())),
+            ref other => other.clone(),
+        };
+        let post = Post {
+            client: Client::new(),
+            url,
+            auth,
+            retry: config.retry.clone(),
+            dead_letter,
+            logger: logger.clone(),
+        };
+        let task = tokio::spawn(run(rx, config, Arc::new(post), logger));
+        AsyncInfluxWriter { host, db, tx, task }
+    }
+
+    pub fn host(&self) -> &str { self.host.as_str() }
+
+    pub fn db(&self) -> &str { self.db.as_str() }
+
+    /// Queues `m` to be sent, returning `Error::QueueFull` if the queue is
+    /// full rather than waiting (see `send_async`).
+    ///
+    pub fn send(&self, m: OwnedMeasurement) -> Result<(), Error> {
+        self.tx.try_send(m).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(m) => Error::QueueFull(Box::new(m)),
+            mpsc::error::TrySendError::Closed(m) => Error::Queue(Box::new(m)),
+        })
+    }
+
+    /// Queues `m` to be sent, waiting for room in the queue if it is full.
+    ///
+    pub async fn send_async(&self, m: OwnedMeasurement) -> Result<(), Error> {
+        self.tx.send(m).await.map_err(|mpsc::error::SendError(m)| Error::Queue(Box::new(m)))
+    }
+
+    /// Sends any buffered measurements and waits for all requests to
+    /// finish (or exhaust their retries).
+    ///
+    pub async fn close(self) -> Result<(), Error> {
+        let AsyncInfluxWriter { tx, task, .. } = self;
+        drop(tx);
+        task.await.map_err(|e| Error::Shutdown(format!("writer task failed: {}", e)))
+    }
+}
+
+async fn run(mut rx: mpsc::Receiver<OwnedMeasurement>, config: InfluxWriterBuilder, post: Arc<Post>, logger: Logger) {
+    let n_buffer_lines = config.buffer_size.max(1);
+    let flush_bytes = config.flush_bytes.unwrap_or(usize::MAX);
+    let opts = config.serialize_opts;
+    let permits = Arc::new(Semaphore::new(MAX_OUTSTANDING_HTTP));
+    let mut requests: Vec<JoinHandle<()>> = Vec::new();
+    let mut buf = String::with_capacity(INITIAL_BUFFER_CAPACITY);
+    let mut count = 0;
+    // `interval` panics on a zero period
+    let mut interval = tokio::time::interval(config.flush_interval.max(Duration::from_millis(1)));
+    info!(logger, "initializing AsyncInfluxWriter ..."; "n_buffer_lines" => n_buffer_lines);
+
+    loop {
+        let flush = tokio::select! {
+            m = rx.recv() => match m {
+                Some(mut m) => {
+                    if m.timestamp.is_none() { m.timestamp = Some(crate::now()) }
+                    if m.fields.is_empty() { m.fields.push(("n".into(), crate::OwnedValue::Integer(1))); }
+                    match append_line(&m, &mut buf, count > 0, MAX_BATCH_BYTES, &opts) {
+                        None => count += 1,
+                        Some(line) => {
+                            warn!(logger, "AsyncInfluxWriter: measurement exceeds MAX_BATCH_BYTES, sending in its own request";
+                                "key" => %m.key, "line.len()" => line.len());
+                            requests.push(spawn_post(&post, &permits, line).await);
+                        }
+                    }
+                    count >= n_buffer_lines || buf.len() >= flush_bytes
+                }
+
+                None => break,
+            },
+
+            _ = interval.tick() => count > 0,
+        };
+
+        if flush {
+            let batch = mem::replace(&mut buf, String::with_capacity(INITIAL_BUFFER_CAPACITY));
+            count = 0;
+            requests.push(spawn_post(&post, &permits, batch).await);
+        }
+        requests.retain(|req| !req.is_finished());
+    }
+
+    if count > 0 {
+        info!(logger, "AsyncInfluxWriter: sending remaining buffer on close"; "count" => count);
+        requests.push(spawn_post(&post, &permits, buf).await);
+    }
+    for req in requests {
+        let _ = req.await;
+    }
+    info!(logger, "AsyncInfluxWriter: closed");
+}
+
+/// Waits for a free request slot, then sends `batch` on a new task.
+///
+async fn spawn_post(post: &Arc<Post>, permits: &Arc<Semaphore>, batch: String) -> JoinHandle<()> {
+    let permit = Arc::clone(permits).acquire_owned().await;
+    let post = Arc::clone(post);
+    tokio::spawn(async move {
+        post.send(batch).await;
+        drop(permit);
+    })
+}
+
+/// Formats `Basic` credentials as an `Authorization` header value.
+struct BasicAuth<'a>(&'a hyper::header::Basic);
+
+impl fmt::Display for BasicAuth<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Basic ")?;
+        hyper::header::Scheme::fmt_scheme(self.0, f)
+    }
+}
+
+struct Post {
+    client: Client<HttpConnector>,
+    url: String,
+    auth: Option<String>,
+    retry: RetryPolicy,
+    dead_letter: Option<DeadLetter>,
+    logger: Logger,
+}
+
+impl Post {
+    async fn send(&self, batch: String) {
+        let logger = &self.logger;
+        for n_req in 0..self.retry.max_attempts.max(1) {
+            if n_req > 0 {
+                tokio::time::sleep(self.retry.delay(n_req)).await;
+            }
+            match self.request(&batch).await {
+                Ok(()) => {
+                    debug!(logger, "batch written ok"; "n_req" => n_req);
+                    return
+                }
+
+                // rejected (e.g. malformed, unauthorized, or for a database
+                // that doesn't exist): retrying won't help, except after
+                // 429 Too Many Requests
+                Err(Error::Transport { status: Some(status), message }) if status < 500 && status != 429 => {
+                    error!(logger, "batch rejected by server, discarding: {}", message; "status" => status, "batch.len()" => batch.len());
+                    if let Some(dead_letter) = self.dead_letter.clone() {
+                        let logger = logger.clone();
+                        let rejected = RejectedBatch { body: batch, status, response: message };
+                        // writing to a dead letter file blocks
+                        let _ = tokio::task::spawn_blocking(move || dead_letter.write(rejected, &logger)).await;
+                    }
+                    return
+                }
+
+                Err(e) => {
+                    warn!(logger, "write failed: {}", e; "n_req" => n_req);
+                }
+            }
+        }
+        error!(logger, "AsyncInfluxWriter: giving up on batch after {} attempts", self.retry.max_attempts.max(1);
+            "batch.len()" => batch.len());
+    }
+
+    async fn request(&self, batch: &str) -> Result<(), Error> {
+        let mut req = Request::post(self.url.as_str());
+        if let Some(ref auth) = self.auth {
+            req = req.header("Authorization", auth.as_str());
+        }
+        let req = req.body(Body::from(batch.to_string()))
+            .map_err(|e| Error::Config(format!("invalid request: {}", e)))?;
+        let resp = self.client.request(req).await
+            .map_err(|e| Error::Transport { status: None, message: e.to_string() })?;
+        let status = resp.status();
+        if status == StatusCode::NO_CONTENT {
+            return Ok(())
+        }
+        let body = hyper014::body::to_bytes(resp.into_body()).await.unwrap_or_default();
+        Err(Error::Transport { status: Some(status.as_u16()), message: String::from_utf8_lossy(&body).into_owned() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use crate::OwnedValue;
+
+    /// accepts one connection, answering each request with `response` and
+    /// returning the bodies of the requests it receives
+    async fn fake_influx(listener: TcpListener, response: &'static [u8]) -> Vec<String> {
+        let (mut sock, _) = listener.accept().await.unwrap();
+        let mut bodies = Vec::new();
+        let mut data = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = sock.read(&mut chunk).await.unwrap();
+            if n == 0 { return bodies }
+            data.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&data).into_owned();
+            if let Some(end) = text.find("\r\n\r\n") {
+                let len: usize = text[..end].lines()
+                    .find_map(|l| l.to_lowercase().strip_prefix("content-length: ").map(|v| v.trim().parse().unwrap()))
+                    .unwrap_or(0);
+                if data.len() >= end + 4 + len {
+                    bodies.push(String::from_utf8_lossy(&data[end + 4..end + 4 + len]).into_owned());
+                    data.drain(..end + 4 + len);
+                    sock.write_all(response).await.unwrap();
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn it_sends_buffered_measurements_on_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(fake_influx(listener, b"HTTP/1.1 204 No Content\r\n\r\n"));
+        let url = format!("http://127.0.0.1:{}/write?db=test&precision=ns", port);
+        let influx = AsyncInfluxWriter::start(InfluxWriterBuilder::new("127.0.0.1", "test"), url);
+        influx.send(OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1))).unwrap();
+        influx.send_async(OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(2))).await.unwrap();
+        influx.close().await.unwrap();
+        let bodies = server.await.unwrap();
+        let lines: Vec<&str> = bodies.iter().flat_map(|b| b.lines()).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("test n=1i "));
+        assert!(lines[1].starts_with("test n=2i "));
+    }
+
+    #[tokio::test]
+    async fn it_sends_batches_rejected_with_a_4xx_status_to_the_dead_letter_without_retrying() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(fake_influx(listener, b"HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nno db xyz"));
+        let url = format!("http://127.0.0.1:{}/write?db=xyz&precision=ns", port);
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let config = InfluxWriterBuilder::new("127.0.0.1", "xyz")
+            .dead_letter_channel(tx)
+            .flush_interval(Duration::from_millis(10));
+        let influx = AsyncInfluxWriter::start(config, url);
+        influx.send(OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1))).unwrap();
+        let rejected = tokio::task::spawn_blocking(move || rx.recv_timeout(Duration::from_secs(5))).await.unwrap().unwrap();
+        assert_eq!(rejected.status, 404);
+        assert_eq!(rejected.response, "no db xyz");
+        assert!(rejected.body.starts_with("test n=1i "));
+        influx.close().await.unwrap();
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+}
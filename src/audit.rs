@@ -0,0 +1,80 @@
+//! Sampling of how long `InfluxWriter::send` takes, enabled with
+//! `InfluxWriterBuilder::audit_enqueue`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use crate::Key;
+
+/// Enqueue latency samples for one measurement key.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Samples {
+    pub n: u64,
+    pub total_nanos: u64,
+    pub max_nanos: u64,
+}
+
+/// Times one in every `sample_every` sends, keeping per-key totals until
+/// they are taken by the self-monitoring thread.
+///
+#[derive(Debug)]
+pub(crate) struct EnqueueAudit {
+    sample_every: u64,
+    n_sends: AtomicU64,
+    by_key: Mutex<HashMap<Key, Samples>>,
+}
+
+impl EnqueueAudit {
+    pub fn new(sample_every: u32) -> Self {
+        EnqueueAudit {
+            sample_every: u64::from(sample_every.max(1)),
+            n_sends: AtomicU64::new(0),
+            by_key: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether this send should be timed.
+    ///
+    #[inline]
+    pub fn sample(&self) -> bool {
+        let i = self.n_sends.fetch_add(1, Ordering::Relaxed) % self.sample_every;
+        i == 0
+    }
+
+    pub fn record(&self, key: &Key, elapsed: Duration) {
+        let nanos = elapsed.as_nanos() as u64;
+        let mut by_key = self.by_key.lock().unwrap();
+        if !by_key.contains_key(&key[..]) { by_key.insert(key.clone(), Samples::default()); }
+        let samples = by_key.get_mut(&key[..]).unwrap();
+        samples.n += 1;
+        samples.total_nanos += nanos;
+        samples.max_nanos = samples.max_nanos.max(nanos);
+    }
+
+    /// The samples recorded since the last call, by key.
+    ///
+    pub fn take(&self) -> HashMap<Key, Samples> {
+        std::mem::take(&mut *self.by_key.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_samples_one_in_n_sends_and_totals_them_by_key() {
+        let audit = EnqueueAudit::new(2);
+        let sampled: Vec<bool> = (0..4).map(|_| audit.sample()).collect();
+        assert_eq!(sampled, vec![true, false, true, false]);
+        audit.record(&"a".into(), Duration::from_nanos(100));
+        audit.record(&"a".to_string().into(), Duration::from_nanos(300));
+        audit.record(&"b".into(), Duration::from_nanos(50));
+        let samples = audit.take();
+        assert_eq!(samples["a"], Samples { n: 2, total_nanos: 400, max_nanos: 300 });
+        assert_eq!(samples["b"], Samples { n: 1, total_nanos: 50, max_nanos: 50 });
+        assert!(audit.take().is_empty());
+    }
+}
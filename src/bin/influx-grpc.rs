@@ -0,0 +1,81 @@
+//! Runs an `InfluxWriter` behind a grpc endpoint, as a sidecar for
+//! processes in other languages. See `influx_writer::GrpcService` and
+//! `proto/influx_writer.proto`.
+
+use std::process::exit;
+use std::time::Duration;
+use influx_writer::{GrpcService, InfluxWriter};
+
+const USAGE: &str = "\
+usage: influx-grpc --db <db> [options]
+
+options:
+    --listen <addr>          address to serve grpc on (default: 127.0.0.1:50051)
+    --host <host>            influxdb host, host:port or url (default: localhost)
+    --db <db>                database to write to
+    --user <user>            username, with the password in $INFLUX_PASSWORD
+    --spool-dir <dir>        spool batches that can't be written to this directory
+";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("influx-grpc: {}\n\n{}", msg, USAGE);
+    exit(2)
+}
+
+fn main() {
+    let mut listen = "127.0.0.1:50051".to_string();
+    let mut host = "localhost".to_string();
+    let (mut db, mut user, mut spool_dir) = (None, None, None);
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(&format!("{} requires a value", arg)));
+        match arg.as_str() {
+            "--listen" => listen = value(),
+            "--host" => host = value(),
+            "--db" => db = Some(value()),
+            "--user" => user = Some(value()),
+            "--spool-dir" => spool_dir = Some(value()),
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return
+            }
+            _ => fail(&format!("unknown argument: {}", arg)),
+        }
+    }
+    let db = db.unwrap_or_else(|| fail("--db is required"));
+    let addr = listen.parse().unwrap_or_else(|_| fail("invalid --listen"));
+
+    let mut builder = InfluxWriter::builder(&host, &db).check_on_start(true);
+    if let Some(user) = user {
+        builder = builder.credentials(InfluxWriter::get_credentials(user, std::env::var("INFLUX_PASSWORD").ok()));
+    }
+    if let Some(dir) = spool_dir {
+        builder = builder.spool_dir(dir);
+    }
+    let influx = match builder.try_build() {
+        Ok(influx) => influx,
+        Err(e) => {
+            eprintln!("influx-grpc: failed to start writer: {}", e);
+            exit(1)
+        }
+    };
+
+    let rt = tokio::runtime::Runtime::new().unwrap_or_else(|e| {
+        eprintln!("influx-grpc: failed to start tokio runtime: {}", e);
+        exit(1)
+    });
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let served = rt.block_on(GrpcService::new(influx.clone()).serve(addr, shutdown));
+    drop(rt);
+    if let Err(ref e) = served {
+        eprintln!("influx-grpc: {}", e);
+    }
+    if let Err(e) = influx.close(Duration::from_secs(30)) {
+        eprintln!("influx-grpc: failed to flush writer: {}", e);
+        exit(1)
+    }
+    if served.is_err() { exit(1) }
+}
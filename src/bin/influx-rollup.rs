@@ -0,0 +1,103 @@
+//! Rolls a measurement's old points up into a long-retention database and
+//! deletes them, e.g. from a daily cron job. See `influx_writer::Rollup`.
+
+use std::process::exit;
+use std::time::Duration;
+use influx_writer::{Aggregate, InfluxWriter, Rollup};
+
+const USAGE: &str = "\
+usage: influx-rollup --db <db> --measurement <name> --into-db <db> --field <name>=<aggregate>... [options]
+
+options:
+    --host <host>                 influxdb host, host:port or url (default: localhost)
+    --db <db>                     database to roll up from
+    --measurement <name>          measurement to roll up
+    --into-db <db>                database the rollups are written to
+    --into-measurement <name>     measurement the rollups are written to (default: --measurement)
+    --field <name>=<aggregate>    field and aggregate (mean, median, min, max, sum, count, first, last), repeatable
+    --older-than-days <n>         roll up points older than this (default: 30)
+    --interval-secs <n>           width of the rollup buckets (default: 3600)
+    --user <user>                 username, with the password in $INFLUX_PASSWORD
+    --keep                        don't delete the rolled-up points
+";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("influx-rollup: {}\n\n{}", msg, USAGE);
+    exit(2)
+}
+
+fn main() {
+    let mut host = "localhost".to_string();
+    let (mut db, mut measurement, mut into_db, mut into_measurement, mut user) = (None, None, None, None, None);
+    let mut fields = Vec::new();
+    let mut older_than_days = 30;
+    let mut interval_secs = 3600;
+    let mut keep = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(&format!("{} requires a value", arg)));
+        match arg.as_str() {
+            "--host" => host = value(),
+            "--db" => db = Some(value()),
+            "--measurement" => measurement = Some(value()),
+            "--into-db" => into_db = Some(value()),
+            "--into-measurement" => into_measurement = Some(value()),
+            "--field" => {
+                let field = value();
+                match field.split_once('=').and_then(|(name, agg)| Aggregate::parse(agg).map(|agg| (name.to_string(), agg))) {
+                    Some(field) => fields.push(field),
+                    None => fail(&format!("invalid --field: {}", field)),
+                }
+            }
+            "--older-than-days" => older_than_days = value().parse().unwrap_or_else(|_| fail("invalid --older-than-days")),
+            "--interval-secs" => interval_secs = value().parse().unwrap_or_else(|_| fail("invalid --interval-secs")),
+            "--user" => user = Some(value()),
+            "--keep" => keep = true,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return
+            }
+            _ => fail(&format!("unknown argument: {}", arg)),
+        }
+    }
+    let db = db.unwrap_or_else(|| fail("--db is required"));
+    let measurement = measurement.unwrap_or_else(|| fail("--measurement is required"));
+    let into_db = into_db.unwrap_or_else(|| fail("--into-db is required"));
+    if fields.is_empty() { fail("at least one --field is required") }
+
+    let mut rollup = Rollup::new(&host, &db, &measurement)
+        .older_than(Duration::from_secs(older_than_days * 24 * 3600))
+        .interval(Duration::from_secs(interval_secs))
+        .delete(!keep);
+    for (name, agg) in &fields {
+        rollup = rollup.aggregate(name, *agg);
+    }
+    if let Some(ref into) = into_measurement {
+        rollup = rollup.into_measurement(into);
+    }
+    let mut builder = InfluxWriter::builder(&host, &into_db).check_on_start(true);
+    if let Some(user) = user {
+        let creds = InfluxWriter::get_credentials(user, std::env::var("INFLUX_PASSWORD").ok());
+        rollup = rollup.credentials(creds.clone());
+        builder = builder.credentials(creds);
+    }
+    let influx = match builder.try_build() {
+        Ok(influx) => influx,
+        Err(e) => {
+            eprintln!("influx-rollup: failed to start writer: {}", e);
+            exit(1)
+        }
+    };
+    match rollup.run(influx, Duration::from_secs(300)) {
+        Ok(stats) => {
+            println!("rolled up {} before {}: {} series, {} rollups written to {}, source points {}",
+                measurement, stats.cutoff, stats.n_series, stats.n_rollups, into_db,
+                if stats.deleted { "deleted" } else { "kept" });
+        }
+        Err(e) => {
+            eprintln!("influx-rollup: {}", e);
+            exit(1)
+        }
+    }
+}
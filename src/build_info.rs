@@ -0,0 +1,94 @@
+//! Compile-time build provenance, see `build_info!`.
+
+/// Version, git sha, rustc version and build time of a binary, captured at
+/// compile time by `build_info!`.
+///
+/// Values that weren't available when the binary was built are "unknown".
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub rustc_version: &'static str,
+    pub build_time: &'static str,
+}
+
+impl BuildInfo {
+    pub const UNKNOWN: &'static str = "unknown";
+
+    /// Build info with only a version and git sha.
+    ///
+    pub const fn new(version: &'static str, git_sha: &'static str) -> Self {
+        BuildInfo { version, git_sha, rustc_version: Self::UNKNOWN, build_time: Self::UNKNOWN }
+    }
+}
+
+/// Captures a `BuildInfo` for the crate it's called from: the version is
+/// `CARGO_PKG_VERSION`, and the git sha, rustc version and build time are
+/// read from the `GIT_SHA`, `RUSTC_VERSION` and `BUILD_TIMESTAMP` env vars at
+/// compile time, which a build script can set:
+///
+/// ```ignore
+/// // build.rs
+/// use std::process::Command;
+///
+/// fn main() {
+///     let run = |cmd: &str, args: &[&str]| Command::new(cmd).args(args).output().ok()
+///         .and_then(|out| String::from_utf8(out.stdout).ok())
+///         .map(|s| s.trim().to_string())
+///         .unwrap_or_default();
+///     println!("cargo:rustc-env=GIT_SHA={}", run("git", &["rev-parse", "--short", "HEAD"]));
+///     println!("cargo:rustc-env=RUSTC_VERSION={}", run("rustc", &["--version"]));
+///     println!("cargo:rustc-env=BUILD_TIMESTAMP={}", run("date", &["-u", "+%Y-%m-%dT%H:%M:%SZ"]));
+/// }
+/// ```
+///
+/// Works in a `const`, for use with the `v(..)` shortcut of `measure!` and
+/// `InfluxWriterBuilder::build_info`:
+///
+/// ```no_run
+/// #[macro_use]
+/// extern crate influx_writer;
+///
+/// use influx_writer::{BuildInfo, InfluxWriter};
+///
+/// const BUILD: BuildInfo = build_info!();
+///
+/// fn main() {
+///     let influx = InfluxWriter::builder("localhost", "test").build_info(BUILD).build();
+///     measure!(influx, requests, v(BUILD.version), i(n, 1));
+/// }
+/// ```
+///
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: $crate::build_info!(@env "GIT_SHA"),
+            rustc_version: $crate::build_info!(@env "RUSTC_VERSION"),
+            build_time: $crate::build_info!(@env "BUILD_TIMESTAMP"),
+        }
+    };
+
+    (@env $var:literal) => {
+        match option_env!($var) {
+            Some(value) if !value.is_empty() => value,
+            _ => $crate::BuildInfo::UNKNOWN,
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUILD: BuildInfo = build_info!();
+
+    #[test]
+    fn it_captures_the_crate_version() {
+        assert_eq!(BUILD.version, env!("CARGO_PKG_VERSION"));
+        assert!(!BUILD.git_sha.is_empty());
+        assert!(!BUILD.build_time.is_empty());
+    }
+}
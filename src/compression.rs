@@ -0,0 +1,172 @@
+//! Compression of the files the writer leaves on disk: spooled batches (see
+//! `InfluxWriterBuilder::spool_compression`) and a `FileSink`'s rotated
+//! files (see `FileSink::compress_rotated`). Line protocol is very
+//! repetitive, and typically compresses to a tenth of its size or less.
+//!
+//! A compressed file is named with its compression's extension, which is
+//! how the spool and `Replay` know to decompress it.
+
+use std::io::{self, Read};
+use std::path::Path;
+use crate::encryption::{self, EncryptionKey};
+
+/// The extensions of compressed files, and the feature required to read
+/// each.
+///
+const EXTENSIONS: &[(&str, &str)] = &[("gz", "gzip"), ("zst", "zstd"), ("sz", "snappy")];
+
+/// How to compress files written to disk. Each variant requires the
+/// feature of the same name ("snappy" for `Snappy`).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileCompression {
+    /// gzip, adding ".gz" to file names
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// zstd (at its default level), adding ".zst" to file names
+    #[cfg(feature = "zstd")]
+    Zstd,
+    /// snappy (framed format), adding ".sz" to file names. Compresses less
+    /// than zstd, but faster
+    #[cfg(feature = "snappy")]
+    Snappy,
+}
+
+impl FileCompression {
+    /// The extension (without the ".") of files compressed this way.
+    ///
+    pub fn extension(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gzip")]
+            FileCompression::Gzip => "gz",
+            #[cfg(feature = "zstd")]
+            FileCompression::Zstd => "zst",
+            #[cfg(feature = "snappy")]
+            FileCompression::Snappy => "sz",
+        }
+    }
+
+    /// The compression of the file at `path`, going by its extension:
+    /// `None` if it isn't compressed, or an error naming the feature
+    /// required if it's compressed in a way that isn't enabled.
+    ///
+    pub(crate) fn of_path(path: &Path) -> Result<Option<Self>, String> {
+        let ext = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => ext,
+            None => return Ok(None),
+        };
+        match ext {
+            #[cfg(feature = "gzip")]
+            "gz" => Ok(Some(FileCompression::Gzip)),
+            #[cfg(feature = "zstd")]
+            "zst" => Ok(Some(FileCompression::Zstd)),
+            #[cfg(feature = "snappy")]
+            "sz" => Ok(Some(FileCompression::Snappy)),
+            _ => match EXTENSIONS.iter().find(|&&(known, _)| known == ext) {
+                Some((_, feature)) => Err(format!("reading {} requires the {:?} feature", path.display(), feature)),
+                None => Ok(None),
+            }
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd", feature = "snappy")), allow(unused_variables))]
+    pub(crate) fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        #[cfg(any(feature = "gzip", feature = "snappy"))]
+        use std::io::Write;
+
+        match self {
+            #[cfg(feature = "gzip")]
+            FileCompression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::with_capacity(data.len() / 4), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            FileCompression::Zstd => zstd::stream::encode_all(data, 0),
+            #[cfg(feature = "snappy")]
+            FileCompression::Snappy => {
+                let mut encoder = snap::write::FrameEncoder::new(Vec::with_capacity(data.len() / 4));
+                encoder.write_all(data)?;
+                encoder.into_inner().map_err(|e| io::Error::new(e.error().kind(), e.error().to_string()))
+            }
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd", feature = "snappy")), allow(unused_variables))]
+    fn decoder<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            FileCompression::Gzip => Ok(Box::new(flate2::read::GzDecoder::new(reader))),
+            #[cfg(feature = "zstd")]
+            FileCompression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(reader)?)),
+            #[cfg(feature = "snappy")]
+            FileCompression::Snappy => Ok(Box::new(snap::read::FrameDecoder::new(reader))),
+        }
+    }
+}
+
+/// Reads the file at `path`, decrypting it with `key` if it's encrypted,
+/// and decompressing it if its extension says it's compressed.
+///
+pub(crate) fn read_to_string(path: &Path, key: Option<&EncryptionKey>) -> io::Result<String> {
+    let compression = FileCompression::of_path(path)
+        .map_err(|msg| io::Error::new(io::ErrorKind::Unsupported, msg))?;
+    let data = encryption::read(path, key)?;
+    match compression {
+        Some(compression) => {
+            let mut text = String::new();
+            compression.decoder(&data[..])?.read_to_string(&mut text)?;
+            Ok(text)
+        }
+        None => String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
+
+/// `name` without the extension of a compressed file, if it has one.
+///
+pub(crate) fn strip_extension(name: &str) -> &str {
+    EXTENSIONS.iter()
+        .find_map(|&(ext, _)| name.strip_suffix(ext).and_then(|rest| rest.strip_suffix('.')))
+        .unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    #[test]
+    fn it_round_trips_compressed_files_by_extension() {
+        let dir = std::env::temp_dir().join(format!("influx-writer-compression-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let text = "trades,exch=plnx price=1.5,amount=2.0 1000\n".repeat(100);
+
+        let compressions: &[FileCompression] = &[
+            #[cfg(feature = "gzip")]
+            FileCompression::Gzip,
+            #[cfg(feature = "zstd")]
+            FileCompression::Zstd,
+            #[cfg(feature = "snappy")]
+            FileCompression::Snappy,
+        ];
+        for &compression in compressions {
+            let compressed = compression.compress(text.as_bytes()).unwrap();
+            assert!(compressed.len() < text.len() / 4, "{:?}: {} bytes", compression, compressed.len());
+            let path = dir.join(format!("batch.lp.{}", compression.extension()));
+            fs::write(&path, &compressed).unwrap();
+            assert_eq!(FileCompression::of_path(&path), Ok(Some(compression)));
+            assert_eq!(read_to_string(&path, None).unwrap(), text);
+        }
+
+        let plain = dir.join("batch.lp");
+        fs::write(&plain, &text).unwrap();
+        assert_eq!(FileCompression::of_path(&plain), Ok(None));
+        assert_eq!(read_to_string(&plain, None).unwrap(), text);
+
+        assert_eq!(strip_extension("file.lp.1000.zst"), "file.lp.1000");
+        assert_eq!(strip_extension("file.lp.1000.gz"), "file.lp.1000");
+        assert_eq!(strip_extension("file.lp.1000"), "file.lp.1000");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,165 @@
+//! A snapshot of how a writer is set up, see `InfluxWriter::config`.
+
+use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The effective configuration of a running writer: the settings it was
+/// built with, the optional features compiled in, and the settings that can
+/// be changed at runtime (see `InfluxWriterBuilder::admin`).
+///
+/// Changes made at runtime are logged, one record per changed setting,
+/// with its old and new values.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WriterConfig {
+    pub host: String,
+    pub db: String,
+    /// e.g. "http", "udp" or "file"
+    pub sink: &'static str,
+    pub https: bool,
+    pub gzip: bool,
+    pub retention_policy: Option<String>,
+    pub buffer_size: usize,
+    /// how long a partly filled batch may wait before it is sent
+    pub flush_interval: Duration,
+    pub flush_bytes: Option<usize>,
+    pub queue_capacity: usize,
+    pub high_water_mark: Option<usize>,
+    pub rate_limit: Option<f64>,
+    pub n_routes: usize,
+    /// (measurement key, database) of each route, sorted
+    pub routes: Vec<(String, String)>,
+    /// (measurement key, keep one in n), sorted
+    pub sampling: Vec<(String, u32)>,
+    pub spool: bool,
+    pub spool_replay_interval: Duration,
+    pub dead_letter: bool,
+    pub self_monitoring: Option<Duration>,
+    pub admin: Option<String>,
+    /// see `InfluxWriterBuilder::resolver`
+    pub re_resolve_interval: Option<Duration>,
+    /// see `InfluxWriterBuilder::watch_config`
+    pub config_file: Option<PathBuf>,
+    /// the crate's optional features that are enabled
+    pub features: Vec<&'static str>,
+    /// see `InfluxWriterBuilder::run_id_tag`
+    pub run_id: Option<Uuid>,
+    pub paused: bool,
+    pub log_level: String,
+    pub disabled_keys: Vec<String>,
+}
+
+impl WriterConfig {
+    /// Each setting's name and value, formatted for logging.
+    ///
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("host", self.host.clone()),
+            ("db", self.db.clone()),
+            ("sink", self.sink.to_string()),
+            ("https", self.https.to_string()),
+            ("gzip", self.gzip.to_string()),
+            ("retention_policy", format!("{:?}", self.retention_policy)),
+            ("buffer_size", self.buffer_size.to_string()),
+            ("flush_interval", format!("{:?}", self.flush_interval)),
+            ("flush_bytes", format!("{:?}", self.flush_bytes)),
+            ("queue_capacity", self.queue_capacity.to_string()),
+            ("high_water_mark", format!("{:?}", self.high_water_mark)),
+            ("rate_limit", format!("{:?}", self.rate_limit)),
+            ("n_routes", self.n_routes.to_string()),
+            ("routes", pairs(&self.routes)),
+            ("sampling", pairs(&self.sampling)),
+            ("spool", self.spool.to_string()),
+            ("spool_replay_interval", format!("{:?}", self.spool_replay_interval)),
+            ("dead_letter", self.dead_letter.to_string()),
+            ("self_monitoring", format!("{:?}", self.self_monitoring)),
+            ("admin", format!("{:?}", self.admin)),
+            ("re_resolve_interval", format!("{:?}", self.re_resolve_interval)),
+            ("config_file", format!("{:?}", self.config_file)),
+            ("features", self.features.join(",")),
+            ("run_id", self.run_id.map(|id| id.to_simple().to_string()).unwrap_or_default()),
+            ("paused", self.paused.to_string()),
+            ("log_level", self.log_level.clone()),
+            ("disabled_keys", self.disabled_keys.join(",")),
+        ]
+    }
+
+    /// The settings that differ between `self` and `new`, as (name, old
+    /// value, new value).
+    ///
+    pub fn diff(&self, new: &WriterConfig) -> Vec<(&'static str, String, String)> {
+        self.settings().into_iter()
+            .zip(new.settings())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((name, old), (_, new))| (name, old, new))
+            .collect()
+    }
+}
+
+/// "k1=v1,k2=v2"
+///
+fn pairs<V: ToString>(kvs: &[(String, V)]) -> String {
+    kvs.iter().map(|(k, v)| format!("{}={}", k, v.to_string())).collect::<Vec<_>>().join(",")
+}
+
+/// Each of the crate's cargo features (besides "default"), and whether
+/// it's enabled in this build.
+///
+const FEATURES: &[(&str, bool)] = &[
+    ("trace", cfg!(feature = "trace")),
+    ("debug", cfg!(feature = "debug")),
+    ("string-tags", cfg!(feature = "string-tags")),
+    ("tls", cfg!(feature = "tls")),
+    ("live-tail", cfg!(feature = "live-tail")),
+    ("gzip", cfg!(feature = "gzip")),
+    ("zstd", cfg!(feature = "zstd")),
+    ("snappy", cfg!(feature = "snappy")),
+    ("encryption", cfg!(feature = "encryption")),
+    ("grpc", cfg!(feature = "grpc")),
+    ("reqwest", cfg!(feature = "reqwest")),
+    ("tokio", cfg!(feature = "tokio")),
+    ("kafka", cfg!(feature = "kafka")),
+    ("mqtt", cfg!(feature = "mqtt")),
+    ("orders", cfg!(feature = "orders")),
+    ("serde", cfg!(feature = "serde")),
+    ("rollup", cfg!(feature = "rollup")),
+    ("unstable", cfg!(feature = "unstable")),
+    ("auth-tests", cfg!(feature = "auth-tests")),
+    ("docker-tests", cfg!(feature = "docker-tests")),
+];
+
+/// The crate's optional features enabled in this build.
+///
+pub(crate) fn enabled_features() -> Vec<&'static str> {
+    FEATURES.iter().filter(|(_, enabled)| *enabled).map(|(name, _)| *name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_lists_the_settings_that_changed() {
+        let old = WriterConfig { host: "localhost".to_string(), log_level: "trace".to_string(), ..Default::default() };
+        let new = WriterConfig { paused: true, log_level: "warning".to_string(), ..old.clone() };
+        assert_eq!(old.diff(&new), vec![
+            ("paused", "false".to_string(), "true".to_string()),
+            ("log_level", "trace".to_string(), "warning".to_string()),
+        ]);
+        assert!(new.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn it_knows_every_cargo_feature() {
+        let manifest = include_str!("../Cargo.toml");
+        let features = manifest.split("\n[features]\n").nth(1).unwrap().split("\n[").next().unwrap();
+        let declared: Vec<&str> = features.lines()
+            .filter(|line| !line.starts_with('#') && line.contains(" = "))
+            .map(|line| line.split(" = ").next().unwrap())
+            .filter(|&name| name != "default")
+            .collect();
+        let known: Vec<&str> = FEATURES.iter().map(|(name, _)| *name).collect();
+        assert_eq!(declared, known);
+    }
+}
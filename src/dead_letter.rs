@@ -0,0 +1,68 @@
+//! Destinations for batches the server rejected as malformed, enabled with
+//! `InfluxWriterBuilder::dead_letter_file` or
+//! `InfluxWriterBuilder::dead_letter_channel`.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crossbeam_channel::{Sender, TrySendError};
+use slog::Logger;
+use crate::encryption::EncryptionKey;
+
+/// A batch rejected by the server with status 400 (e.g. malformed line
+/// protocol), which is not retried.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedBatch {
+    /// the batch, as sent
+    pub body: String,
+    /// http status of the server's response
+    pub status: u16,
+    /// body of the server's response
+    pub response: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum DeadLetter {
+    /// appends each rejected batch to the file, preceded by a comment line
+    /// with the time, status and server response, encrypted if there's a
+    /// key
+    File(PathBuf, Option<EncryptionKey>),
+    /// sends each rejected batch on the channel, dropping it if the
+    /// channel is full
+    Channel(Sender<RejectedBatch>),
+}
+
+impl DeadLetter {
+    pub fn write(&self, batch: RejectedBatch, logger: &Logger) {
+        match self {
+            DeadLetter::File(path, key) => {
+                if let Err(e) = Self::append(path, key.as_ref(), &batch) {
+                    error!(logger, "InfluxWriter: failed to write rejected batch to dead letter file: {}", e;
+                        "path" => %path.display());
+                }
+            }
+
+            DeadLetter::Channel(tx) => {
+                match tx.try_send(batch) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        warn!(logger, "InfluxWriter: dead letter channel full, discarding rejected batch");
+                    }
+                    Err(TrySendError::Disconnected(_)) => {
+                        warn!(logger, "InfluxWriter: dead letter channel disconnected, discarding rejected batch");
+                    }
+                }
+            }
+        }
+    }
+
+    fn append(path: &Path, key: Option<&EncryptionKey>, batch: &RejectedBatch) -> io::Result<()> {
+        let response = batch.response.trim().replace('\n', " ");
+        let text = format!("# {} status={} response={}\n{}\n", chrono::Utc::now().to_rfc3339(), batch.status, response, batch.body);
+        match key {
+            Some(key) => key.append(path, text.as_bytes()),
+            None => OpenOptions::new().create(true).append(true).open(path)?.write_all(text.as_bytes()),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Encryption of the files the writer leaves on disk: spooled batches and
+//! the dead letter file, enabled with `InfluxWriterBuilder::encryption_key`.
+//! `Replay` decrypts them given the same key (see `Replay::key`).
+//!
+//! An encrypted file starts with `MAGIC`, followed by one or more frames
+//! (one per spooled batch, or per batch appended to a dead letter file),
+//! each a big-endian `u32` length, a random 24-byte nonce, and that many
+//! bytes of XChaCha20-Poly1305 ciphertext. Encrypted files are recognized
+//! by `MAGIC` rather than their names, so they keep the names they would
+//! have otherwise (including a compression's extension: batches are
+//! compressed before they're encrypted).
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+#[cfg(feature = "encryption")]
+use crate::Error;
+
+pub(crate) const MAGIC: &[u8] = b"IWENC\x01";
+
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 24;
+
+/// A 256-bit key for encrypting spooled batches and dead letter files. The
+/// constructors require the "encryption" feature.
+///
+/// Keep the key somewhere other than the disk it protects, e.g. in an
+/// environment variable set by a secrets manager, or a file on a tmpfs.
+///
+#[derive(Clone)]
+pub struct EncryptionKey(Key);
+
+#[derive(Clone)]
+enum Key {
+    #[cfg(feature = "encryption")]
+    XChaCha20Poly1305([u8; 32]),
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        EncryptionKey(Key::XChaCha20Poly1305(key))
+    }
+
+    /// Reads the key from environment variable `var`, as 64 hex digits.
+    ///
+    pub fn from_env(var: &str) -> Result<Self, Error> {
+        let hex = std::env::var(var)
+            .map_err(|e| Error::Config(format!("failed to read encryption key from ${}: {}", var, e)))?;
+        Self::from_hex(hex.trim())
+            .ok_or_else(|| Error::Config(format!("encryption key in ${} is not 64 hex digits", var)))
+    }
+
+    /// Reads the key from the file at `path`, which holds either the 32
+    /// bytes of the key, or 64 hex digits (optionally followed by a
+    /// newline).
+    ///
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)
+            .map_err(|e| Error::Config(format!("failed to read encryption key from {}: {}", path.display(), e)))?;
+        if let Ok(key) = <[u8; 32] as std::convert::TryFrom<_>>::try_from(&bytes[..]) {
+            return Ok(Self::new(key))
+        }
+        std::str::from_utf8(&bytes).ok()
+            .and_then(|hex| Self::from_hex(hex.trim()))
+            .ok_or_else(|| Error::Config(format!("encryption key in {} is neither 32 bytes nor 64 hex digits", path.display())))
+    }
+
+    fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 64 || !hex.is_ascii() { return None }
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(Self::new(key))
+    }
+}
+
+impl EncryptionKey {
+    /// Encrypts `plaintext` into a frame.
+    ///
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    fn seal(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        match self.0 {
+            #[cfg(feature = "encryption")]
+            Key::XChaCha20Poly1305(ref key) => {
+                use chacha20poly1305::{XChaCha20Poly1305, aead::{Aead, AeadCore, KeyInit, OsRng}};
+
+                let cipher = XChaCha20Poly1305::new(key.into());
+                let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+                let ciphertext = cipher.encrypt(&nonce, plaintext)
+                    .map_err(|_| io::Error::other("encryption failed"))?;
+                let mut frame = Vec::with_capacity(4 + NONCE_LEN + ciphertext.len());
+                frame.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+                frame.extend_from_slice(&nonce);
+                frame.extend_from_slice(&ciphertext);
+                Ok(frame)
+            }
+        }
+    }
+
+    /// Decrypts the frames of `data` (an encrypted file, after `MAGIC`).
+    ///
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    fn open(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self.0 {
+            #[cfg(feature = "encryption")]
+            Key::XChaCha20Poly1305(ref key) => {
+                use chacha20poly1305::{XChaCha20Poly1305, XNonce, aead::{Aead, KeyInit}};
+
+                let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_string());
+                let cipher = XChaCha20Poly1305::new(key.into());
+                let mut plaintext = Vec::with_capacity(data.len());
+                let mut rest = data;
+                while !rest.is_empty() {
+                    if rest.len() < 4 + NONCE_LEN { return Err(invalid("truncated encrypted frame")) }
+                    let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+                    let (nonce, ciphertext) = rest[4..].split_at(NONCE_LEN);
+                    if ciphertext.len() < len { return Err(invalid("truncated encrypted frame")) }
+                    let decrypted = cipher.decrypt(XNonce::from_slice(nonce), &ciphertext[..len])
+                        .map_err(|_| invalid("decryption failed (wrong key, or the file is corrupt)"))?;
+                    plaintext.extend_from_slice(&decrypted);
+                    rest = &ciphertext[len..];
+                }
+                Ok(plaintext)
+            }
+        }
+    }
+
+    /// The contents of an encrypted file holding `plaintext`.
+    ///
+    pub(crate) fn encrypt(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&self.seal(plaintext)?);
+        Ok(data)
+    }
+
+    /// Appends `plaintext` to the encrypted file at `path`, creating it if
+    /// it doesn't exist. Fails if the file exists but isn't encrypted.
+    ///
+    pub(crate) fn append(&self, path: &Path, plaintext: &[u8]) -> io::Result<()> {
+        let frame = self.seal(plaintext)?;
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        let mut magic = Vec::with_capacity(MAGIC.len());
+        (&mut file).take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+        if magic.is_empty() {
+            file.write_all(&[MAGIC, &frame[..]].concat())
+        } else if magic == MAGIC {
+            file.write_all(&frame)
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("{} exists but isn't encrypted (move it aside to start an encrypted one)", path.display())))
+        }
+    }
+}
+
+/// Reads the file at `path`, decrypting it with `key` if it's encrypted.
+///
+pub(crate) fn read(path: &Path, key: Option<&EncryptionKey>) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    match data.strip_prefix(MAGIC) {
+        None => Ok(data),
+        Some(frames) => match key {
+            Some(key) => key.open(frames),
+            #[cfg(feature = "encryption")]
+            None => Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("{} is encrypted, but no encryption key is configured", path.display()))),
+            #[cfg(not(feature = "encryption"))]
+            None => Err(io::Error::new(io::ErrorKind::Unsupported,
+                format!("reading {} (which is encrypted) requires the \"encryption\" feature", path.display()))),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encrypts_files_and_appends_frames() {
+        let dir = std::env::temp_dir().join(format!("influx-writer-encryption-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let key = EncryptionKey::from_hex(&"2a".repeat(32)).unwrap();
+
+        let path = dir.join("batch.lp");
+        fs::write(&path, key.encrypt(b"a n=1i 1").unwrap()).unwrap();
+        assert!(!fs::read(&path).unwrap().windows(8).any(|w| w == b"a n=1i 1"));
+        assert_eq!(read(&path, Some(&key)).unwrap(), b"a n=1i 1");
+        assert!(read(&path, None).is_err());
+        let other = EncryptionKey::new([1; 32]);
+        assert_eq!(read(&path, Some(&other)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let appended = dir.join("rejected.lp");
+        key.append(&appended, b"a n=1i 1\n").unwrap();
+        key.append(&appended, b"b n=2i 2\n").unwrap();
+        assert_eq!(read(&appended, Some(&key)).unwrap(), b"a n=1i 1\nb n=2i 2\n");
+        let plain = dir.join("plain.lp");
+        fs::write(&plain, "a n=1i 1\n").unwrap();
+        assert!(key.append(&plain, b"b n=2i 2\n").is_err());
+        assert_eq!(read(&plain, None).unwrap(), b"a n=1i 1\n");
+
+        let key_file = dir.join("key");
+        fs::write(&key_file, format!("{}\n", "2a".repeat(32))).unwrap();
+        assert_eq!(read(&path, Some(&EncryptionKey::from_file(&key_file).unwrap())).unwrap(), b"a n=1i 1");
+        fs::write(&key_file, [0x2a; 32]).unwrap();
+        assert_eq!(read(&path, Some(&EncryptionKey::from_file(&key_file).unwrap())).unwrap(), b"a n=1i 1");
+        fs::write(&key_file, "not a key").unwrap();
+        assert!(EncryptionKey::from_file(&key_file).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,103 @@
+//! Last-resort output of measurements that would otherwise be lost, see
+//! `InfluxWriterBuilder::stderr_fallback`.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use crate::{serialize_owned_with, OwnedMeasurement, SerializeOptions, TokenBucket};
+
+/// Writes measurements (as line protocol) and warnings to stderr, one per
+/// line, prefixed with "influx-writer", and at most `per_sec` lines a
+/// second. Lines over the limit are counted and reported with the next line
+/// allowed.
+///
+pub(crate) struct StderrFallback {
+    opts: SerializeOptions,
+    limit: Mutex<TokenBucket>,
+    n_suppressed: AtomicUsize,
+}
+
+impl StderrFallback {
+    pub fn new(per_sec: f64, opts: SerializeOptions) -> Self {
+        StderrFallback {
+            opts,
+            limit: Mutex::new(TokenBucket::new(per_sec, Instant::now())),
+            n_suppressed: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn measurement(&self, m: &OwnedMeasurement) {
+        let mut line = String::new();
+        serialize_owned_with(m, &mut line, &self.opts);
+        self.write("measurement", &line);
+    }
+
+    /// Writes each line of a serialized batch.
+    ///
+    pub fn batch(&self, batch: &str) {
+        for line in batch.lines().filter(|line| !line.is_empty()) {
+            self.write("measurement", line);
+        }
+    }
+
+    pub fn warning(&self, message: &str) {
+        self.write("warning", message);
+    }
+
+    fn write(&self, kind: &str, text: &str) {
+        if let Some(output) = self.format(kind, text, Instant::now()) {
+            let _ = io::stderr().lock().write_all(output.as_bytes());
+        }
+    }
+
+    /// The output for one line, or `None` if it's over the rate limit.
+    ///
+    fn format(&self, kind: &str, text: &str, now: Instant) -> Option<String> {
+        if !self.limit.lock().unwrap().take(now) {
+            self.n_suppressed.fetch_add(1, Ordering::Relaxed);
+            return None
+        }
+        let mut output = String::new();
+        let n_suppressed = self.n_suppressed.swap(0, Ordering::Relaxed);
+        if n_suppressed > 0 {
+            output.push_str(&format!("influx-writer warning: {} lines suppressed by rate limit\n", n_suppressed));
+        }
+        // keep it to one line, whatever is in `text`
+        let text = text.trim_end().replace('\n', "\\n");
+        output.push_str(&format!("influx-writer {}: {}\n", kind, text));
+        Some(output)
+    }
+}
+
+impl fmt::Debug for StderrFallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StderrFallback")
+            .field("n_suppressed", &self.n_suppressed)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::OwnedValue;
+    use super::*;
+
+    #[test]
+    fn it_writes_one_line_per_measurement_within_the_rate_limit() {
+        let fallback = StderrFallback::new(2.0, SerializeOptions::default());
+        let start = Instant::now();
+        let mut line = String::new();
+        serialize_owned_with(&OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1)), &mut line, &fallback.opts);
+        assert_eq!(fallback.format("measurement", &line, start), Some("influx-writer measurement: test n=1i\n".to_string()));
+        assert_eq!(fallback.format("warning", "a\nb", start), Some("influx-writer warning: a\\nb\n".to_string()));
+        assert_eq!(fallback.format("warning", "c", start), None);
+        assert_eq!(fallback.format("warning", "d", start), None);
+        assert_eq!(
+            fallback.format("warning", "e", start + Duration::from_secs(1)),
+            Some("influx-writer warning: 2 lines suppressed by rate limit\ninflux-writer warning: e\n".to_string()),
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! A grpc endpoint feeding an `InfluxWriter` (requires the "grpc" feature),
+//! so processes in other languages can send measurements through this
+//! crate's batching, retries, spool and so on, e.g. to a sidecar running
+//! the `influx-grpc` binary.
+//!
+//! The service is `influx_writer.Writer`, defined in
+//! `proto/influx_writer.proto` (for generating clients). The messages and
+//! server here are written by hand rather than generated, so building the
+//! crate doesn't require `protoc`; they must be kept in sync with the
+//! .proto file.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use tonic::codegen::{http, empty_body, Body, BoxFuture, Context, Poll, Service, StdError};
+use tonic::server::{ClientStreamingService, Grpc, NamedService};
+use tonic::{Code, Request, Response, Status, Streaming};
+use crate::{Error, InfluxWriter, OwnedMeasurement, OwnedValue};
+
+const WRITE_PATH: &str = "/influx_writer.Writer/Write";
+
+/// A measurement, as sent to `Write`.
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Measurement {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    /// nanoseconds since the unix epoch. If not set, the writer's clock
+    /// at the time it's written is used
+    #[prost(int64, optional, tag = "2")]
+    pub timestamp: Option<i64>,
+    #[prost(btree_map = "string, string", tag = "3")]
+    pub tags: BTreeMap<String, String>,
+    /// at least one is required
+    #[prost(btree_map = "string, message", tag = "4")]
+    pub fields: BTreeMap<String, Value>,
+}
+
+/// A field value.
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Value {
+    #[prost(oneof = "value::Kind", tags = "1, 2, 3, 4")]
+    pub kind: Option<value::Kind>,
+}
+
+pub mod value {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Kind {
+        #[prost(double, tag = "1")]
+        Float(f64),
+        #[prost(int64, tag = "2")]
+        Integer(i64),
+        #[prost(string, tag = "3")]
+        String(String),
+        #[prost(bool, tag = "4")]
+        Boolean(bool),
+    }
+}
+
+/// The response to `Write`.
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WriteResponse {
+    /// number of measurements passed to the writer
+    #[prost(uint64, tag = "1")]
+    pub n_measurements: u64,
+}
+
+impl Measurement {
+    fn into_owned(self) -> Result<OwnedMeasurement, String> {
+        if self.key.is_empty() {
+            return Err("measurement with an empty key".to_string())
+        }
+        if self.fields.is_empty() {
+            return Err(format!("measurement {:?} has no fields", self.key))
+        }
+        let mut m = OwnedMeasurement::with_capacity(self.key, self.tags.len(), self.fields.len());
+        m.timestamp = self.timestamp;
+        for (key, value) in self.tags {
+            m = m.add_tag_string(key, value);
+        }
+        for (key, value) in self.fields {
+            let value = match value.kind {
+                Some(value::Kind::Float(x)) => OwnedValue::Float(x),
+                Some(value::Kind::Integer(x)) => OwnedValue::Integer(x),
+                Some(value::Kind::String(x)) => OwnedValue::String(x),
+                Some(value::Kind::Boolean(x)) => OwnedValue::Boolean(x),
+                None => return Err(format!("field {:?} of measurement {:?} has no value", key, m.key)),
+            };
+            m = m.add_field(key, value);
+        }
+        Ok(m)
+    }
+}
+
+/// The `influx_writer.Writer` grpc service, for a tonic server.
+///
+/// Its one method, `Write(stream Measurement) returns (WriteResponse)`,
+/// passes each measurement to the writer with `try_send`, so a full queue
+/// fails the call (with `RESOURCE_EXHAUSTED`) rather than blocking the
+/// server. A call that fails part way through (including on an invalid
+/// measurement) has passed the measurements before the one that failed
+/// to the writer, and its status message says how many.
+///
+/// # Examples
+///
+/// ```no_run
+/// use influx_writer::{GrpcService, InfluxWriter};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let influx = InfluxWriter::builder("localhost", "test").try_build().unwrap();
+///     tonic::transport::Server::builder()
+///         .add_service(GrpcService::new(influx))
+///         .serve("127.0.0.1:50051".parse().unwrap())
+///         .await
+///         .unwrap();
+/// }
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct GrpcService {
+    writer: InfluxWriter,
+}
+
+impl GrpcService {
+    pub fn new(writer: InfluxWriter) -> Self {
+        GrpcService { writer }
+    }
+
+    /// Serves the service (alone) on `addr` until `shutdown` completes.
+    /// Must be called from within a tokio runtime.
+    ///
+    pub async fn serve<F>(self, addr: SocketAddr, shutdown: F) -> Result<(), Error>
+        where F: std::future::Future<Output = ()>
+    {
+        tonic::transport::Server::builder()
+            .add_service(self)
+            .serve_with_shutdown(addr, shutdown)
+            .await
+            .map_err(|e| Error::Io(io::Error::other(format!("grpc server on {}: {}", addr, e))))
+    }
+
+    async fn write(writer: InfluxWriter, request: Request<Streaming<Measurement>>) -> Result<Response<WriteResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut n_measurements = 0;
+        let failed = |status: Status, n: u64| {
+            Status::new(status.code(), format!("{} ({} measurements written before the failure)", status.message(), n))
+        };
+        while let Some(m) = stream.message().await.map_err(|e| failed(e, n_measurements))? {
+            let m = m.into_owned().map_err(|e| failed(Status::invalid_argument(e), n_measurements))?;
+            match writer.try_send(m) {
+                Ok(()) => n_measurements += 1,
+                Err(Error::QueueFull(_)) => {
+                    return Err(failed(Status::resource_exhausted("writer queue is full"), n_measurements))
+                }
+                Err(e) => return Err(failed(Status::unavailable(e.to_string()), n_measurements)),
+            }
+        }
+        Ok(Response::new(WriteResponse { n_measurements }))
+    }
+}
+
+struct WriteSvc(InfluxWriter);
+
+impl ClientStreamingService<Measurement> for WriteSvc {
+    type Response = WriteResponse;
+    type Future = BoxFuture<Response<WriteResponse>, Status>;
+
+    fn call(&mut self, request: Request<Streaming<Measurement>>) -> Self::Future {
+        Box::pin(GrpcService::write(self.0.clone(), request))
+    }
+}
+
+impl<B> Service<http::Request<B>> for GrpcService
+    where B: Body + Send + 'static,
+          B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if req.uri().path() != WRITE_PATH {
+            return Box::pin(async move {
+                let mut resp = http::Response::new(empty_body());
+                resp.headers_mut().insert("grpc-status", (Code::Unimplemented as i32).into());
+                resp.headers_mut().insert(http::header::CONTENT_TYPE, http::HeaderValue::from_static("application/grpc"));
+                Ok(resp)
+            })
+        }
+        let writer = self.writer.clone();
+        Box::pin(async move {
+            let mut grpc = Grpc::new(tonic::codec::ProstCodec::default());
+            Ok(grpc.client_streaming(WriteSvc(writer), req).await)
+        })
+    }
+}
+
+impl NamedService for GrpcService {
+    const NAME: &'static str = "influx_writer.Writer";
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use tonic::codegen::tokio_stream;
+    use crate::MemorySink;
+    use super::*;
+
+    fn measurement(key: &str, fields: Vec<(&str, value::Kind)>) -> Measurement {
+        Measurement {
+            key: key.to_string(),
+            timestamp: None,
+            tags: vec![("exch".to_string(), "plnx".to_string())].into_iter().collect(),
+            fields: fields.into_iter().map(|(k, v)| (k.to_string(), Value { kind: Some(v) })).collect(),
+        }
+    }
+
+    #[test]
+    fn it_writes_measurements_streamed_over_grpc() {
+        let sink = MemorySink::new(Duration::from_secs(60));
+        let influx = InfluxWriter::builder("localhost", "test").sink(sink.clone()).try_build().unwrap();
+        let service = GrpcService::new(influx.clone());
+        let now = crate::now();
+        let mut first = measurement("trades", vec![("price", value::Kind::Float(1.5)), ("n", value::Kind::Integer(2))]);
+        first.timestamp = Some(now);
+
+        let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+        let results = rt.block_on(async move {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+            tokio::spawn(tonic::transport::Server::builder().add_service(service).serve_with_incoming(incoming));
+
+            let channel = tonic::transport::Channel::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap();
+            let mut results = Vec::new();
+            let requests = vec![
+                vec![
+                    first,
+                    measurement("trades", vec![("side", value::Kind::String("bid".to_string())), ("maker", value::Kind::Boolean(true))]),
+                ],
+                vec![measurement("trades", vec![("n", value::Kind::Integer(3))]), measurement("trades", vec![])],
+            ];
+            for request in requests {
+                let mut client = tonic::client::Grpc::new(channel.clone());
+                client.ready().await.unwrap();
+                let result = client.client_streaming::<_, Measurement, WriteResponse, _>(
+                    Request::new(tokio_stream::iter(request)),
+                    http::uri::PathAndQuery::from_static(WRITE_PATH),
+                    tonic::codec::ProstCodec::default(),
+                ).await;
+                results.push(result.map(|resp| resp.into_inner().n_measurements));
+            }
+            results
+        });
+        assert_eq!(results[0].as_ref().unwrap(), &2);
+        let err = results[1].as_ref().unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        assert!(err.message().ends_with("(1 measurements written before the failure)"), "{}", err.message());
+
+        drop(rt);
+        influx.close(Duration::from_secs(5)).unwrap();
+        let points = sink.filter(|p| p.key == "trades");
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0].tag("exch"), Some("plnx"));
+        assert_eq!(points[0].field("price"), Some("1.5"));
+        assert_eq!(points[0].field("n"), Some("2i"));
+        assert_eq!(points[0].timestamp, now);
+        assert!(points[1].timestamp >= now);
+        assert_eq!(points[1].field("side"), Some("\"bid\""));
+        assert_eq!(points[1].field("maker"), Some("t"));
+        assert_eq!(points[2].field("n"), Some("3i"));
+    }
+}
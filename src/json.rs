@@ -0,0 +1,127 @@
+//! JSON serialization of measurements, for webhooks and message queues that
+//! don't speak line protocol.
+//!
+//! The format is plain JSON for consumers that don't know this crate, so
+//! value types aren't preserved (e.g. a `D128` is written as a string). It
+//! is not the format of `OwnedMeasurement`'s `Serialize` impl (with the
+//! "serde" feature), which tags each value with its type so measurements
+//! can be deserialized exactly.
+
+use std::fmt::Write;
+use chrono::SecondsFormat;
+use crate::{OwnedMeasurement, OwnedValue};
+
+/// Serializes `m` as a JSON object:
+///
+/// ```text
+/// {"measurement": <key>, "tags": {<key>: <string>, ..}, "fields": {<key>: <value>, ..}, "timestamp": <ns> | null}
+/// ```
+///
+/// Field values are JSON numbers (`Integer`, `Float`), booleans and strings
+/// (`String`, and `D128`, `Uuid` and `DateTime`, formatted as strings so
+/// decimals keep their precision, `DateTime` as RFC3339). Non-finite
+/// floats, which JSON can't represent, are `null`. The timestamp is in
+/// nanoseconds since the unix epoch, or `null` if the measurement doesn't
+/// have one.
+///
+/// # Examples
+///
+/// ```
+/// use influx_writer::{to_json, OwnedMeasurement, OwnedValue};
+///
+/// let m = OwnedMeasurement::new("test").add_tag("color", "red").add_field("n", OwnedValue::Integer(1));
+/// assert_eq!(to_json(&m), r#"{"measurement":"test","tags":{"color":"red"},"fields":{"n":1},"timestamp":null}"#);
+/// ```
+///
+pub fn to_json(m: &OwnedMeasurement) -> String {
+    let mut out = String::new();
+    write_json(m, &mut out);
+    out
+}
+
+/// Serializes `ms` as a JSON array of objects in the format of `to_json`.
+///
+pub fn to_json_batch(ms: &[OwnedMeasurement]) -> String {
+    let mut out = String::from("[");
+    for (i, m) in ms.iter().enumerate() {
+        if i > 0 { out.push(',') }
+        write_json(m, &mut out);
+    }
+    out.push(']');
+    out
+}
+
+fn write_json(m: &OwnedMeasurement, out: &mut String) {
+    out.push_str("{\"measurement\":");
+    write_str(&m.key, out);
+    out.push_str(",\"tags\":{");
+    for (i, (k, v)) in m.tags.iter().enumerate() {
+        if i > 0 { out.push(',') }
+        write_str(k, out);
+        out.push(':');
+        write_str(v, out);
+    }
+    out.push_str("},\"fields\":{");
+    for (i, (k, v)) in m.fields.iter().enumerate() {
+        if i > 0 { out.push(',') }
+        write_str(k, out);
+        out.push(':');
+        match v {
+            OwnedValue::String(s) => write_str(s, out),
+            OwnedValue::Float(x) if x.is_finite() => { let _ = write!(out, "{}", x); }
+            OwnedValue::Float(_) => out.push_str("null"),
+            OwnedValue::Integer(x) => { let _ = write!(out, "{}", x); }
+            OwnedValue::Boolean(x) => { let _ = write!(out, "{}", x); }
+            OwnedValue::D128(x) => write_str(&x.to_string(), out),
+            OwnedValue::Uuid(x) => write_str(&x.to_string(), out),
+            OwnedValue::DateTime(x) => write_str(&x.to_rfc3339_opts(SecondsFormat::AutoSi, true), out),
+        }
+    }
+    out.push_str("},\"timestamp\":");
+    match m.timestamp {
+        Some(t) => { let _ = write!(out, "{}", t); }
+        None => out.push_str("null"),
+    }
+    out.push('}');
+}
+
+/// Writes `s` as a quoted, escaped JSON string.
+///
+fn write_str(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_serializes_a_batch_as_valid_json() {
+        let a = OwnedMeasurement::new("a")
+            .add_tag("t", "x\"y")
+            .add_field("s", OwnedValue::String("line\nbreak\u{1}".to_string()))
+            .add_field("f", OwnedValue::Float(1.5))
+            .add_field("nan", OwnedValue::Float(f64::NAN))
+            .add_field("b", OwnedValue::Boolean(false))
+            .set_timestamp(7);
+        let b = OwnedMeasurement::new("b").add_field("i", OwnedValue::Integer(-3));
+        let json: serde_json::Value = serde_json::from_str(&to_json_batch(&[a, b])).unwrap();
+        assert_eq!(json, serde_json::json!([
+            {"measurement": "a", "tags": {"t": "x\"y"}, "fields": {"s": "line\nbreak\u{1}", "f": 1.5, "nan": null, "b": false}, "timestamp": 7},
+            {"measurement": "b", "tags": {}, "fields": {"i": -3}, "timestamp": null},
+        ]));
+        assert_eq!(to_json_batch(&[]), "[]");
+    }
+}
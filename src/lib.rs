@@ -7,18 +7,35 @@
 extern crate test;
 #[macro_use]
 extern crate slog;
-
-use std::io::Read;
-use std::sync::Arc;
+#[macro_use]
+extern crate lazy_static;
+
+use std::io::{self, Read, Write, BufRead, BufReader};
+use std::fs;
+use std::fmt;
+use std::cell::RefCell;
+use fallback::StderrFallback;
+use pool::MeasurementPool;
+use audit::EnqueueAudit;
+pub use config::WriterConfig;
+pub use json::{to_json, to_json_batch};
+pub use market::{book_snapshot, MarketTags, Side, MAX_BOOK_DEPTH};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::{thread, mem};
 use std::time::*;
 use std::collections::VecDeque;
 use std::convert::TryInto;
-use crossbeam_channel::{Sender, Receiver, bounded, SendError};
-use hyper::status::StatusCode;
+use std::path::{Path, PathBuf};
+use crossbeam_channel::{Sender, Receiver, bounded, SendError, TrySendError};
 use hyper::client::response::Response;
 use hyper::Url;
 use hyper::client::Client;
+use hyper::client::pool::{Pool, Config as PoolConfig};
 use slog::Drain;
 use chrono::prelude::*;
 use decimal::d128;
@@ -27,6 +44,63 @@ use smallvec::SmallVec;
 use slog::Logger;
 use pretty_toa::ThousandsSep;
 
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "live-tail")]
+mod live_tail;
+mod admin;
+mod watch;
+mod resolve;
+mod host;
+mod spool;
+mod compression;
+mod encryption;
+mod dead_letter;
+mod span;
+mod throttle;
+mod build_info;
+mod fallback;
+mod pool;
+mod audit;
+mod config;
+mod market;
+mod json;
+#[cfg(feature = "orders")]
+pub mod orders;
+#[cfg(feature = "serde")]
+mod serde_support;
+mod replay;
+#[cfg(feature = "rollup")]
+mod rollup;
+#[cfg(feature = "tokio")]
+mod async_writer;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod sink;
+
+pub use resolve::{Resolved, Resolver, SystemResolver};
+pub use sink::{Sink, HttpSink, UdpSink, StatsdSink, StatsdType, GraphiteSink, PrometheusSink, FileSink, TeeSink, LoadBalancedSink, Balance, RoutingSink, MemorySink, MemoryPoint, ChunkedSink};
+#[cfg(feature = "reqwest")]
+pub use sink::ReqwestSink;
+#[cfg(feature = "kafka")]
+pub use sink::KafkaSink;
+#[cfg(feature = "mqtt")]
+pub use sink::{MqttSink, MqttTopic};
+pub use compression::FileCompression;
+pub use encryption::EncryptionKey;
+pub use dead_letter::RejectedBatch;
+pub use span::Span;
+pub use throttle::Throttle;
+pub use build_info::BuildInfo;
+pub use replay::{Replay, ReplayStats};
+#[cfg(feature = "rollup")]
+pub use rollup::{Aggregate, Rollup, RollupStats};
+#[cfg(feature = "tokio")]
+pub use async_writer::AsyncInfluxWriter;
+#[cfg(feature = "grpc")]
+pub use grpc::GrpcService;
+use dead_letter::DeadLetter;
+
 /// whether non-finite `f64` and `d128` values should be skipped
 /// during serialization to influxdb line format. influx does not
 /// handle `NaN` values at all. the other option is a marker value,
@@ -35,8 +109,145 @@ pub const SKIP_NAN_VALUES: bool = true;
 
 pub const DROP_DEADLINE: Duration = Duration::from_secs(30);
 
+/// default number of measurements batched into each request (see
+/// `InfluxWriterBuilder::buffer_size`).
+pub const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+/// default for how long a partly filled batch may wait before it is sent
+/// (see `InfluxWriterBuilder::flush_interval`).
+const MAX_PENDING: Duration = Duration::from_secs(3);
+
+/// max idle keep-alive connections kept open to the server (see
+/// `pooled_client`).
+const HTTP_POOL_SIZE: usize = 64;
+
+/// idle keep-alive connections are closed after this long.
+const HTTP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// default time the server's addresses are cached for, when the resolver
+/// doesn't report a TTL (see `InfluxWriterBuilder::resolver`).
+pub const DEFAULT_RE_RESOLVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// default number of measurements that can be queued for the writer
+/// thread (see `InfluxWriterBuilder::queue_capacity`).
+pub const DEFAULT_QUEUE_CAPACITY: usize = 4096;
+
+/// upper bound on the number of measurements batched into each request,
+/// regardless of the configured buffer size.
+pub const INFLUX_WRITER_MAX_BUFFER: usize = 4096;
+
+/// upper bound on the serialized size of a single measurement that will be
+/// appended to a batch. a measurement that serializes to more than this is
+/// instead sent to the server in a request of its own, so one huge line can't
+/// blow up the size of the batch (or take the rest of the batch down with it
+/// if the server rejects it).
+pub const MAX_BATCH_BYTES: usize = 1024 * 1024 * 4;
+
 pub type Credentials = hyper::header::Authorization<hyper::header::Basic>;
 
+/// Errors returned by this crate.
+///
+#[derive(Debug)]
+pub enum Error {
+    /// invalid configuration, e.g. a host that doesn't make a valid url
+    Config(String),
+
+    /// a request to the server failed. `status` is the http status code
+    /// of the server's response, if one was received.
+    Transport {
+        status: Option<u16>,
+        message: String,
+    },
+
+    /// a measurement or batch could not be serialized or deserialized
+    Serialization(String),
+
+    /// the measurement could not be queued for the writer thread, which is
+    /// no longer receiving. the measurement is returned.
+    Queue(Box<OwnedMeasurement>),
+
+    /// the measurement could not be queued by `InfluxWriter::try_send`
+    /// because the queue is full (the writer thread is falling behind).
+    /// the measurement is returned.
+    QueueFull(Box<OwnedMeasurement>),
+
+    /// the writer thread did not shut down cleanly
+    Shutdown(String),
+
+    /// the writer thread could not be started
+    Spawn(io::Error),
+
+    /// an i/o operation other than an http request failed, e.g. a file
+    /// could not be read by `Replay`, or a udp socket could not be bound
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(msg) => write!(f, "invalid configuration: {}", msg),
+            Error::Transport { status: Some(status), message } => write!(f, "request failed with status {}: {}", status, message),
+            Error::Transport { status: None, message } => write!(f, "request failed: {}", message),
+            Error::Serialization(msg) => write!(f, "serialization failed: {}", msg),
+            Error::Queue(m) => write!(f, "failed to queue measurement \"{}\": writer thread is not receiving", m.key),
+            Error::QueueFull(m) => write!(f, "failed to queue measurement \"{}\": queue is full", m.key),
+            Error::Shutdown(msg) => write!(f, "shutdown failed: {}", msg),
+            Error::Spawn(e) => write!(f, "failed to spawn writer thread: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<hyper::Error> for Error {
+    fn from(e: hyper::Error) -> Self {
+        Error::Transport { status: None, message: e.to_string() }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<hyper::error::ParseError> for Error {
+    fn from(e: hyper::error::ParseError) -> Self {
+        Error::Config(format!("invalid url: {}", e))
+    }
+}
+
+impl From<SendError<Option<OwnedMeasurement>>> for Error {
+    fn from(SendError(m): SendError<Option<OwnedMeasurement>>) -> Self {
+        match m {
+            Some(m) => Error::Queue(Box::new(m)),
+            None => Error::Shutdown("writer thread is not receiving".to_string()),
+        }
+    }
+}
+
+impl From<TrySendError<Option<OwnedMeasurement>>> for Error {
+    fn from(e: TrySendError<Option<OwnedMeasurement>>) -> Self {
+        match e {
+            TrySendError::Full(Some(m)) => Error::QueueFull(Box::new(m)),
+            TrySendError::Full(None) => Error::Shutdown("writer thread queue is full".to_string()),
+            TrySendError::Disconnected(m) => Error::from(SendError(m)),
+        }
+    }
+}
+
+impl Error {
+    /// Builds a `Transport` error from a server response with a non-success
+    /// status, reading the response body into the message.
+    ///
+    fn from_response(resp: &mut Response) -> Self {
+        let mut body = String::new();
+        let _ = resp.read_to_string(&mut body);
+        Error::Transport { status: Some(resp.status.to_u16()), message: body }
+    }
+}
+
 /// Created this so I know what types can be passed through the
 /// `measure!` macro, which used to convert with `as i64` and
 /// `as f64` until I accidentally passed a function name, and it
@@ -133,6 +344,70 @@ impl AsF64 for f32 { fn as_f64(x: Self) -> f64 { x as f64 } }
 /// }
 /// ```
 ///
+/// `@name(..)` in place of the name takes the measurement name from an
+/// expression evaluated at runtime, anything that converts `Into<Key>`
+/// (e.g. a `String` assembled from config), rather than an identifier.
+/// Unlike identifiers, these names aren't checked at compile time:
+///
+/// ```
+/// #[macro_use]
+/// extern crate influx_writer;
+///
+/// use influx_writer::{AsI64, OwnedMeasurement, OwnedValue};
+///
+/// fn main() {
+///     let (tx, rx) = crossbeam_channel::bounded(1024);
+///     let exchange = "plnx";
+///     measure!(tx, @name(format!("{}_fills", exchange)), i(n, 1));
+///     let meas: OwnedMeasurement = rx.recv().unwrap();
+///     assert_eq!(meas.key, "plnx_fills");
+///     assert_eq!(meas.get_field("n"), Some(&OwnedValue::Integer(1)));
+/// }
+/// ```
+///
+/// The `dt` kind adds a `DateTime<Utc>` field, e.g. a secondary timestamp
+/// like `dt(exchange_time, fill.time)` (see `SerializeOptions::datetime_format`).
+///
+/// The `oi`, `of`, `os`, `od`, `ou`, `odt` and `ob` kinds take an `Option`
+/// of the value `i`, `f`, etc. take, and only add the field when it's
+/// `Some`:
+///
+/// ```
+/// #[macro_use]
+/// extern crate influx_writer;
+///
+/// use influx_writer::{AsF64, AsI64, OwnedMeasurement, OwnedValue};
+///
+/// fn main() {
+///     let (tx, rx) = crossbeam_channel::bounded(1024);
+///     let (bid, ask): (Option<f64>, Option<f64>) = (Some(1.5), None);
+///     measure!(tx, book, of[bid], of[ask], oi[depth; Some(12)]);
+///     let meas: OwnedMeasurement = rx.recv().unwrap();
+///     assert_eq!(meas.get_field("bid"), Some(&OwnedValue::Float(1.5)));
+///     assert_eq!(meas.get_field("ask"), None);
+///     assert_eq!(meas.get_field("depth"), Some(&OwnedValue::Integer(12)));
+/// }
+/// ```
+///
+/// The `mkt` kind adds the tags of a `MarketTags`, e.g.
+/// `measure!(influx, fills, mkt(MarketTags::new("plnx", "xmr_btc")), f(price, 1.2345))`.
+///
+/// With an `InfluxWriter` built with a `high_water_mark`, the `@shed` flag
+/// skips the measurement entirely (including building it) while the
+/// writer's queue is at or above the mark:
+///
+/// ```no_run
+/// #[macro_use]
+/// extern crate influx_writer;
+///
+/// use influx_writer::InfluxWriter;
+///
+/// fn main() {
+///     let influx = InfluxWriter::builder("localhost", "test").high_water_mark(2048).build();
+///     measure!(@shed influx, ticks, t(venue, "x"), i(n, 1));
+/// }
+/// ```
+///
 #[macro_export]
 macro_rules! measure {
     (@kv $t:tt, $meas:ident, $k:tt => $v:expr) => { measure!(@ea $t, $meas, stringify!($k), $v) };
@@ -142,13 +417,18 @@ macro_rules! measure {
     (@kv tm, $meas:ident, $tm:expr) => { $meas = $meas.set_timestamp(AsI64::as_i64($tm)) };
     (@kv utc, $meas:ident, $tm:expr) => { $meas = $meas.set_timestamp(AsI64::as_i64($crate::nanos($tm))) };
     (@kv v, $meas:ident, $k:expr) => { measure!(@ea t, $meas, "version", $k) };
+    (@kv mkt, $meas:ident, $tags:expr) => { $meas = $crate::MarketTags::add_to($tags, $meas) };
     (@kv $t:tt, $meas:ident, $k:tt) => { measure!(@ea $t, $meas, stringify!($k), measure!(@as_expr $k)) };
-    (@ea t, $meas:ident, $k:expr, $v:expr) => { $meas = $meas.add_tag($k, $v); };
+    (@ea t, $meas:ident, $k:expr, $v:expr) => {
+        const _: () = assert!($crate::is_valid_name($k), "invalid tag key: contains a space or comma, or starts with '_'");
+        $meas = $meas.add_tag($k, $v);
+    };
     (@ea i, $meas:ident, $k:expr, $v:expr) => { $meas = $meas.add_field($k, $crate::OwnedValue::Integer(AsI64::as_i64($v))) };
     (@ea f, $meas:ident, $k:expr, $v:expr) => { $meas = $meas.add_field($k, $crate::OwnedValue::Float(AsF64::as_f64($v))) };
     (@ea s, $meas:ident, $k:expr, $v:expr) => { $meas = $meas.add_field($k, $crate::OwnedValue::String($v)) };
     (@ea d, $meas:ident, $k:expr, $v:expr) => { $meas = $meas.add_field($k, $crate::OwnedValue::D128($v)) };
     (@ea u, $meas:ident, $k:expr, $v:expr) => { $meas = $meas.add_field($k, $crate::OwnedValue::Uuid($v)) };
+    (@ea dt, $meas:ident, $k:expr, $v:expr) => { $meas = $meas.add_field($k, $crate::OwnedValue::DateTime($v)) };
     (@ea b, $meas:ident, $k:expr, $v:expr) => { $meas = $meas.add_field($k, $crate::OwnedValue::Boolean(bool::from($v))) };
 
     (@ea D, $meas:ident, $k:expr, $v:expr) => { 
@@ -158,6 +438,14 @@ macro_rules! measure {
         }
     };
 
+    (@ea oi, $meas:ident, $k:expr, $v:expr) => { if let Some(v) = $v { measure!(@ea i, $meas, $k, v) } };
+    (@ea of, $meas:ident, $k:expr, $v:expr) => { if let Some(v) = $v { measure!(@ea f, $meas, $k, v) } };
+    (@ea os, $meas:ident, $k:expr, $v:expr) => { if let Some(v) = $v { measure!(@ea s, $meas, $k, v) } };
+    (@ea od, $meas:ident, $k:expr, $v:expr) => { if let Some(v) = $v { measure!(@ea d, $meas, $k, v) } };
+    (@ea ou, $meas:ident, $k:expr, $v:expr) => { if let Some(v) = $v { measure!(@ea u, $meas, $k, v) } };
+    (@ea odt, $meas:ident, $k:expr, $v:expr) => { if let Some(v) = $v { measure!(@ea dt, $meas, $k, v) } };
+    (@ea ob, $meas:ident, $k:expr, $v:expr) => { if let Some(v) = $v { measure!(@ea b, $meas, $k, v) } };
+
     (@as_expr $e:expr) => {$e};
 
     (@count_tags) => {0usize};
@@ -169,11 +457,28 @@ macro_rules! measure {
     (@count_fields time $($tail:tt)*) => {0usize + measure!(@count_fields $($tail)*)};
     (@count_fields $t:tt $($tail:tt)*) => {1usize + measure!(@count_fields $($tail)*)};
 
+    (@make_meas @name($name:expr), $( $t:tt ( $($tail:tt)* ) ),+ $(,)*) => {
+        measure!(@make_meas @name($name), $( $t [ $($tail)* ] ),*)
+    };
+
+    (@make_meas @name($name:expr), $( $t:tt [ $($tail:tt)* ] ),+ $(,)*) => {{
+        let n_tags = measure!(@count_tags $($t)*);
+        let n_fields = measure!(@count_fields $($t)*);
+        let mut meas =
+            $crate::OwnedMeasurement::with_capacity($name, n_tags, n_fields);
+        $(
+            measure!(@kv $t, meas, $($tail)*);
+        )*
+        meas
+    }};
+
     (@make_meas $name:tt, $( $t:tt ( $($tail:tt)* ) ),+ $(,)*) => {
         measure!(@make_meas $name, $( $t [ $($tail)* ] ),*)
     };
 
     (@make_meas $name:tt, $( $t:tt [ $($tail:tt)* ] ),+ $(,)*) => {{
+        const _: () = assert!($crate::is_valid_name(stringify!($name)),
+            "invalid measurement name: contains a space or comma, or starts with '_'");
         let n_tags = measure!(@count_tags $($t)*);
         let n_fields = measure!(@count_fields $($t)*);
         let mut meas =
@@ -184,6 +489,37 @@ macro_rules! measure {
         meas
     }};
 
+    (@shed $m:tt, @name($name:expr), $( $t:tt ( $($tail:tt)* ) ),+ $(,)*) => {{
+        if $m.should_shed() {
+            $m.count_shed();
+        } else {
+            measure!($m, @name($name), $( $t [ $($tail)* ] ),+)
+        }
+    }};
+
+    (@shed $m:tt, $name:tt, $( $t:tt ( $($tail:tt)* ) ),+ $(,)*) => {
+        measure!(@shed $m, $name, $($t [ $($tail)* ] ),+)
+    };
+
+    (@shed $m:tt, $name:tt, $( $t:tt [ $($tail:tt)* ] ),+ $(,)*) => {{
+        if $m.should_shed() {
+            $m.count_shed();
+        } else {
+            measure!($m, $name, $( $t [ $($tail)* ] ),+)
+        }
+    }};
+
+    ($m:expr, @name($name:expr), $( $t:tt ( $($tail:tt)* ) ),+ $(,)*) => {
+        measure!($m, @name($name), $($t [ $($tail)* ] ),+)
+    };
+
+    ($m:tt, @name($name:expr), $( $t:tt [ $($tail:tt)* ] ),+ $(,)*) => {{
+        #[allow(unused_imports)]
+        use $crate::{AsI64, AsF64};
+        let measurement = measure!(@make_meas @name($name), $( $t [ $($tail)* ] ),*);
+        let _ = $m.send(measurement);
+    }};
+
     ($m:expr, $name:tt, $( $t:tt ( $($tail:tt)* ) ),+ $(,)*) => {
         measure!($m, $name, $($t [ $($tail)* ] ),+)
     };
@@ -196,6 +532,35 @@ macro_rules! measure {
     }};
 }
 
+/// Whether `name` is usable as a measurement name or tag key: not empty,
+/// without spaces or commas, and not starting with an underscore (which
+/// influxdb reserves). Checked at compile time by `measure!`:
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate influx_writer;
+///
+/// fn main() {
+///     let influx = influx_writer::InfluxWriter::placeholder();
+///     measure!(influx, _requests, i(n, 1));
+/// }
+/// ```
+///
+pub const fn is_valid_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    if bytes.is_empty() || bytes[0] == b'_' {
+        return false
+    }
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b' ' || bytes[i] == b',' {
+            return false
+        }
+        i += 1;
+    }
+    true
+}
+
 /// converts a chrono::DateTime to an integer timestamp (ns)
 ///
 #[inline]
@@ -292,6 +657,15 @@ pub struct InfluxWriter {
     db: String,
     tx: Sender<Option<OwnedMeasurement>>,
     thread: Option<Arc<thread::JoinHandle<()>>>,
+    tag_thread: bool,
+    tag_correlation_id: bool,
+    high_water_mark: usize,
+    done: Option<Receiver<FlushStats>>,
+    stats: Arc<Counters>,
+    stderr_fallback: Option<Arc<StderrFallback>>,
+    pool: Option<Arc<MeasurementPool>>,
+    audit: Option<Arc<EnqueueAudit>>,
+    config: Arc<RwLock<WriterConfig>>,
 }
 
 impl Default for InfluxWriter {
@@ -309,6 +683,15 @@ impl Clone for InfluxWriter {
             db: self.db.to_string(),
             tx: self.tx.clone(),
             thread,
+            tag_thread: self.tag_thread,
+            tag_correlation_id: self.tag_correlation_id,
+            high_water_mark: self.high_water_mark,
+            done: self.done.clone(),
+            stats: Arc::clone(&self.stats),
+            stderr_fallback: self.stderr_fallback.clone(),
+            pool: self.pool.clone(),
+            audit: self.audit.clone(),
+            config: Arc::clone(&self.config),
         }
     }
 }
@@ -318,11 +701,213 @@ impl InfluxWriter {
 
     pub fn db(&self) -> &str { self.db.as_str() }
 
+    /// A snapshot of the writer's effective configuration, including any
+    /// changes made at runtime.
+    ///
+    pub fn config(&self) -> WriterConfig {
+        self.config.read().map(|config| config.clone()).unwrap_or_default()
+    }
+
     /// Sends the `OwnedMeasurement` to the serialization thread.
     ///
+    /// If the writer was built with `tag_thread(true)`, the name of the
+    /// calling thread is added as a "thread" tag first (unless the measurement
+    /// already has one, or the thread is unnamed). Likewise for the current
+    /// correlation id with `tag_correlation_id(true)`.
+    ///
+    #[inline]
+    pub fn send(&self, m: OwnedMeasurement) -> Result<(), Error> {
+        let start = self.audit_start();
+        let key = start.map(|_| m.key.clone());
+        let result = self.tx.send(Some(self.tag(m))).map_err(|e| self.fall_back(Error::from(e)));
+        self.audit_finish(key, start);
+        result
+    }
+
+    /// The time a sampled send started, if `audit_enqueue` is on.
+    ///
+    #[inline]
+    fn audit_start(&self) -> Option<Instant> {
+        match self.audit {
+            Some(ref audit) if audit.sample() => Some(Instant::now()),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn audit_finish(&self, key: Option<Key>, start: Option<Instant>) {
+        if let (Some(start), Some(key), Some(ref audit)) = (start, key, &self.audit) {
+            audit.record(&key, start.elapsed());
+        }
+    }
+
+    /// Writes the measurement returned in an `Error::Queue` (the writer
+    /// thread is gone) to stderr, if configured (see
+    /// `InfluxWriterBuilder::stderr_fallback`).
+    ///
+    fn fall_back(&self, e: Error) -> Error {
+        if let (Error::Queue(ref m), Some(ref fallback)) = (&e, &self.stderr_fallback) {
+            fallback.measurement(m);
+        }
+        e
+    }
+
+    #[cfg(feature = "string-tags")]
+    #[inline]
+    fn tag(&self, m: OwnedMeasurement) -> OwnedMeasurement {
+        let m = if self.tag_thread { tag_current_thread(m) } else { m };
+        if self.tag_correlation_id { tag_correlation_id(m) } else { m }
+    }
+
+    #[cfg(not(feature = "string-tags"))]
+    #[inline]
+    fn tag(&self, m: OwnedMeasurement) -> OwnedMeasurement { m }
+
+    /// Like `send`, but returns `Error::QueueFull` instead of blocking if
+    /// the writer thread's queue is full (see
+    /// `InfluxWriterBuilder::queue_capacity`), so the caller can decide
+    /// whether to drop the measurement or wait.
+    ///
+    pub fn try_send(&self, m: OwnedMeasurement) -> Result<(), Error> {
+        let start = self.audit_start();
+        let key = start.map(|_| m.key.clone());
+        let result = self.tx.try_send(Some(self.tag(m))).map_err(|e| self.fall_back(Error::from(e)));
+        self.audit_finish(key, start);
+        result
+    }
+
+    /// A new measurement, reused from the pool if one is configured (see
+    /// `InfluxWriterBuilder::measurement_pool`) and not empty.
+    ///
+    #[inline]
+    pub fn acquire<K: Into<Key>>(&self, key: K) -> OwnedMeasurement {
+        match self.pool {
+            Some(ref pool) => pool.measurement(key),
+            None => OwnedMeasurement::new(key),
+        }
+    }
+
+    /// A copy of `value`, in a string reused from the pool if one is
+    /// configured. For tag values (with `OwnedMeasurement::add_tag_string`)
+    /// and string fields.
+    ///
+    #[inline]
+    pub fn acquire_string(&self, value: &str) -> String {
+        match self.pool {
+            Some(ref pool) => pool.string(value),
+            None => value.to_string(),
+        }
+    }
+
+    /// Sends one measurement with the fields (and tags) added by `f`, so
+    /// that related values read under one lock (or from one atomic
+    /// snapshot) are written together, with a single timestamp, rather
+    /// than as several measurements taken at slightly different times.
+    ///
+    /// The timestamp is the time `f` is called, unless `f` sets another.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::sync::Mutex;
+    /// use influx_writer::{InfluxWriter, OwnedValue};
+    ///
+    /// struct Book { bid: f64, ask: f64 }
+    ///
+    /// let book = Mutex::new(Book { bid: 99.5, ask: 100.5 });
+    /// let influx = InfluxWriter::new("localhost", "test");
+    /// influx.snapshot("book", |m| {
+    ///     let book = book.lock().unwrap();
+    ///     m.add_field("bid", OwnedValue::Float(book.bid))
+    ///         .add_field("ask", OwnedValue::Float(book.ask))
+    /// }).unwrap();
+    /// ```
+    ///
+    pub fn snapshot<K, F>(&self, key: K, f: F) -> Result<(), Error>
+        where K: Into<Key>,
+              F: FnOnce(OwnedMeasurement) -> OwnedMeasurement
+    {
+        let m = self.acquire(key).set_timestamp(now());
+        self.send(f(m))
+    }
+
+    /// Whether the writer thread's queue has reached the high water mark
+    /// (see `InfluxWriterBuilder::high_water_mark`), in which case the
+    /// caller should skip sending (and call `count_shed`).
+    ///
+    /// Used by `measure!(@shed ..)`, and cheap enough to check before
+    /// building a measurement.
+    ///
+    #[inline]
+    pub fn should_shed(&self) -> bool {
+        self.tx.len() >= self.high_water_mark
+    }
+
+    /// Counts a measurement skipped because `should_shed` returned `true`
+    /// in `WriterStats::n_dropped`. `measure!(@shed ..)` calls this itself.
+    ///
     #[inline]
-    pub fn send(&self, m: OwnedMeasurement) -> Result<(), SendError<Option<OwnedMeasurement>>> {
-        self.tx.send(Some(m))
+    pub fn count_shed(&self) {
+        self.stats.n_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current counters of the writer thread. Useful for
+    /// alerting when telemetry itself is failing, e.g. on a rising
+    /// `n_request_errors` or `backlog_len`.
+    ///
+    pub fn stats(&self) -> WriterStats { self.stats.snapshot() }
+
+    /// Starts timing a section of code, returning a guard that logs (at
+    /// debug level, through a child of `logger`) and sends the elapsed time
+    /// as a "span" measurement when dropped. Spans opened while another is
+    /// open on the same thread are recorded with it as their "parent".
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use influx_writer::InfluxWriter;
+    /// # use slog::Drain;
+    ///
+    /// let influx = InfluxWriter::new("localhost", "test");
+    /// # let logger = slog::Logger::root(slog::Discard.fuse(), slog::o!());
+    /// {
+    ///     let span = influx.span("handle_order", &logger);
+    ///     slog::info!(span.logger(), "validating");
+    ///     let _db = influx.span("db_insert", &logger);
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    pub fn span(&self, name: &'static str, logger: &Logger) -> Span<'_> {
+        Span::enter(self, name, logger)
+    }
+
+    /// Shuts down the writer thread, waiting up to `timeout` for it to send
+    /// any remaining buffered measurements.
+    ///
+    /// Unlike dropping the writer, this shuts down the thread even if other
+    /// handles (clones) exist: sends from those handles will fail afterwards.
+    ///
+    /// Returns `Error::Shutdown` if the thread does not finish by the
+    /// deadline (it will keep trying in the background until
+    /// `DROP_DEADLINE` has passed). Batches the thread gave up on are
+    /// counted in `FlushStats::n_batches_unsent`.
+    ///
+    pub fn close(mut self, timeout: Duration) -> Result<FlushStats, Error> {
+        let deadline = Instant::now() + timeout;
+        let thread = self.thread.take();
+        let done = self.done.take()
+            .ok_or_else(|| Error::Shutdown("writer thread is not running".to_string()))?;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        self.tx.send_timeout(None, remaining)
+            .map_err(|_| Error::Shutdown("timed out queueing terminate signal".to_string()))?;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let stats = done.recv_timeout(remaining)
+            .map_err(|_| Error::Shutdown(format!("writer thread did not finish within {:?}", timeout)))?;
+        if let Some(thread) = thread.and_then(|arc| Arc::try_unwrap(arc).ok()) {
+            let _ = thread.join();
+        }
+        Ok(stats)
     }
 
     #[inline]
@@ -354,6 +939,27 @@ impl InfluxWriter {
     #[inline]
     pub fn is_full(&self) -> bool { self.tx.is_full() }
 
+    /// A writer without a thread, sending to `tx`.
+    ///
+    #[cfg(test)]
+    pub(crate) fn test_writer(tx: Sender<Option<OwnedMeasurement>>) -> Self {
+        InfluxWriter {
+            host: String::new(),
+            db: String::new(),
+            tx,
+            thread: None,
+            tag_thread: false,
+            tag_correlation_id: false,
+            high_water_mark: usize::MAX,
+            done: None,
+            stats: Arc::new(Counters::default()),
+            stderr_fallback: None,
+            pool: None,
+            audit: None,
+            config: Arc::default(),
+        }
+    }
+
     pub fn placeholder() -> Self {
         let (tx, _) = bounded(1024);
         Self {
@@ -361,20 +967,90 @@ impl InfluxWriter {
             db: String::new(),
             tx,
             thread: None,
+            tag_thread: false,
+            tag_correlation_id: false,
+            high_water_mark: usize::MAX,
+            done: None,
+            stats: Arc::new(Counters::default()),
+            stderr_fallback: None,
+            pool: None,
+            audit: None,
+            config: Arc::default(),
         }
     }
 
+    /// Returns an `InfluxWriterBuilder`, which allows configuring optional
+    /// settings prior to starting the writer thread.
+    ///
+    /// `host` is a hostname or ip address, with an optional port (default:
+    /// 8086), e.g. "localhost", "influx:9999", "::1" or "[::1]:9999", or a
+    /// url, e.g. "https://influx.example.com:8086" or
+    /// "http://proxy.example.com/influx" (a url without a port uses its
+    /// scheme's default). An invalid `host` is an `Error::Config` from
+    /// `try_build`.
+    ///
+    pub fn builder(host: &str, db: &str) -> InfluxWriterBuilder {
+        InfluxWriterBuilder::new(host, db)
+    }
+
+    /// Panics if the writer can't be started (see `try_new`).
+    ///
     pub fn new(host: &str, db: &str) -> Self {
         let noop_logger = slog::Logger::root(slog::Discard.fuse(), o!());
         Self::with_logger_and_opt_creds(host, db, None, &noop_logger)
     }
 
+    /// Like `new`, but returns an error instead of panicking if `host`
+    /// does not produce a valid url or the writer thread can't be spawned.
+    ///
+    pub fn try_new(host: &str, db: &str) -> Result<Self, Error> {
+        Self::builder(host, db).try_build()
+    }
+
     pub fn get_credentials(username: String, password: Option<String>) -> Credentials {
         hyper::header::Authorization(
             hyper::header::Basic { username, password }
         )
     }
 
+    #[cfg(feature = "tls")]
+    fn http_client(https: bool, ca_cert: Option<&Path>, resolving: Option<&resolve::Resolving>) -> Result<Client, Error> {
+        match (https, resolving) {
+            (true, _) => tls::https_client(ca_cert, resolving),
+            (false, Some(resolving)) => Ok(pooled_client(resolving.clone(), Some(resolving))),
+            (false, None) => Ok(pooled_client(host::connect, None)),
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn http_client(https: bool, _ca_cert: Option<&Path>, resolving: Option<&resolve::Resolving>) -> Result<Client, Error> {
+        match (https, resolving) {
+            (true, _) => Err(Error::Config("https requires the \"tls\" feature".to_string())),
+            (false, Some(resolving)) => Ok(pooled_client(resolving.clone(), Some(resolving))),
+            (false, None) => Ok(pooled_client(host::connect, None)),
+        }
+    }
+
+    /// The client `ReqwestSink`s are built with when no client is given
+    /// with `reqwest_client`: its tls trusts `ca_cert` (pem or der), in
+    /// addition to the system's roots, and its pool is sized like hyper's.
+    ///
+    #[cfg(feature = "reqwest")]
+    fn reqwest_default_client(ca_cert: Option<&Path>) -> Result<reqwest::blocking::Client, Error> {
+        let mut builder = reqwest::blocking::Client::builder()
+            .pool_max_idle_per_host(HTTP_POOL_SIZE)
+            .pool_idle_timeout(HTTP_IDLE_TIMEOUT);
+        if let Some(path) = ca_cert {
+            let bytes = fs::read(path)
+                .map_err(|e| Error::Config(format!("failed to read ca cert {}: {}", path.display(), e)))?;
+            let cert = reqwest::Certificate::from_pem(&bytes)
+                .or_else(|_| reqwest::Certificate::from_der(&bytes))
+                .map_err(|e| Error::Config(format!("invalid ca cert {}: {}", path.display(), e)))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        builder.build().map_err(|e| Error::Config(format!("failed to initialize http client: {}", e)))
+    }
+
     fn http_req<'a>(client: &'a Client, url: Url, body: &'a str, creds: &Option<Credentials>) -> hyper::client::RequestBuilder<'a> {
         let req = client.post(url.clone())
             .body(body);
@@ -385,44 +1061,382 @@ impl InfluxWriter {
         }
     }
 
+    /// Sends `CREATE DATABASE` for `db` to the server's `/query` endpoint at
+    /// `url`, which succeeds (and does nothing) if the database exists.
+    ///
+    fn create_database(client: &Client, mut url: Url, db: &str, creds: &Option<Credentials>) -> Result<(), Error> {
+        let q = format!("CREATE DATABASE \"{}\"", db.replace('\\', "\\\\").replace('"', "\\\""));
+        url.query_pairs_mut().append_pair("q", &q);
+        let mut resp = Self::http_req(client, url, "", creds).send()?;
+        let mut body = String::new();
+        let _ = resp.read_to_string(&mut body);
+        // errors in the query itself are returned in the json body, with
+        // status 200
+        if resp.status != hyper::status::StatusCode::Ok || body.contains("\"error\"") {
+            return Err(Error::Transport {
+                status: Some(resp.status.to_u16()),
+                message: format!("failed to create database {}: {}", db, body),
+            })
+        }
+        Ok(())
+    }
+
+    /// Checks that the server's `/ping` endpoint at `url` responds.
+    ///
+    fn ping(client: &Client, url: Url) -> Result<(), Error> {
+        let resp = client.get(url).send()?;
+        if !resp.status.is_success() {
+            return Err(Error::Transport { status: Some(resp.status.to_u16()), message: format!("ping returned {}", resp.status) })
+        }
+        Ok(())
+    }
+
     #[allow(unused_assignments)]
     pub fn with_logger(host: &str, db: &str, logger: &Logger) -> Self {
         Self::with_logger_and_opt_creds(host, db, None, logger)
     }
 
     pub fn with_logger_and_opt_creds(host: &str, db: &str, creds: Option<Credentials>, logger: &Logger) -> Self {
+        let mut builder = Self::builder(host, db).logger(logger);
+        if let Some(creds) = creds {
+            builder = builder.credentials(creds);
+        }
+        builder.build()
+    }
+
+    fn spawn(config: InfluxWriterBuilder) -> Result<Self, Error> {
+        let InfluxWriterBuilder { host, db, creds, logger, tag_thread, tag_correlation_id, serialize_opts, buffer_size, flush_interval, https, ca_cert, warm_up, sink, hyper_client, retry, live_tail, admin, admin_token, spool_dir, spool_compression, encryption_key, queue_capacity, self_monitoring, dead_letter, gzip, routes, retention_policy, flush_bytes, high_water_mark, rate_limit, dedup, max_request_bytes, sampling, spool_replay_interval, process_start, standard_tags: _, stderr_fallback, check_on_start, create_database, measurement_pool, tee, audit_enqueue, cardinality_warning, run_id, watch_config, resolver, re_resolve_interval } = config;
+        if audit_enqueue.is_some() && self_monitoring.is_none() {
+            return Err(Error::Config("audit_enqueue requires self_monitoring".to_string()))
+        }
+        let audit = audit_enqueue.map(|sample_every| Arc::new(EnqueueAudit::new(sample_every)));
+        let pool = measurement_pool.map(|capacity| Arc::new(MeasurementPool::new(capacity)));
+        let stderr_fallback = stderr_fallback.map(|per_sec| Arc::new(StderrFallback::new(per_sec, serialize_opts.clone())));
+        let sink_kind = sink.kind();
+        let (host, db) = (host.as_str(), db.as_str());
+        // an https url as the host (or one of the load balanced hosts)
+        // implies `https`
+        let https = https || host::is_https(host) || match sink {
+            SinkConfig::LoadBalanced { ref hosts, .. } => hosts.iter().any(|h| host::is_https(h)),
+            _ => false,
+        };
+        let logger = logger.unwrap_or_else(|| slog::Logger::root(slog::Discard.fuse(), o!()));
+        let stats = Arc::new(Counters::default());
+        let config = Arc::new(RwLock::new(WriterConfig {
+            host: host.to_string(),
+            db: db.to_string(),
+            sink: sink_kind,
+            https,
+            gzip,
+            retention_policy: retention_policy.clone(),
+            buffer_size,
+            flush_interval,
+            flush_bytes,
+            queue_capacity,
+            high_water_mark,
+            rate_limit,
+            n_routes: routes.len(),
+            routes: {
+                let mut routes: Vec<(String, String)> = routes.iter().map(|(key, (db, _))| (key.clone(), db.clone())).collect();
+                routes.sort();
+                routes
+            },
+            sampling: {
+                let mut sampling: Vec<(String, u32)> = sampling.iter().map(|(key, n)| (key.clone(), *n)).collect();
+                sampling.sort();
+                sampling
+            },
+            spool: spool_dir.is_some(),
+            spool_replay_interval,
+            dead_letter: dead_letter.is_some(),
+            self_monitoring,
+            admin: admin.clone(),
+            re_resolve_interval: if resolver.is_some() || re_resolve_interval.is_some() {
+                Some(re_resolve_interval.unwrap_or(DEFAULT_RE_RESOLVE_INTERVAL))
+            } else {
+                None
+            },
+            config_file: watch_config.as_ref().map(|(path, _)| path.clone()),
+            features: config::enabled_features(),
+            run_id,
+            ..Default::default()
+        }));
+        // runtime controls, shared with the admin listener and config
+        // file watcher
+        let control = if admin.is_some() || watch_config.is_some() {
+            Some(admin::Control::new(Arc::clone(&stats), Arc::clone(&config)))
+        } else {
+            None
+        };
+        let logger = match control {
+            Some((ref ctl, _)) => ctl.logger(logger),
+            None => logger,
+        };
         let logger = logger.new(o!(
             "host" => host.to_string(),
             "db" => db.to_string()));
-        let (tx, rx): (Sender<Option<OwnedMeasurement>>, Receiver<Option<OwnedMeasurement>>) = bounded(4096);
-        let url =
-            Url::parse_with_params(&format!("http://{}:8086/write", host),
-                                   &[("db", db), ("precision", "ns")])
-                .expect("influx writer url should parse");
-        let thread = thread::Builder::new().name(format!("inflx:{}", db)).spawn(move || {
-            use std::time::*;
-            use crossbeam_channel as chan;
-
-            #[cfg(feature = "no-influx-buffer")]
-            const N_BUFFER_LINES: usize = 0;
-
-            const N_BUFFER_LINES: usize = 1024;
-            const MAX_PENDING: Duration = Duration::from_secs(3);
-            const INITIAL_BUFFER_CAPACITY: usize = 4096;
-            const MAX_BACKLOG: usize = 1024;
-            const MAX_OUTSTANDING_HTTP: usize = 64;
-            const DEBUG_HB_EVERY: usize = 1024 * 96;
-            const INFO_HB_EVERY: usize = 1024 * 1024;
-            const N_HTTP_ATTEMPTS: u32 = 15;
-            const INITIAL_BACKLOG: usize = MAX_OUTSTANDING_HTTP * 2;
-
-            let client = Arc::new(Client::new());
-            let creds = Arc::new(creds);
-
-            info!(logger, "initializing InfluxWriter ...";
-                "N_BUFFER_LINES" => N_BUFFER_LINES,
-                "MAX_PENDING" => %format_args!("{:?}", MAX_PENDING),
-                "MAX_OUTSTANDING_HTTP" => MAX_OUTSTANDING_HTTP,
+        if let Ok(config) = config.read() {
+            info!(logger, "InfluxWriter: config"; "config" => ?*config);
+        }
+        let (tx, rx): (Sender<Option<OwnedMeasurement>>, Receiver<Option<OwnedMeasurement>>) = bounded(queue_capacity);
+        #[cfg(feature = "live-tail")]
+        let live_tail = match live_tail {
+            Some((addr, sample_every)) => Some(live_tail::LiveTail::bind(&addr, sample_every, &logger)?),
+            None => None,
+        };
+        #[cfg(not(feature = "live-tail"))]
+        let _ = live_tail;
+        let mut spool = match spool_dir {
+            Some(dir) => Some(spool::Spool::open(&dir, spool_compression, encryption_key.clone())
+                .map_err(|e| Error::Config(format!("failed to open spool dir {}: {}", dir.display(), e)))?),
+            None => None,
+        };
+        let dead_letter = match dead_letter {
+            Some(DeadLetter::File(path, _)) => Some(DeadLetter::File(path, encryption_key)),
+            other => other,
+        };
+        let admin_listener = match (admin, &control) {
+            (Some(addr), Some((ctl, _))) => Some(admin::Admin::listen(&addr, admin_token, Arc::clone(ctl), &logger)?),
+            _ => None,
+        };
+        let admin = control;
+        let write_url = |host: &str, db: &str, rp: Option<&str>| -> Result<Url, Error> {
+            let mut url = host::endpoint(host, https, "write")?;
+            url.query_pairs_mut().append_pair("db", db).append_pair("precision", "ns");
+            if let Some(rp) = rp { url.query_pairs_mut().append_pair("rp", rp); }
+            Ok(url)
+        };
+        // shared by the http clients, so there's one cache of addresses
+        let resolving = if resolver.is_some() || re_resolve_interval.is_some() {
+            let resolver = resolver.unwrap_or_else(|| Arc::new(SystemResolver));
+            Some(resolve::Resolving::new(resolver, re_resolve_interval.unwrap_or(DEFAULT_RE_RESOLVE_INTERVAL), &logger))
+        } else {
+            None
+        };
+        // reqwest is the default transport, unless `hyper_client` was set
+        // or addresses come from `resolving`, which plugs into hyper's
+        // connector. One client (and connection pool) is shared by the sinks
+        #[cfg(feature = "reqwest")]
+        let reqwest_client = match sink {
+            SinkConfig::Http | SinkConfig::LoadBalanced { .. } if !hyper_client && resolving.is_none() => {
+                Some(Self::reqwest_default_client(ca_cert.as_deref())?)
+            }
+            _ => None,
+        };
+        #[cfg(not(feature = "reqwest"))]
+        let _ = hyper_client;
+        let http_sink = |url: Url| -> Result<Box<dyn Sink>, Error> {
+            #[cfg(feature = "reqwest")]
+            {
+                if let Some(ref client) = reqwest_client {
+                    let http = sink::ReqwestSink::new(client.clone(), url, creds.clone());
+                    #[cfg(feature = "gzip")]
+                    let http = http.gzip(gzip);
+                    return Ok(Box::new(http))
+                }
+            }
+            let client = Self::http_client(https, ca_cert.as_deref(), resolving.as_ref())?;
+            let http = HttpSink::new(client, url, creds.clone());
+            #[cfg(feature = "gzip")]
+            let http = http.gzip(gzip);
+            Ok(Box::new(http))
+        };
+        #[cfg(not(feature = "gzip"))]
+        let _ = gzip;
+        // the servers written to, with the http sinks
+        let http_hosts: Option<Vec<&str>> = match sink {
+            SinkConfig::LoadBalanced { ref hosts, .. } => Some(hosts.iter().map(String::as_str).collect()),
+            SinkConfig::Udp { .. } | SinkConfig::Statsd { .. } | SinkConfig::Graphite { .. } | SinkConfig::File(_) | SinkConfig::Custom(_) => None,
+            #[cfg(feature = "kafka")]
+            SinkConfig::Kafka { .. } => None,
+            #[cfg(feature = "mqtt")]
+            SinkConfig::Mqtt { .. } => None,
+            _ => Some(vec![host]),
+        };
+        if let (true, Some(hosts)) = (check_on_start, &http_hosts) {
+            // the empty write below goes through the sink, which with
+            // `load_balance` reaches at most one of the hosts
+            let client = Self::http_client(https, ca_cert.as_deref(), resolving.as_ref())?;
+            for &host in hosts {
+                if let Err(e) = Self::ping(&client, host::endpoint(host, https, "ping")?) {
+                    error!(logger, "InfluxWriter: startup check failed: server unreachable: {}", e; "target_host" => host);
+                    let status = match e { Error::Transport { status, .. } => status, _ => None };
+                    return Err(Error::Transport { status, message: format!("startup check for host {}: server unreachable: {}", host, e) })
+                }
+            }
+        }
+        if create_database {
+            let hosts = http_hosts.ok_or_else(|| Error::Config("create_database requires an http sink".to_string()))?;
+            let mut dbs = vec![db];
+            for (route_db, _) in routes.values() {
+                if !dbs.contains(&route_db.as_str()) { dbs.push(route_db.as_str()) }
+            }
+            let client = Self::http_client(https, ca_cert.as_deref(), resolving.as_ref())?;
+            for host in hosts {
+                let url = host::endpoint(host, https, "query")?;
+                for db in &dbs {
+                    Self::create_database(&client, url.clone(), db, &creds)?;
+                    info!(logger, "InfluxWriter: created database (if it did not exist)"; "target_host" => host, "target_db" => db);
+                }
+            }
+        }
+        // a sink writing to `db` and retention policy `rp` (only possible
+        // with the http sinks)
+        let db_sink = |db: &str, rp: Option<&str>| -> Result<Arc<dyn Sink>, Error> {
+            match sink {
+                SinkConfig::Http => Ok(Arc::from(http_sink(write_url(host, db, rp)?)?)),
+                SinkConfig::LoadBalanced { ref hosts, balance } => {
+                    if hosts.is_empty() {
+                        return Err(Error::Config("load_balance requires at least one host".to_string()))
+                    }
+                    let mut sinks: Vec<Box<dyn Sink>> = Vec::with_capacity(hosts.len());
+                    for host in hosts {
+                        sinks.push(http_sink(write_url(host, db, rp)?)?);
+                    }
+                    Ok(Arc::new(LoadBalancedSink::new(sinks, balance)))
+                }
+                #[cfg(feature = "reqwest")]
+                SinkConfig::Reqwest(ref client) => {
+                    Ok(Arc::new(sink::ReqwestSink::new(client.clone(), write_url(host, db, rp)?, creds.clone())))
+                }
+                SinkConfig::Udp { .. } | SinkConfig::Statsd { .. } | SinkConfig::Graphite { .. } | SinkConfig::File(_) | SinkConfig::Custom(_) => {
+                    Err(Error::Config("routing measurements by key requires an http sink".to_string()))
+                }
+                #[cfg(feature = "kafka")]
+                SinkConfig::Kafka { .. } => {
+                    Err(Error::Config("routing measurements by key requires an http sink".to_string()))
+                }
+                #[cfg(feature = "mqtt")]
+                SinkConfig::Mqtt { .. } => {
+                    Err(Error::Config("routing measurements by key requires an http sink".to_string()))
+                }
+            }
+        };
+        // an empty write, which fails if the server is unreachable, the
+        // credentials are rejected or the database doesn't exist
+        let check = |sink: &Arc<dyn Sink>, db: &str| -> Result<(), Error> {
+            if !check_on_start { return Ok(()) }
+            match sink.write("") {
+                Ok(()) => {
+                    info!(logger, "InfluxWriter: startup check ok"; "target_db" => db);
+                    Ok(())
+                }
+
+                Err(Error::Transport { status, message }) => {
+                    let problem = match status {
+                        None => "server unreachable",
+                        Some(401) | Some(403) => "credentials rejected",
+                        Some(404) => "database not found",
+                        Some(_) => "write failed",
+                    };
+                    error!(logger, "InfluxWriter: startup check failed: {}: {}", problem, message; "target_db" => db, "status" => status);
+                    Err(Error::Transport { status, message: format!("startup check for db {}: {}: {}", db, problem, message) })
+                }
+
+                Err(e) => Err(e),
+            }
+        };
+        let default_sink: Arc<dyn Sink> = match sink {
+            SinkConfig::Udp { ref addr, max_datagram_size } => Arc::new(UdpSink::new(addr, max_datagram_size)?),
+            SinkConfig::Statsd { ref addr, max_datagram_size } => Arc::new(StatsdSink::new(addr, max_datagram_size)?),
+            SinkConfig::Graphite { ref addr, ref template } => Arc::new(GraphiteSink::new(addr).template(template)),
+            SinkConfig::File(ref path) => Arc::new(FileSink::new(path)?),
+            #[cfg(feature = "kafka")]
+            SinkConfig::Kafka { ref brokers, ref topic } => Arc::new(sink::KafkaSink::new(brokers, topic)?),
+            #[cfg(feature = "mqtt")]
+            SinkConfig::Mqtt { ref host, port, ref topic } => Arc::new(sink::MqttSink::new(host, port, topic.clone())?),
+            SinkConfig::Custom(ref sink) => Arc::clone(sink),
+            _ => db_sink(db, retention_policy.as_deref())?,
+        };
+        check(&default_sink, db)?;
+        let mut router = None;
+        let sink: Arc<dyn Sink> = if routes.is_empty() {
+            default_sink
+        } else {
+            let mut by_target: HashMap<(&str, Option<&str>), Arc<dyn Sink>> = HashMap::new();
+            by_target.insert((db, retention_policy.as_deref()), Arc::clone(&default_sink));
+            let mut routing = RoutingSink::new(default_sink);
+            for (key, (route_db, route_rp)) in &routes {
+                let target_key = (route_db.as_str(), route_rp.as_deref().or(retention_policy.as_deref()));
+                let target = match by_target.get(&target_key) {
+                    Some(target) => Arc::clone(target),
+                    None => {
+                        let target = db_sink(target_key.0, target_key.1)?;
+                        check(&target, target_key.0)?;
+                        by_target.insert(target_key, Arc::clone(&target));
+                        target
+                    }
+                };
+                // routing happens after serialization, so with the key prefix
+                routing = routing.route(&format!("{}{}", escape_tag(&serialize_opts.key_prefix), escape_tag(key)), target);
+            }
+            let routing = Arc::new(routing);
+            let mut targets: HashMap<String, Arc<dyn Sink>> = HashMap::new();
+            targets.insert(db.to_string(), Arc::clone(&by_target[&(db, retention_policy.as_deref())]));
+            for ((target_db, _), target) in &by_target {
+                targets.entry(target_db.to_string()).or_insert_with(|| Arc::clone(target));
+            }
+            router = Some(watch::Router { sink: Arc::clone(&routing), targets, key_prefix: serialize_opts.key_prefix.clone() });
+            routing
+        };
+        if let (Some((path, interval)), Some((ctl, _))) = (watch_config, &admin) {
+            watch::watch(path, interval, ctl, router, Arc::clone(&config), &logger)?;
+        }
+        let sink: Arc<dyn Sink> = if tee.is_empty() {
+            sink
+        } else {
+            Arc::new(TeeSink::new(std::iter::once(sink).chain(tee).collect())
+                .logger(&logger)
+                .counter(Arc::clone(&stats.n_tee_errors)))
+        };
+        let sink: Arc<dyn Sink> = match max_request_bytes {
+            Some((max_bytes, concurrency)) => Arc::new(ChunkedSink::new(sink, max_bytes, concurrency)),
+            None => sink,
+        };
+        // how many backlog batches are sent at once
+        let backlog_concurrency = max_request_bytes.map(|(_, concurrency)| concurrency.max(1)).unwrap_or(1);
+        let (done_tx, done_rx) = bounded(1);
+        let counters = Arc::clone(&stats);
+        let fallback = stderr_fallback.clone();
+        let recycle = pool.clone();
+        let thread = thread::Builder::new().name(format!("inflx:{}", db)).spawn(move || {
+            use std::time::*;
+            use crossbeam_channel as chan;
+
+            // stops listening when the thread exits
+            let _admin_listener = admin_listener;
+
+            // adjustable at runtime with `watch_config`
+            let n_buffer_lines = || match admin {
+                Some((ref ctl, _)) => ctl.buffer_size().min(INFLUX_WRITER_MAX_BUFFER),
+                None => buffer_size.min(INFLUX_WRITER_MAX_BUFFER),
+            };
+            let max_pending = || match admin {
+                Some((ref ctl, _)) => ctl.flush_interval(),
+                None => flush_interval,
+            };
+            let flush_bytes = flush_bytes.unwrap_or(usize::MAX);
+            const INITIAL_BUFFER_CAPACITY: usize = 4096;
+            const MAX_BACKLOG: usize = 1024;
+            const MAX_OUTSTANDING_HTTP: usize = 64;
+            const DEBUG_HB_EVERY: usize = 1024 * 96;
+            const INFO_HB_EVERY: usize = 1024 * 1024;
+            const INITIAL_BACKLOG: usize = MAX_OUTSTANDING_HTTP * 2;
+            // a buffer returned to `spares` is shrunk to at most this, so one
+            // oversized batch (or measurement sent in its own request) doesn't
+            // keep its memory for the life of the writer
+            const MAX_SPARE_CAPACITY: usize = 1024 * 1024;
+            // while writes are failing and nothing else is being sent, a
+            // spooled batch is replayed this often to find out whether the
+            // server is back
+            const SPOOL_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+
+            info!(logger, "initializing InfluxWriter ...";
+                "retry" => ?retry,
+                "n_buffer_lines" => n_buffer_lines(),
+                "max_pending" => %format_args!("{:?}", max_pending()),
+                "MAX_OUTSTANDING_HTTP" => MAX_OUTSTANDING_HTTP,
                 "INITIAL_BUFFER_CAPACITY" => INITIAL_BUFFER_CAPACITY,
                 "INITIAL_BACKLOG" => INITIAL_BACKLOG,
                 "MAX_BACKLOG" => MAX_BACKLOG,
@@ -457,6 +1471,10 @@ impl InfluxWriter {
 
             let (http_tx, http_rx) = chan::bounded(32);
 
+            // latest line (sans timestamp) of each series of the measurements
+            // configured with `warm_up_gauges`, keyed by series
+            let mut gauges: HashMap<String, String> = HashMap::new();
+
             let mut buf = spares.pop_front().unwrap();
             let mut count = 0;
             let mut extras = 0; // any new Strings we intro to the system
@@ -467,6 +1485,15 @@ impl InfluxWriter {
             let mut last_clear = Instant::now();
             let mut last_memory_check = Instant::now();
             let mut loop_time: Instant;
+            let mut n_ok = 0; // batches sent during shutdown
+            let mut n_err = 0;
+            let mut n_spooled = 0;
+            let mut rate_limit = rate_limit.map(|per_sec| TokenBucket::new(per_sec, Instant::now()));
+            let mut dedup = dedup.map(|(keys, keepalive)| Dedup::new(keys, keepalive));
+            let mut cardinality = cardinality_warning.map(|(limit, hook)| Cardinality::new(limit, hook));
+            // key -> (keep 1 in n, number seen)
+            let mut sampling: HashMap<String, (u32, u64)> = sampling.into_iter().map(|(k, n)| (k, (n, 0))).collect();
+            let mut sampling_version = 0;
 
             let n_out = |s: &VecDeque<String>, b: &VecDeque<String>, extras: usize| -> usize {
                 INITIAL_BACKLOG + extras - s.len() - b.len() - 1
@@ -480,40 +1507,53 @@ impl InfluxWriter {
                 + (*in_flight_buffer_bytes)
             };
 
+            // passes a batch being flushed to the live tail, if enabled
+            let tail = |batch: &str| {
+                #[cfg(feature = "live-tail")]
+                {
+                    if let Some(ref live_tail) = live_tail { live_tail.publish(batch) }
+                }
+                #[cfg(not(feature = "live-tail"))]
+                let _ = batch;
+            };
+
             let send = |mut buf: String, backlog: &mut VecDeque<String>, n_outstanding: usize, in_flight_buffer_bytes: &mut usize| {
                 if n_outstanding >= MAX_OUTSTANDING_HTTP {
                     backlog.push_back(buf);
                     return
                 }
-                let url = url.clone(); // Arc would be faster, but `hyper::Client::post` consumes url
                 let tx = http_tx.clone();
                 let thread_logger = logger.new(o!("thread" => "InfluxWriter:http", "in flight req at spawn time" => n_outstanding)); // re `thread_logger` name: disambiguating for `logger` after thread closure
-                let client = Arc::clone(&client);
-                let creds = Arc::clone(&creds);
+                let sink = Arc::clone(&sink);
+                let counters = Arc::clone(&counters);
+                let dead_letter = dead_letter.clone();
+                let retry = retry.clone();
                 *in_flight_buffer_bytes = *in_flight_buffer_bytes + buf.capacity();
                 debug!(logger, "launching http thread");
                 let thread_res = thread::Builder::new().name(format!("inflx-http{}", n_outstanding)).spawn(move || {
                     let logger = thread_logger;
                     debug!(logger, "preparing to send http request to influx"; "buf.len()" => buf.len());
                     let start = Instant::now();
-                    for n_req in 0..N_HTTP_ATTEMPTS {
-                        let throttle = Duration::from_secs(2) * n_req * n_req;
+                    for n_req in 0..retry.max_attempts.max(1) {
                         if n_req > 0 {
+                            let throttle = retry.delay(n_req);
                             warn!(logger, "InfluxWriter http thread: pausing before next request";
                                   "n_req" => n_req,
                                   "throttle" => %format_args!("{:?}", throttle),
                                   "elapsed" => %format_args!("{:?}", Instant::now() - start));
-                            thread::sleep(throttle); // 0, 2, 8, 16, 32
+                            thread::sleep(throttle);
                         }
                         let sent = Instant::now();
-                        let req = Self::http_req(&client, url.clone(), buf.as_str(), &creds);
-                        let resp = req.send();
+                        let resp = sink.write(buf.as_str());
                         let rcvd = Instant::now();
                         let took = rcvd - sent;
                         let mut n_tx = 0u32;
                         match resp {
-                            Ok(Response { status, .. }) if status == StatusCode::NoContent => {
-                                debug!(logger, "server responded ok: 204 NoContent");
+                            Ok(()) => {
+                                debug!(logger, "batch written ok");
+                                counters.n_batches_ok.fetch_add(1, Ordering::Relaxed);
+                                counters.n_bytes_sent.fetch_add(buf.len() as u64, Ordering::Relaxed);
+                                counters.last_request_nanos.store(dur_nanos(took).max(0) as u64, Ordering::Relaxed);
                                 buf.clear();
                                 let mut resp = Some(Ok(Resp { buf, took }));
                                 loop {
@@ -546,16 +1586,24 @@ impl InfluxWriter {
                                 }
                             }
 
-                            Ok(mut resp) =>  {
-                                let mut server_resp = String::new();
-                                let _ = resp.read_to_string(&mut server_resp); //.unwrap_or(0);
-                                error!(logger, "influx server error (request took {:?})", took;
-                                       "status" => %resp.status,
-                                       "body" => server_resp);
+                            Err(Error::Transport { status: Some(400), message }) => {
+                                // malformed batch: retrying won't help
+                                error!(logger, "batch rejected by server, discarding: {}", message; "buf.len()" => buf.len());
+                                counters.n_request_errors.fetch_add(1, Ordering::Relaxed);
+                                counters.n_batches_rejected.fetch_add(1, Ordering::Relaxed);
+                                if let Some(ref dead_letter) = dead_letter {
+                                    dead_letter.write(RejectedBatch { body: buf.clone(), status: 400, response: message }, &logger);
+                                }
+                                buf.clear();
+                                if let Err(e) = tx.send(Ok(Resp { buf, took })) {
+                                    crit!(logger, "failed to return buffer of rejected batch: {:?}", e; "err" => %e);
+                                }
+                                return
                             }
 
                             Err(e) => {
-                                error!(logger, "http request failed: {:?} (request took {:?})", e, took; "err" => %e);
+                                error!(logger, "write failed: {} (request took {:?})", e, took; "err" => %e);
+                                counters.n_request_errors.fetch_add(1, Ordering::Relaxed);
                             }
                         }
 
@@ -563,6 +1611,7 @@ impl InfluxWriter {
                     let took = Instant::now() - start;
                     warn!(logger, "InfluxWriter http thread: aborting http req, returning buffer";
                         "took" => %format_args!("{:?}", took));
+                    counters.n_batches_failed.fetch_add(1, Ordering::Relaxed);
                     let buflen = buf.len();
                     let n_lines = buf.lines().count();
                     if let Err(e) = tx.send(Err(Resp { buf, took })) {
@@ -576,27 +1625,88 @@ impl InfluxWriter {
                 }
             };
 
-            let next = |prev: usize, m: &OwnedMeasurement, buf: &mut String, loop_time: Instant, last: Instant| -> Result<usize, usize> {
+            // `oversized` receives the serialized line of a measurement that was too
+            // large to be appended to `buf` (see `MAX_BATCH_BYTES`). in that case the
+            // count is returned unchanged, since nothing was added to `buf`.
+            //
+            let next = |prev: usize, m: &OwnedMeasurement, buf: &mut String, loop_time: Instant, last: Instant, oversized: &mut Option<String>| -> Result<usize, usize> {
                 match prev {
-                    0 if N_BUFFER_LINES > 0 => {
-                        serialize_owned(m, buf);
-                        Ok(1)
+                    0 if n_buffer_lines() > 0 => {
+                        match append_line(m, buf, false, MAX_BATCH_BYTES, &serialize_opts) {
+                            None if buf.len() >= flush_bytes => Err(1),
+                            None => Ok(1),
+                            Some(line) => { *oversized = Some(line); Ok(0) }
+                        }
                     }
 
-                    n if n < N_BUFFER_LINES && loop_time - last < MAX_PENDING => {
-                        buf.push_str("\n");
-                        serialize_owned(m, buf);
-                        Ok(n + 1)
+                    n if n < n_buffer_lines() && loop_time - last < max_pending() => {
+                        match append_line(m, buf, true, MAX_BATCH_BYTES, &serialize_opts) {
+                            None if buf.len() >= flush_bytes => Err(n + 1),
+                            None => Ok(n + 1),
+                            Some(line) => { *oversized = Some(line); Ok(n) }
+                        }
                     }
 
                     n => {
-                        buf.push_str("\n");
-                        serialize_owned(m, buf);
-                        Err(n + 1)
+                        match append_line(m, buf, n > 0, MAX_BATCH_BYTES, &serialize_opts) {
+                            None => Err(n + 1),
+                            Some(line) => { *oversized = Some(line); Err(n) }
+                        }
+                    }
+                }
+            };
+
+            if let Some((ref path, _)) = warm_up {
+                match load_gauges(path) {
+                    Ok(lines) if !lines.is_empty() => {
+                        info!(logger, "InfluxWriter: re-sending last known gauge values";
+                            "n" => lines.len(), "path" => %path.display());
+                        let t = now();
+                        let mut body = String::with_capacity(INITIAL_BUFFER_CAPACITY);
+                        for line in lines {
+                            if !body.is_empty() { body.push('\n'); }
+                            body.push_str(&format!("{} {}", line, t));
+                            gauges.insert(series_of_line(&line).to_string(), line);
+                        }
+                        extras += 1; // `body` is a new String entering the system
+                        let n_outstanding = n_out(&spares, &backlog, extras);
+                        send(body, &mut backlog, n_outstanding, &mut in_flight_buffer_bytes);
+                    }
+
+                    Ok(_) => {}
+
+                    Err(e) => {
+                        warn!(logger, "InfluxWriter: failed to load gauges for warm up: {}", e; "path" => %path.display());
+                    }
+                }
+            }
+
+            // removes the oldest batch from the spool, if any
+            let unspool = |spool: &mut Option<spool::Spool>| -> Option<String> {
+                let spool = spool.as_mut()?;
+                match spool.pop()? {
+                    Ok(batch) => {
+                        info!(logger, "InfluxWriter: replaying spooled batch"; "spool.len()" => spool.len());
+                        Some(batch)
+                    }
+
+                    Err(e) => {
+                        error!(logger, "InfluxWriter: failed to read spooled batch, discarding it: {}", e);
+                        None
                     }
                 }
             };
 
+            // batches left over from a previous run: the rest are replayed
+            // one at a time as writes succeed
+            if let Some(batch) = unspool(&mut spool) {
+                extras += 1; // `batch` is a new String entering the system
+                let n_outstanding = n_out(&spares, &backlog, extras);
+                send(batch, &mut backlog, n_outstanding, &mut in_flight_buffer_bytes);
+            }
+            let mut last_replay = Instant::now();
+            let mut writes_ok = false; // whether the latest response was a success
+
             'event: loop {
                 loop_time = Instant::now();
                 active = false;
@@ -612,10 +1722,73 @@ impl InfluxWriter {
                     );
                     last_memory_check = loop_time;
                 }
-                match rx.recv() {
+                if let Some((ref ctl, _)) = admin {
+                    if let Some(rates) = ctl.sampling_since(&mut sampling_version) {
+                        sampling.retain(|key, _| rates.contains_key(key));
+                        for (key, n) in rates {
+                            sampling.entry(key).or_insert((n, 0)).0 = n;
+                        }
+                    }
+                }
+                let rcvd = match admin {
+                    Some((_, ref flush_rx)) => chan::select! {
+                        recv(rx) -> msg => msg,
+
+                        recv(flush_rx) -> _ => {
+                            if count > 0 {
+                                info!(logger, "InfluxWriter: flushing active buffer on request"; "count" => count);
+                                let mut placeholder = spares.pop_front().unwrap_or_else(|| {
+                                    extras += 1;
+                                    String::with_capacity(INITIAL_BUFFER_CAPACITY)
+                                });
+                                mem::swap(&mut buf, &mut placeholder);
+                                tail(&placeholder);
+                                let n_outstanding = n_out(&spares, &backlog, extras);
+                                send(placeholder, &mut backlog, n_outstanding, &mut in_flight_buffer_bytes);
+                                count = 0;
+                                last = loop_time;
+                            }
+                            Err(chan::RecvError)
+                        }
+
+                        // wake periodically so the stats stay current while idle
+                        default(Duration::from_millis(100)) => Err(chan::RecvError),
+                    },
+
+                    // wake periodically while requests are in flight or queued, so
+                    // their responses are handled and the backlog drains, and
+                    // so spooled batches are replayed while idle
+                    None if spool.is_some() || !backlog.is_empty() || n_out(&spares, &backlog, extras) > 0 => rx.recv_timeout(Duration::from_millis(100)).map_err(|_| chan::RecvError),
+                    None => rx.recv(),
+                };
+
+                match rcvd {
+                    Ok(Some(ref meas)) if admin.as_ref().map(|(ctl, _)| !ctl.accepts(&meas.key)).unwrap_or(false) => {
+                        n_rcvd += 1;
+                        counters.n_dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    Ok(Some(ref meas)) if sampled_out(&mut sampling, &meas.key) => {
+                        n_rcvd += 1;
+                    }
+
+                    Ok(Some(ref meas)) if dedup.as_ref().map(|d| d.is_repeat(meas, &serialize_opts, Instant::now())).unwrap_or(false) => {
+                        n_rcvd += 1;
+                    }
+
+                    Ok(Some(_)) if rate_limit.as_mut().map(|bucket| !bucket.take(Instant::now())).unwrap_or(false) => {
+                        n_rcvd += 1;
+                        counters.n_dropped.fetch_add(1, Ordering::Relaxed);
+                        crate::warn_throttled!(logger, Duration::from_secs(10),
+                            "InfluxWriter: rate limit exceeded, dropping measurements");
+                    }
+
                     Ok(Some(mut meas)) => {
                         n_rcvd += 1;
                         active = true;
+                        if let Some(ref mut dedup) = dedup {
+                            dedup.record(&meas, &serialize_opts, Instant::now());
+                        }
 
                         if n_rcvd % INFO_HB_EVERY == 0 {
                             let n_outstanding = n_out(&spares, &backlog, extras);
@@ -646,12 +1819,30 @@ impl InfluxWriter {
                         if meas.timestamp.is_none() { meas.timestamp = Some(now()) }
 
                         if meas.fields.is_empty() {
-                            meas.fields.push(("n", OwnedValue::Integer(1)));
+                            meas.fields.push(("n".into(), OwnedValue::Integer(1)));
+                        }
+
+                        if let Some(ref mut cardinality) = cardinality {
+                            cardinality.observe(&meas, |key, tag, n| {
+                                warn!(logger, "InfluxWriter: tag cardinality limit exceeded";
+                                      "key" => key, "tag" => tag, "n_values" => n);
+                            });
+                        }
+
+                        if let Some((_, ref keys)) = warm_up {
+                            if keys.iter().any(|k| *k == meas.key) {
+                                let mut line = String::new();
+                                serialize_owned_with(&meas, &mut line, &serialize_opts);
+                                if let Some(i) = line.rfind(' ') { line.truncate(i); } // drop timestamp
+                                gauges.insert(series_of_line(&line).to_string(), line);
+                            }
                         }
 
                         //#[cfg(feature = "trace")] { if count % 10 == 0 { trace!(logger, "rcvd new measurement"; "count" => count, "key" => meas.key); } }
 
-                        count = match next(count, &meas, &mut buf, loop_time, last) {
+                        let mut oversized = None;
+
+                        count = match next(count, &meas, &mut buf, loop_time, last, &mut oversized) {
                             Ok(n) => n,
                             Err(_n) => {
                                 let mut count = 0;
@@ -705,29 +1896,52 @@ impl InfluxWriter {
                                 // after swap, buf in next, so want to send next
                                 //
                                 mem::swap(&mut buf, &mut next);
+                                tail(&next);
                                 let n_outstanding = n_out(&spares, &backlog, extras);
                                 send(next, &mut backlog, n_outstanding, &mut in_flight_buffer_bytes);
                                 last = loop_time;
                                 count
                             }
                         };
+
+                        if let Some(line) = oversized {
+                            warn!(logger, "InfluxWriter: measurement exceeds MAX_BATCH_BYTES, sending in its own request";
+                                "key" => %meas.key,
+                                "line.len()" => line.len(),
+                                "MAX_BATCH_BYTES" => MAX_BATCH_BYTES);
+                            extras += 1; // `line` is a new String entering the system
+                            tail(&line);
+                            let n_outstanding = n_out(&spares, &backlog, extras);
+                            send(line, &mut backlog, n_outstanding, &mut in_flight_buffer_bytes);
+                        }
+
+                        if let Some(ref pool) = recycle {
+                            pool.recycle(meas);
+                        }
                     }
 
                     Ok(None) => {
                         let start = Instant::now();
                         let mut hb = Instant::now();
                         warn!(logger, "terminate signal rcvd"; "count" => count);
+                        if let Some((ref path, _)) = warm_up {
+                            match save_gauges(path, gauges.values()) {
+                                Ok(()) => info!(logger, "InfluxWriter: saved gauges for warm up";
+                                    "n" => gauges.len(), "path" => %path.display()),
+                                Err(e) => error!(logger, "InfluxWriter: failed to save gauges for warm up: {}", e;
+                                    "path" => %path.display()),
+                            }
+                        }
                         if buf.len() > 0 {
                             info!(logger, "InfluxWriter: sending remaining buffer to influx on terminate"; "count" => count);
                             let meas = OwnedMeasurement::new("influx_writer").add_field("n", OwnedValue::Integer(1));
-                            let _ = next(N_BUFFER_LINES, &meas, &mut buf, loop_time, last);
+                            let _ = next(n_buffer_lines().max(1), &meas, &mut buf, loop_time, last, &mut None);
                             let n_outstanding = n_out(&spares, &backlog, extras);
                             let mut placeholder = spares.pop_front().unwrap_or_else(String::new);
                             mem::swap(&mut buf, &mut placeholder);
+                            tail(&placeholder);
                             send(placeholder, &mut backlog, n_outstanding, &mut in_flight_buffer_bytes);
                         }
-                        let mut n_ok = 0;
-                        let mut n_err = 0;
                         loop {
                             loop_time = Instant::now();
                             let n_outstanding = n_out(&spares, &backlog, extras);
@@ -750,6 +1964,17 @@ impl InfluxWriter {
                                     "n outstanding" => n_outstanding,
                                     "backlog.len()" => backlog.len(),
                                 );
+                                if let Some(ref mut spool) = spool {
+                                    while let Some(buf) = backlog.pop_front() {
+                                        if let Err(e) = spool.push(&buf) {
+                                            error!(logger, "InfluxWriter: failed to spool batch on exit: {}", e);
+                                            backlog.push_front(buf);
+                                            break
+                                        }
+                                        n_spooled += 1;
+                                    }
+                                    info!(logger, "InfluxWriter: spooled remaining backlog"; "spool.len()" => spool.len());
+                                }
                                 break 'event
                             }
 
@@ -777,10 +2002,11 @@ impl InfluxWriter {
 
                             'rx: loop {
                                 match http_rx.try_recv() {
-                                    Ok(Ok(Resp { buf, .. })) => {
+                                    Ok(Ok(Resp { mut buf, .. })) => {
                                         n_ok += 1;
                                         in_flight_buffer_bytes = in_flight_buffer_bytes.saturating_sub(buf.capacity());
                                         if spares.len() <= INITIAL_BACKLOG {
+                                            buf.shrink_to(MAX_SPARE_CAPACITY);
                                             spares.push_back(buf); // needed so `n_outstanding` count remains accurate
                                         } else {
                                             extras = extras.saturating_sub(1);
@@ -815,26 +2041,36 @@ impl InfluxWriter {
 
                 db_health.refresh(loop_time);
                 let n_outstanding = n_out(&spares, &backlog, extras);
+                counters.n_rcvd.store(n_rcvd, Ordering::Relaxed);
+                counters.n_outstanding.store(n_outstanding, Ordering::Relaxed);
+                counters.backlog_len.store(backlog.len(), Ordering::Relaxed);
                 let healthy = db_health.count == 0 || db_health.mean < Duration::from_secs(200);
                 if (n_outstanding < MAX_OUTSTANDING_HTTP 
                     || loop_time.saturating_duration_since(last_clear) > Duration::from_secs(60)) 
                     && healthy {
 
-                    if let Some(queued) = backlog.pop_front() {
-                        let n_outstanding = n_out(&spares, &backlog, extras);
-                        send(queued, &mut backlog, n_outstanding, &mut in_flight_buffer_bytes);
-                        active = true;
+                    for i in 0..backlog_concurrency {
+                        if i > 0 && n_out(&spares, &backlog, extras) >= MAX_OUTSTANDING_HTTP { break }
+                        match backlog.pop_front() {
+                            Some(queued) => {
+                                let n_outstanding = n_out(&spares, &backlog, extras);
+                                send(queued, &mut backlog, n_outstanding, &mut in_flight_buffer_bytes);
+                                active = true;
+                            }
+                            None => break,
+                        }
                     }
                     last_clear = loop_time;
                 }
 
                 loop {
                     match http_rx.try_recv() {
-                        Ok(Ok(Resp { buf, took })) => {
+                        Ok(Ok(Resp { mut buf, took })) => {
                             db_health.add(loop_time, took);
                             let in_flight_before = in_flight_buffer_bytes.clone();
                             in_flight_buffer_bytes = in_flight_buffer_bytes.saturating_sub(buf.capacity());
                             if spares.len() <= INITIAL_BACKLOG {
+                                buf.shrink_to(MAX_SPARE_CAPACITY);
                                 spares.push_back(buf);
                             } else {
                                 extras = extras.saturating_sub(1);
@@ -847,13 +2083,43 @@ impl InfluxWriter {
                             }
 
                             //spares.push_back(buf);
+
+                            writes_ok = true;
                             active = true;
                         }
 
-                        Ok(Err(Resp { buf, took })) => {
+                        Ok(Err(Resp { mut buf, took })) => {
                             db_health.add(loop_time, took);
                             in_flight_buffer_bytes = in_flight_buffer_bytes.saturating_sub(buf.capacity());
-                            backlog.push_front(buf);
+                            let spooled = match spool {
+                                Some(ref mut spool) => match spool.push(&buf) {
+                                    Ok(()) => {
+                                        warn!(logger, "InfluxWriter: spooled failed batch to disk";
+                                            "buf.len()" => buf.len(), "spool.len()" => spool.len());
+                                        Ok(())
+                                    }
+
+                                    Err(e) => {
+                                        error!(logger, "InfluxWriter: failed to spool batch, requeueing in memory: {}", e);
+                                        Err(format!("writes failing and spool unavailable ({})", e))
+                                    }
+                                }
+
+                                None => Err("writes failing".to_string()),
+                            };
+                            writes_ok = false;
+                            if let Err(why) = spooled {
+                                if let Some(ref fallback) = fallback {
+                                    fallback.warning(&format!("{}, requeueing batch in memory, backlog.len() = {}", why, backlog.len() + 1));
+                                }
+                                backlog.push_front(buf);
+                            } else if spares.len() <= INITIAL_BACKLOG {
+                                buf.clear();
+                                buf.shrink_to(MAX_SPARE_CAPACITY);
+                                spares.push_back(buf);
+                            } else {
+                                extras = extras.saturating_sub(1);
+                            }
                             active = true;
                         }
 
@@ -870,66 +2136,1773 @@ impl InfluxWriter {
                     }
                 }
 
+                // writes are succeeding: replay the next spooled batch, paced by
+                // `spool_replay_interval`, and leaving at least half of the
+                // outstanding requests for live batches. otherwise, if the
+                // writer is idle, replay one now and then as a probe, since
+                // there may be no live batches to find out the server is back
+                let n_outstanding = n_out(&spares, &backlog, extras);
+                let since_replay = loop_time.saturating_duration_since(last_replay);
+                let replay = if writes_ok {
+                    n_outstanding < MAX_OUTSTANDING_HTTP / 2 && since_replay >= spool_replay_interval
+                } else {
+                    n_outstanding == 0 && backlog.is_empty() && since_replay >= spool_replay_interval.max(SPOOL_PROBE_INTERVAL)
+                };
+                if replay {
+                    if let Some(batch) = unspool(&mut spool) {
+                        extras += 1; // `batch` is a new String entering the system
+                        let n_outstanding = n_out(&spares, &backlog, extras);
+                        send(batch, &mut backlog, n_outstanding, &mut in_flight_buffer_bytes);
+                        last_replay = loop_time;
+                        active = true;
+                    }
+                }
+
                 if !active {
                     thread::sleep(Duration::new(0, 1))
                 }
             }
+            drop(rx); // so sends from any remaining handles fail from here on
+            if let Some(ref fallback) = fallback {
+                if !backlog.is_empty() {
+                    fallback.warning(&format!("exiting with {} unsent batches", backlog.len()));
+                    backlog.iter().for_each(|buf| fallback.batch(buf));
+                }
+            }
+            let _ = done_tx.send(FlushStats {
+                n_rcvd,
+                n_batches_ok: n_ok,
+                n_batches_err: n_err,
+                n_batches_spooled: n_spooled,
+                n_batches_unsent: backlog.len() + n_out(&spares, &backlog, extras),
+            });
             thread::sleep(Duration::from_millis(10));
-        }).unwrap();
+        }).map_err(Error::Spawn)?;
 
-        InfluxWriter {
+        if let Some(interval) = self_monitoring {
+            Self::spawn_self_monitoring(interval, tx.clone(), Arc::clone(&stats), audit.clone())?;
+        }
+
+        if let Some(build) = process_start {
+            let m = OwnedMeasurement::new("process_start")
+                .add_tag("version", build.version)
+                .add_tag("git_hash", build.git_sha)
+                .add_tag("hostname", hostname())
+                .add_field("rustc_version", OwnedValue::String(build.rustc_version.to_string()))
+                .add_field("build_time", OwnedValue::String(build.build_time.to_string()))
+                .add_field("pid", OwnedValue::Integer(i64::from(std::process::id())))
+                .add_field("sink", OwnedValue::String(sink_kind.to_string()))
+                .add_field("buffer_size", OwnedValue::Integer(buffer_size as i64))
+                .add_field("queue_capacity", OwnedValue::Integer(queue_capacity as i64))
+                .add_field("https", OwnedValue::Boolean(https));
+            let _ = tx.send(Some(m));
+        }
+
+        Ok(InfluxWriter {
             host: host.to_string(),
             db: db.to_string(),
             tx,
-            thread: Some(Arc::new(thread))
-        }
+            thread: Some(Arc::new(thread)),
+            tag_thread,
+            tag_correlation_id,
+            high_water_mark: high_water_mark.unwrap_or(usize::MAX),
+            done: Some(done_rx),
+            stats,
+            stderr_fallback,
+            pool,
+            audit,
+            config,
+        })
+    }
+
+    /// Spawns a thread that sends a measurement of the writer's counters
+    /// (and one of enqueue latency per key, if audited) every `interval`,
+    /// until the writer thread stops receiving.
+    ///
+    fn spawn_self_monitoring(interval: Duration, tx: Sender<Option<OwnedMeasurement>>, stats: Arc<Counters>, audit: Option<Arc<EnqueueAudit>>) -> Result<(), Error> {
+        thread::Builder::new().name("inflx-monitor".to_string()).spawn(move || {
+            let mut prev = stats.snapshot();
+            loop {
+                thread::sleep(interval);
+                let cur = stats.snapshot();
+                let n_batches = cur.n_batches_ok - prev.n_batches_ok;
+                let mean_batch_bytes = if n_batches > 0 { (cur.n_bytes_sent - prev.n_bytes_sent) / n_batches as u64 } else { 0 };
+                let m = OwnedMeasurement::new("influx_writer")
+                    .add_field("queue_depth", OwnedValue::Integer(tx.len() as i64))
+                    .add_field("backlog_len", OwnedValue::Integer(cur.backlog_len as i64))
+                    .add_field("n_outstanding", OwnedValue::Integer(cur.n_outstanding as i64))
+                    .add_field("n_rcvd", OwnedValue::Integer(cur.n_rcvd as i64))
+                    .add_field("n_dropped", OwnedValue::Integer(cur.n_dropped as i64))
+                    .add_field("n_batches_ok", OwnedValue::Integer(cur.n_batches_ok as i64))
+                    .add_field("n_batches_failed", OwnedValue::Integer(cur.n_batches_failed as i64))
+                    .add_field("n_request_errors", OwnedValue::Integer(cur.n_request_errors as i64))
+                    .add_field("n_bytes_sent", OwnedValue::Integer(cur.n_bytes_sent as i64))
+                    .add_field("mean_batch_bytes", OwnedValue::Integer(mean_batch_bytes as i64))
+                    .add_field("last_request_ms", OwnedValue::Float(cur.last_request_latency.as_secs_f64() * 1000.0));
+                let enqueue = audit.as_ref().map(|audit| audit.take()).unwrap_or_default();
+                let enqueue = enqueue.into_iter().map(|(key, samples)| {
+                    // without "string-tags", tag values must be static
+                    #[cfg(not(feature = "string-tags"))]
+                    let key = match key { Cow::Borrowed(key) => key, Cow::Owned(_) => "(dynamic)" };
+                    OwnedMeasurement::new("influx_writer_enqueue")
+                        .add_tag("key", key)
+                        .add_field("n_samples", OwnedValue::Integer(samples.n as i64))
+                        .add_field("mean_ns", OwnedValue::Integer((samples.total_nanos / samples.n) as i64))
+                        .add_field("max_ns", OwnedValue::Integer(samples.max_nanos as i64))
+                });
+                for m in std::iter::once(m).chain(enqueue) {
+                    match tx.try_send(Some(m)) {
+                        Ok(()) | Err(TrySendError::Full(_)) => {}
+                        Err(TrySendError::Disconnected(_)) => return,
+                    }
+                }
+                prev = cur;
+            }
+        }).map_err(Error::Spawn)?;
+        Ok(())
     }
 }
 
-impl Drop for InfluxWriter {
-    fn drop(&mut self) {
-        if let Some(arc) = self.thread.take() {
-            if let Ok(thread) = Arc::try_unwrap(arc) {
-                let _ = self.tx.send(None);
-                let _ = thread.join();
+/// Builds a `Client` that keeps connections to the server alive between
+/// requests (up to `HTTP_POOL_SIZE` idle connections, one per concurrent
+/// request), so flushes don't pay for tcp (and tls) setup each time.
+///
+/// With `resolving`, idle connections to addresses the server's hostname
+/// no longer resolves to are closed rather than reused.
+///
+pub(crate) fn pooled_client<C, S>(connector: C, resolving: Option<&resolve::Resolving>) -> Client
+    where C: hyper::net::NetworkConnector<Stream = S> + Send + Sync + 'static,
+          S: hyper::net::NetworkStream + Send
+{
+    let mut pool = Pool::with_connector(PoolConfig { max_idle: HTTP_POOL_SIZE }, connector);
+    pool.set_idle_timeout(Some(HTTP_IDLE_TIMEOUT));
+    if let Some(resolving) = resolving.cloned() {
+        pool.set_stale_check(move |mut check| {
+            match hyper::net::NetworkStream::peer_addr(check.stream()) {
+                Ok(addr) if !resolving.is_current(addr.ip()) => check.stale(),
+                _ => check.fresh(),
             }
+        });
+    }
+    Client::with_connector(pool)
+}
+
+#[cfg(feature = "string-tags")]
+fn tag_current_thread(m: OwnedMeasurement) -> OwnedMeasurement {
+    if m.get_tag("thread").is_some() { return m }
+    match thread::current().name() {
+        Some(name) => m.add_tag("thread", name),
+        None => m,
+    }
+}
+
+#[cfg(feature = "string-tags")]
+fn tag_correlation_id(m: OwnedMeasurement) -> OwnedMeasurement {
+    if m.get_tag("correlation_id").is_some() { return m }
+    CORRELATION_ID.with(|id| match *id.borrow() {
+        Some(ref id) => m.add_tag("correlation_id", id.as_str()),
+        None => m,
+    })
+}
+
+thread_local! {
+    static CORRELATION_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Sets the correlation id of the current thread (e.g. an order or request
+/// id) until the returned guard is dropped, at which point the previous id
+/// (if any) is restored.
+///
+/// Writers built with `InfluxWriterBuilder::tag_correlation_id(true)` tag
+/// measurements sent from this thread with the id (as "correlation_id"),
+/// so a single order's metrics can be grouped, or joined with log lines
+/// that include `current_correlation_id()`. To carry the id to another
+/// thread, call `set_correlation_id` there as well.
+///
+/// # Examples
+///
+/// ```
+/// use influx_writer::{set_correlation_id, current_correlation_id};
+///
+/// assert_eq!(current_correlation_id(), None);
+/// {
+///     let _guard = set_correlation_id("order-123");
+///     assert_eq!(current_correlation_id().as_deref(), Some("order-123"));
+/// }
+/// assert_eq!(current_correlation_id(), None);
+/// ```
+///
+pub fn set_correlation_id(id: &str) -> CorrelationGuard {
+    let prev = CORRELATION_ID.with(|cur| cur.borrow_mut().replace(id.to_string()));
+    CorrelationGuard { prev }
+}
+
+/// Returns the correlation id of the current thread (see `set_correlation_id`).
+///
+pub fn current_correlation_id() -> Option<String> {
+    CORRELATION_ID.with(|id| id.borrow().clone())
+}
+
+/// Restores the previous correlation id when dropped (see `set_correlation_id`).
+///
+#[must_use]
+#[derive(Debug)]
+pub struct CorrelationGuard {
+    prev: Option<String>,
+}
+
+impl Drop for CorrelationGuard {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CORRELATION_ID.with(|id| *id.borrow_mut() = prev);
+    }
+}
+
+/// Controls how a failed write of a batch is retried.
+///
+/// The delay before each retry doubles, starting from `initial_delay`
+/// and capped at `max_delay`. Once `max_attempts` have failed, the batch
+/// is returned to the writer thread, which queues it to be sent again
+/// later (i.e. it is not dropped).
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// total number of attempts, including the first (minimum one)
+    pub max_attempts: u32,
+    /// delay before the first retry
+    pub initial_delay: Duration,
+    /// upper bound on the delay between attempts
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 15,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
         }
     }
 }
 
-/// This removes offending things rather than escaping them.
+impl RetryPolicy {
+    /// Delay before attempt number `n_req` (zero-based, so the first retry
+    /// is `n_req == 1`).
+    ///
+    pub fn delay(&self, n_req: u32) -> Duration {
+        if n_req == 0 { return Duration::from_secs(0) }
+        let factor = 1u32.checked_shl(n_req - 1).unwrap_or(u32::MAX);
+        self.initial_delay.checked_mul(factor).unwrap_or(self.max_delay).min(self.max_delay)
+    }
+}
+
+/// Whether a measurement with `key` should be skipped, per the sampling
+/// ratios set with `InfluxWriterBuilder::sample` (the first of every `n`
+/// is kept).
 ///
-fn escape_tag(s: &str) -> String {
-    s.replace(" ", "")
-     .replace(",", "")
-     .replace("\"", "")
+fn sampled_out(sampling: &mut HashMap<String, (u32, u64)>, key: &str) -> bool {
+    match sampling.get_mut(key) {
+        Some((n, seen)) => {
+            let i = *seen % u64::from((*n).max(1));
+            *seen += 1;
+            i != 0
+        }
+        None => false,
+    }
 }
 
-fn escape(s: &str) -> String {
-    s.replace(" ", "\\ ")
-     .replace(",", "\\,")
+/// Suppresses measurements whose value is unchanged since the last one
+/// written for the same series (see `InfluxWriterBuilder::dedup_gauges`).
+///
+#[derive(Debug)]
+struct Dedup {
+    keys: Vec<String>,
+    keepalive: Duration,
+    /// series -> (serialized fields, when last written)
+    last: HashMap<String, (String, Instant)>,
 }
 
-fn as_string(s: &str) -> String {
-    // the second replace removes double escapes
-    //
-    format!("\"{}\"", s.replace("\"", "\\\"")
-                       .replace(r#"\\""#, r#"\""#))
+impl Dedup {
+    fn new(keys: Vec<String>, keepalive: Duration) -> Self {
+        Dedup { keys, keepalive, last: HashMap::new() }
+    }
+
+    /// `m` serialized without its timestamp, and the length of its series
+    /// (key and tags), if its key is deduplicated.
+    ///
+    fn line(&self, m: &OwnedMeasurement, opts: &SerializeOptions) -> Option<(String, usize)> {
+        if !self.keys.iter().any(|k| *k == m.key) { return None }
+        let mut line = String::new();
+        serialize_owned_with(m, &mut line, opts);
+        if m.timestamp.is_some() {
+            if let Some(i) = line.rfind(' ') { line.truncate(i); } // drop timestamp
+        }
+        let n = series_of_line(&line).len();
+        Some((line, n))
+    }
+
+    /// Whether `m` repeats the last value written for its series within
+    /// `keepalive` (in which case it should be skipped).
+    ///
+    fn is_repeat(&self, m: &OwnedMeasurement, opts: &SerializeOptions, now: Instant) -> bool {
+        match self.line(m, opts) {
+            Some((line, n)) => match self.last.get(&line[..n]) {
+                Some((prev, at)) => *prev == line[n..] && now.saturating_duration_since(*at) < self.keepalive,
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Records `m` as the last value written for its series. Only called
+    /// once `m` is accepted, so a value dropped later (e.g. by the rate
+    /// limit) doesn't suppress the next one.
+    ///
+    fn record(&mut self, m: &OwnedMeasurement, opts: &SerializeOptions, now: Instant) {
+        if let Some((mut line, n)) = self.line(m, opts) {
+            let fields = line.split_off(n);
+            self.last.insert(line, (fields, now));
+        }
+    }
 }
 
-#[test]
-fn it_checks_as_string_does_not_double_escape() {
-    let raw = "this is \\\"an escaped string\\\" so it's problematic";
+/// Called with (measurement key, tag key, number of distinct values) when
+/// a tag exceeds the limit set with `InfluxWriterBuilder::cardinality_warning`.
+///
+pub type CardinalityHook = Arc<dyn Fn(&str, &str, usize) + Send + Sync>;
+
+/// Counts the distinct values of each tag, by measurement key, and reports
+/// the first time one exceeds `limit` (see
+/// `InfluxWriterBuilder::cardinality_warning`).
+///
+struct Cardinality {
+    limit: usize,
+    hook: CardinalityHook,
+    /// key -> tag -> hashes of the values seen, or `None` once over the limit
+    seen: HashMap<Key, HashMap<Key, Option<HashSet<u64>>>>,
+}
+
+impl Cardinality {
+    fn new(limit: usize, hook: CardinalityHook) -> Self {
+        Cardinality { limit, hook, seen: HashMap::new() }
+    }
+
+    /// Records the tag values of `m`, calling the hook and `on_exceeded`
+    /// for each tag that just went over the limit.
+    ///
+    fn observe<F: FnMut(&str, &str, usize)>(&mut self, m: &OwnedMeasurement, mut on_exceeded: F) {
+        if !self.seen.contains_key(&m.key[..]) { self.seen.insert(m.key.clone(), HashMap::new()); }
+        let by_tag = self.seen.get_mut(&m.key[..]).unwrap();
+        for (tag, value) in m.tags.iter() {
+            if !by_tag.contains_key(&tag[..]) { by_tag.insert(tag.clone(), Some(HashSet::new())); }
+            let entry = by_tag.get_mut(&tag[..]).unwrap();
+            let n = match entry {
+                Some(values) => {
+                    let mut hasher = DefaultHasher::new();
+                    value.hash(&mut hasher);
+                    values.insert(hasher.finish());
+                    values.len()
+                }
+                None => continue,
+            };
+            if n > self.limit {
+                *entry = None; // stop counting, and free the hashes
+                (self.hook)(&m.key, tag, n);
+                on_exceeded(&m.key, tag, n);
+            }
+        }
+    }
+}
+
+/// Allows up to `per_sec` measurements per second, with bursts of up to
+/// one second's worth.
+///
+#[derive(Debug)]
+struct TokenBucket {
+    per_sec: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_sec: f64, now: Instant) -> Self {
+        TokenBucket { per_sec, tokens: per_sec.max(1.0), last: now }
+    }
+
+    /// Takes a token, if one is available at `now`.
+    ///
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_sec).min(self.per_sec.max(1.0));
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Counters shared by the writer thread and its handles (see
+/// `InfluxWriter::stats`).
+///
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    n_rcvd: AtomicUsize,
+    n_dropped: AtomicUsize,
+    n_batches_ok: AtomicUsize,
+    n_batches_failed: AtomicUsize,
+    n_batches_rejected: AtomicUsize,
+    n_request_errors: AtomicUsize,
+    n_bytes_sent: AtomicU64,
+    last_request_nanos: AtomicU64,
+    n_outstanding: AtomicUsize,
+    backlog_len: AtomicUsize,
+    /// shared with the `TeeSink`, if any
+    n_tee_errors: Arc<AtomicUsize>,
+}
+
+impl Counters {
+    pub(crate) fn snapshot(&self) -> WriterStats {
+        WriterStats {
+            n_rcvd: self.n_rcvd.load(Ordering::Relaxed),
+            n_dropped: self.n_dropped.load(Ordering::Relaxed),
+            n_batches_ok: self.n_batches_ok.load(Ordering::Relaxed),
+            n_batches_failed: self.n_batches_failed.load(Ordering::Relaxed),
+            n_batches_rejected: self.n_batches_rejected.load(Ordering::Relaxed),
+            n_request_errors: self.n_request_errors.load(Ordering::Relaxed),
+            n_bytes_sent: self.n_bytes_sent.load(Ordering::Relaxed),
+            last_request_latency: Duration::from_nanos(self.last_request_nanos.load(Ordering::Relaxed)),
+            n_outstanding: self.n_outstanding.load(Ordering::Relaxed),
+            backlog_len: self.backlog_len.load(Ordering::Relaxed),
+            n_tee_errors: self.n_tee_errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counters of a writer's activity, returned by `InfluxWriter::stats`.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WriterStats {
+    /// measurements received by the writer thread
+    pub n_rcvd: usize,
+    /// measurements discarded because the writer was paused or their key
+    /// was disabled (see `InfluxWriterBuilder::admin`), or shed at the
+    /// high water mark (see `InfluxWriter::should_shed`), or over the rate
+    /// limit (see `InfluxWriterBuilder::rate_limit`)
+    pub n_dropped: usize,
+    /// batches written successfully
+    pub n_batches_ok: usize,
+    /// batches that failed every attempt (per the `RetryPolicy`) and were
+    /// requeued or spooled
+    pub n_batches_failed: usize,
+    /// batches rejected by the server as malformed (status 400), which are
+    /// not retried (see `InfluxWriterBuilder::dead_letter_file`)
+    pub n_batches_rejected: usize,
+    /// failed requests, counting each retry
+    pub n_request_errors: usize,
+    /// bytes of line protocol written successfully
+    pub n_bytes_sent: u64,
+    /// duration of the most recent successful request
+    pub last_request_latency: Duration,
+    /// batches currently being sent
+    pub n_outstanding: usize,
+    /// batches waiting to be sent (e.g. during an outage)
+    pub backlog_len: usize,
+    /// failed writes to the sinks added with `InfluxWriterBuilder::tee`,
+    /// which are not retried
+    pub n_tee_errors: usize,
+}
+
+/// Returned by `InfluxWriter::close`.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlushStats {
+    /// measurements received by the writer thread over its lifetime
+    pub n_rcvd: usize,
+    /// batches sent successfully during shutdown
+    pub n_batches_ok: usize,
+    /// failed attempts to send batches during shutdown
+    pub n_batches_err: usize,
+    /// batches written to the spool (see `InfluxWriterBuilder::spool_dir`)
+    /// rather than sent, because the deadline passed
+    pub n_batches_spooled: usize,
+    /// batches that were not sent (or spooled) when the thread exited
+    pub n_batches_unsent: usize,
+}
+
+/// Configures optional settings of an `InfluxWriter` prior to starting
+/// its thread. Created by `InfluxWriter::builder`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use influx_writer::InfluxWriter;
+///
+/// let influx = InfluxWriter::builder("localhost", "test")
+///     .tag_thread(true)
+///     .build();
+/// ```
+///
+#[derive(Clone)]
+pub struct InfluxWriterBuilder {
+    host: String,
+    db: String,
+    creds: Option<Credentials>,
+    logger: Option<Logger>,
+    tag_thread: bool,
+    tag_correlation_id: bool,
+    serialize_opts: SerializeOptions,
+    buffer_size: usize,
+    flush_interval: Duration,
+    https: bool,
+    ca_cert: Option<PathBuf>,
+    warm_up: Option<(PathBuf, Vec<String>)>,
+    sink: SinkConfig,
+    hyper_client: bool,
+    retry: RetryPolicy,
+    live_tail: Option<(String, usize)>,
+    admin: Option<String>,
+    admin_token: Option<String>,
+    spool_dir: Option<PathBuf>,
+    spool_compression: Option<FileCompression>,
+    encryption_key: Option<EncryptionKey>,
+    queue_capacity: usize,
+    self_monitoring: Option<Duration>,
+    dead_letter: Option<DeadLetter>,
+    gzip: bool,
+    /// key -> (db, retention policy)
+    routes: HashMap<String, (String, Option<String>)>,
+    retention_policy: Option<String>,
+    flush_bytes: Option<usize>,
+    high_water_mark: Option<usize>,
+    rate_limit: Option<f64>,
+    dedup: Option<(Vec<String>, Duration)>,
+    max_request_bytes: Option<(usize, usize)>,
+    sampling: HashMap<String, u32>,
+    spool_replay_interval: Duration,
+    process_start: Option<BuildInfo>,
+    standard_tags: bool,
+    stderr_fallback: Option<f64>,
+    check_on_start: bool,
+    create_database: bool,
+    measurement_pool: Option<usize>,
+    tee: Vec<Arc<dyn Sink>>,
+    audit_enqueue: Option<u32>,
+    cardinality_warning: Option<(usize, CardinalityHook)>,
+    run_id: Option<Uuid>,
+    watch_config: Option<(PathBuf, Duration)>,
+    resolver: Option<Arc<dyn Resolver>>,
+    re_resolve_interval: Option<Duration>,
+}
+
+#[derive(Clone)]
+enum SinkConfig {
+    Http,
+    LoadBalanced { hosts: Vec<String>, balance: Balance },
+    Udp { addr: String, max_datagram_size: usize },
+    Statsd { addr: String, max_datagram_size: usize },
+    Graphite { addr: String, template: String },
+    File(PathBuf),
+    #[cfg(feature = "kafka")]
+    Kafka { brokers: Vec<String>, topic: String },
+    #[cfg(feature = "mqtt")]
+    Mqtt { host: String, port: u16, topic: sink::MqttTopic },
+    Custom(Arc<dyn Sink>),
+    #[cfg(feature = "reqwest")]
+    Reqwest(reqwest::blocking::Client),
+}
+
+impl SinkConfig {
+    fn kind(&self) -> &'static str {
+        match self {
+            SinkConfig::Http => "http",
+            SinkConfig::LoadBalanced { .. } => "load_balanced",
+            SinkConfig::Udp { .. } => "udp",
+            SinkConfig::Statsd { .. } => "statsd",
+            SinkConfig::Graphite { .. } => "graphite",
+            SinkConfig::File(_) => "file",
+            #[cfg(feature = "kafka")]
+            SinkConfig::Kafka { .. } => "kafka",
+            #[cfg(feature = "mqtt")]
+            SinkConfig::Mqtt { .. } => "mqtt",
+            SinkConfig::Custom(_) => "custom",
+            #[cfg(feature = "reqwest")]
+            SinkConfig::Reqwest(_) => "reqwest",
+        }
+    }
+}
+
+impl fmt::Debug for SinkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SinkConfig::Http => f.write_str("Http"),
+            SinkConfig::LoadBalanced { hosts, balance } => {
+                f.debug_struct("LoadBalanced").field("hosts", hosts).field("balance", balance).finish()
+            }
+            SinkConfig::Udp { addr, max_datagram_size } => {
+                f.debug_struct("Udp").field("addr", addr).field("max_datagram_size", max_datagram_size).finish()
+            }
+            SinkConfig::Statsd { addr, max_datagram_size } => {
+                f.debug_struct("Statsd").field("addr", addr).field("max_datagram_size", max_datagram_size).finish()
+            }
+            SinkConfig::Graphite { addr, template } => {
+                f.debug_struct("Graphite").field("addr", addr).field("template", template).finish()
+            }
+            SinkConfig::File(path) => f.debug_tuple("File").field(path).finish(),
+            #[cfg(feature = "kafka")]
+            SinkConfig::Kafka { brokers, topic } => {
+                f.debug_struct("Kafka").field("brokers", brokers).field("topic", topic).finish()
+            }
+            #[cfg(feature = "mqtt")]
+            SinkConfig::Mqtt { host, port, topic } => {
+                f.debug_struct("Mqtt").field("host", host).field("port", port).field("topic", topic).finish()
+            }
+            SinkConfig::Custom(_) => f.write_str("Custom"),
+            #[cfg(feature = "reqwest")]
+            SinkConfig::Reqwest(_) => f.write_str("Reqwest"),
+        }
+    }
+}
+
+/// Omits the password, so a builder can be logged safely.
+///
+impl fmt::Debug for InfluxWriterBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InfluxWriterBuilder")
+            .field("host", &self.host)
+            .field("db", &self.db)
+            .field("username", &self.creds.as_ref().map(|c| c.0.username.as_str()))
+            .field("tag_thread", &self.tag_thread)
+            .field("tag_correlation_id", &self.tag_correlation_id)
+            .field("serialize_opts", &self.serialize_opts)
+            .field("buffer_size", &self.buffer_size)
+            .field("flush_interval", &self.flush_interval)
+            .field("https", &self.https)
+            .field("ca_cert", &self.ca_cert)
+            .field("warm_up", &self.warm_up)
+            .field("sink", &self.sink)
+            .field("hyper_client", &self.hyper_client)
+            .field("retry", &self.retry)
+            .field("live_tail", &self.live_tail)
+            .field("admin", &self.admin)
+            .field("admin_token", &self.admin_token.as_ref().map(|_| ".."))
+            .field("spool_dir", &self.spool_dir)
+            .field("spool_compression", &self.spool_compression)
+            .field("encryption_key", &self.encryption_key)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("self_monitoring", &self.self_monitoring)
+            .field("dead_letter", &self.dead_letter)
+            .field("gzip", &self.gzip)
+            .field("routes", &self.routes)
+            .field("retention_policy", &self.retention_policy)
+            .field("flush_bytes", &self.flush_bytes)
+            .field("high_water_mark", &self.high_water_mark)
+            .field("rate_limit", &self.rate_limit)
+            .field("dedup", &self.dedup)
+            .field("max_request_bytes", &self.max_request_bytes)
+            .field("sampling", &self.sampling)
+            .field("spool_replay_interval", &self.spool_replay_interval)
+            .field("process_start", &self.process_start)
+            .field("standard_tags", &self.standard_tags)
+            .field("stderr_fallback", &self.stderr_fallback)
+            .field("check_on_start", &self.check_on_start)
+            .field("create_database", &self.create_database)
+            .field("measurement_pool", &self.measurement_pool)
+            .field("tee", &self.tee.len())
+            .field("audit_enqueue", &self.audit_enqueue)
+            .field("cardinality_warning", &self.cardinality_warning.as_ref().map(|(limit, _)| limit))
+            .field("run_id", &self.run_id)
+            .field("watch_config", &self.watch_config)
+            .field("resolver", &self.resolver.is_some())
+            .field("re_resolve_interval", &self.re_resolve_interval)
+            .finish()
+    }
+}
+
+impl InfluxWriterBuilder {
+    pub fn new(host: &str, db: &str) -> Self {
+        InfluxWriterBuilder {
+            host: host.to_string(),
+            db: db.to_string(),
+            creds: None,
+            logger: None,
+            tag_thread: false,
+            tag_correlation_id: false,
+            serialize_opts: SerializeOptions::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            flush_interval: MAX_PENDING,
+            https: false,
+            ca_cert: None,
+            warm_up: None,
+            sink: SinkConfig::Http,
+            hyper_client: false,
+            retry: RetryPolicy::default(),
+            live_tail: None,
+            admin: None,
+            admin_token: None,
+            spool_dir: None,
+            spool_compression: None,
+            encryption_key: None,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            self_monitoring: None,
+            dead_letter: None,
+            gzip: false,
+            routes: HashMap::new(),
+            retention_policy: None,
+            flush_bytes: None,
+            high_water_mark: None,
+            rate_limit: None,
+            dedup: None,
+            max_request_bytes: None,
+            sampling: HashMap::new(),
+            spool_replay_interval: Duration::from_secs(0),
+            process_start: None,
+            standard_tags: false,
+            stderr_fallback: None,
+            check_on_start: false,
+            create_database: false,
+            measurement_pool: None,
+            tee: Vec::new(),
+            audit_enqueue: None,
+            cardinality_warning: None,
+            run_id: None,
+            watch_config: None,
+            resolver: None,
+            re_resolve_interval: None,
+        }
+    }
+
+    /// The writer thread logs to a child of `logger` (default: discards all
+    /// log output).
+    ///
+    pub fn logger(mut self, logger: &Logger) -> Self {
+        self.logger = Some(logger.clone());
+        self
+    }
+
+    /// Credentials sent with every request (see `InfluxWriter::get_credentials`).
+    ///
+    pub fn credentials(mut self, creds: Credentials) -> Self {
+        self.creds = Some(creds);
+        self
+    }
+
+    /// Sends `username` and `password` with every request, using http
+    /// basic auth (an `Authorization` header).
+    ///
+    pub fn basic_auth(self, username: &str, password: &str) -> Self {
+        self.credentials(InfluxWriter::get_credentials(username.to_string(), Some(password.to_string())))
+    }
+
+    /// Tag every measurement sent through `InfluxWriter::send` with the
+    /// name of the sending thread (as "thread"). Only has an effect when
+    /// the "string-tags" feature is enabled.
+    ///
+    pub fn tag_thread(mut self, yes: bool) -> Self {
+        self.tag_thread = yes;
+        self
+    }
+
+    /// Tag every measurement sent through `InfluxWriter::send` with the
+    /// sending thread's correlation id (as "correlation_id"), if one is set
+    /// (see `set_correlation_id`). Only has an effect when the "string-tags"
+    /// feature is enabled.
+    ///
+    /// Off by default: a tag with many distinct values (such as a per-order
+    /// id) increases series cardinality on the server.
+    ///
+    pub fn tag_correlation_id(mut self, yes: bool) -> Self {
+        self.tag_correlation_id = yes;
+        self
+    }
+
+    /// Number of measurements batched into each request (default:
+    /// `DEFAULT_BUFFER_SIZE`, capped at `INFLUX_WRITER_MAX_BUFFER`). A
+    /// partial batch is still sent once it has been pending for
+    /// `flush_interval`. `0` sends every measurement in its own request.
+    ///
+    pub fn buffer_size(mut self, n: usize) -> Self {
+        self.buffer_size = n;
+        self
+    }
+
+    /// How long a partial batch may wait before it is sent (default: 3
+    /// seconds). Can be changed at runtime with `watch_config`.
+    ///
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Send requests to an `https://` url (default: `http://`, unless the
+    /// host is an https url). Posting with reqwest (the default) uses its
+    /// tls; posting with hyper (see `hyper_client`), and the requests of
+    /// `check_on_start` and `create_database`, require the "tls" feature,
+    /// without which `try_build` returns an error.
+    ///
+    pub fn https(mut self, yes: bool) -> Self {
+        self.https = yes;
+        self
+    }
+
+    /// Trust the CA certificate (pem or der) at `path` for https requests,
+    /// in addition to the system's root certificates. Useful for servers
+    /// with self-signed certificates.
+    ///
+    pub fn ca_cert<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.ca_cert = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Resolve the server's hostname with `resolver`, caching its addresses
+    /// for the TTL it reports (or `re_resolve_interval`) and re-resolving
+    /// them when they expire. Kept-alive connections to addresses that
+    /// drop out are closed, so a writer pointed at a DNS-load-balanced or
+    /// failover endpoint follows it without a restart. If a lookup fails,
+    /// the previous addresses are used until the next one.
+    ///
+    /// Applies to the http sinks, which then post with hyper (see
+    /// `hyper_client`), whose connector does the resolving.
+    ///
+    pub fn resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// How long the server's addresses are cached when the resolver doesn't
+    /// report a TTL (default: `DEFAULT_RE_RESOLVE_INTERVAL`). Without a
+    /// `resolver`, enables re-resolution with the system resolver (see
+    /// `SystemResolver`), which never reports one.
+    ///
+    pub fn re_resolve_interval(mut self, interval: Duration) -> Self {
+        self.re_resolve_interval = Some(interval);
+        self
+    }
+
+    /// Keep the latest value of each series of the measurements named in
+    /// `keys`, save them to `path` on shutdown, and re-send them (with the
+    /// current time) when a writer is next started with the same `path`.
+    ///
+    /// Meant for slowly-changing gauges, so dashboards don't show gaps
+    /// after a restart while waiting for the next natural update.
+    ///
+    pub fn warm_up_gauges<P: AsRef<Path>>(mut self, path: P, keys: &[&str]) -> Self {
+        let keys = keys.iter().map(|k| k.to_string()).collect();
+        self.warm_up = Some((path.as_ref().to_path_buf(), keys));
+        self
+    }
+
+    /// Write only one in every `n` measurements with `key` (the rest are
+    /// discarded by the writer thread before being serialized), to
+    /// downsample extremely chatty measurements. Other keys are unaffected.
+    ///
+    /// Sampled-out measurements are not counted in `WriterStats::n_dropped`.
+    ///
+    pub fn sample(mut self, key: &str, n: u32) -> Self {
+        self.sampling.insert(key.to_string(), n);
+        self
+    }
+
+    /// Skip measurements named in `keys` whose tags and fields are the same
+    /// as the last one written for that series, unless `keepalive` has
+    /// passed since then. Meant for slowly-changing gauges that are sampled
+    /// often: only changes (plus a periodic repeat, so the series doesn't
+    /// look dead) are stored.
+    ///
+    /// Skipped measurements are not counted in `WriterStats::n_dropped`.
+    ///
+    pub fn dedup_gauges(mut self, keys: &[&str], keepalive: Duration) -> Self {
+        let keys = keys.iter().map(|k| k.to_string()).collect();
+        self.dedup = Some((keys, keepalive));
+        self
+    }
+
+    /// Send batches over udp to the influxdb udp listener at `addr`
+    /// (`host:port`) instead of over http, split into datagrams of at most
+    /// `max_datagram_size` bytes. See `UdpSink`.
+    ///
+    pub fn udp(mut self, addr: &str, max_datagram_size: usize) -> Self {
+        self.sink = SinkConfig::Udp { addr: addr.to_string(), max_datagram_size };
+        self
+    }
+
+    /// Send each field as a statsd gauge to the statsd server at `addr`
+    /// (`host:port`) instead of to influxdb, in datagrams of at most
+    /// `max_datagram_size` bytes. To send some fields as counters or
+    /// timers, or to include tags, pass a configured `StatsdSink` to `sink`
+    /// instead.
+    ///
+    pub fn statsd(mut self, addr: &str, max_datagram_size: usize) -> Self {
+        self.sink = SinkConfig::Statsd { addr: addr.to_string(), max_datagram_size };
+        self
+    }
+
+    /// Send each field to the graphite server's plaintext listener at
+    /// `addr` (`host:port`) over tcp instead of to influxdb, with metric
+    /// paths built from `template` (see `GraphiteSink::template`, and
+    /// `GraphiteSink::DEFAULT_TEMPLATE`).
+    ///
+    pub fn graphite(mut self, addr: &str, template: &str) -> Self {
+        self.sink = SinkConfig::Graphite { addr: addr.to_string(), template: template.to_string() };
+        self
+    }
+
+    /// Append batches to the file at `path` instead of sending them to a
+    /// server, e.g. for an agent to ship later. See `FileSink`, which can
+    /// also rotate and compress the file (configured with `sink`).
+    ///
+    pub fn file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.sink = SinkConfig::File(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Publish batches to the kafka `topic` at `brokers` (`host:port`)
+    /// instead of sending them to influxdb, one message per batch. See
+    /// `KafkaSink`, and `tee` to publish to kafka as well. Requires the
+    /// "kafka" feature.
+    ///
+    #[cfg(feature = "kafka")]
+    pub fn kafka(mut self, brokers: &[&str], topic: &str) -> Self {
+        let brokers = brokers.iter().map(|b| b.to_string()).collect();
+        self.sink = SinkConfig::Kafka { brokers, topic: topic.to_string() };
+        self
+    }
+
+    /// Publish batches to the mqtt broker at `host:port` instead of sending
+    /// them to influxdb, either to a single topic or to a topic per
+    /// measurement key (see `MqttSink`), e.g. at the edge, where telegraf
+    /// consumes mqtt and forwards to influxdb. Requires the "mqtt" feature.
+    ///
+    #[cfg(feature = "mqtt")]
+    pub fn mqtt(mut self, host: &str, port: u16, topic: MqttTopic) -> Self {
+        self.sink = SinkConfig::Mqtt { host: host.to_string(), port, topic };
+        self
+    }
+
+    /// Also write every batch to `sink`, in addition to the configured
+    /// destination (see `TeeSink`). Can be called more than once.
+    ///
+    /// Writes to `sink` are best-effort: a failure is logged and counted
+    /// (see `WriterStats::n_tee_errors`), but doesn't fail the batch.
+    ///
+    pub fn tee<S: Sink>(mut self, sink: S) -> Self {
+        self.tee.push(Arc::new(sink));
+        self
+    }
+
+    /// Distribute batches across the servers at `hosts` (each in any of the
+    /// forms `builder` accepts, with the same db, credentials and https
+    /// settings) instead
+    /// of sending them all to `host`, which is then only used in log
+    /// messages. A server that fails is skipped for a while. See
+    /// `LoadBalancedSink`, which can also be used with `sink` to balance
+    /// across other kinds of sinks.
+    ///
+    /// `hosts` must not be empty.
+    ///
+    pub fn load_balance(mut self, hosts: &[&str], balance: Balance) -> Self {
+        self.sink = SinkConfig::LoadBalanced { hosts: hosts.iter().map(|h| h.to_string()).collect(), balance };
+        self
+    }
+
+    /// Send a batch once its serialized size reaches `bytes`, even if it
+    /// holds fewer than `buffer_size` measurements, to bound request sizes
+    /// when measurements carry long string fields. (By default, batches are
+    /// only limited by count.)
+    ///
+    /// A single measurement larger than `MAX_BATCH_BYTES` is always sent
+    /// in a request of its own.
+    ///
+    pub fn flush_bytes(mut self, bytes: usize) -> Self {
+        self.flush_bytes = Some(bytes);
+        self
+    }
+
+    /// Queue length at which `InfluxWriter::should_shed` starts returning
+    /// `true`, so `measure!(@shed ..)` skips measurements (without building
+    /// them) while the writer thread is falling behind. By default,
+    /// measurements are never shed.
+    ///
+    pub fn high_water_mark(mut self, n: usize) -> Self {
+        self.high_water_mark = Some(n);
+        self
+    }
+
+    /// Write at most `points_per_sec` measurements per second (with bursts
+    /// of up to one second's worth), so a runaway loop can't overwhelm the
+    /// server. Measurements over the limit are discarded by the writer
+    /// thread and counted in `WriterStats::n_dropped`.
+    ///
+    pub fn rate_limit(mut self, points_per_sec: f64) -> Self {
+        self.rate_limit = Some(points_per_sec);
+        self
+    }
+
+    /// Split batches larger than `max_bytes` into several requests, sent in
+    /// parallel (at most `concurrency` at a time, see `ChunkedSink`), and
+    /// send up to `concurrency` batches from the backlog at once, rather
+    /// than one at a time, so a backlog built up during an outage drains
+    /// quickly once the server is back.
+    ///
+    pub fn max_request_bytes(mut self, max_bytes: usize, concurrency: usize) -> Self {
+        self.max_request_bytes = Some((max_bytes, concurrency));
+        self
+    }
+
+    /// Write measurements with `key` to database `db` instead of the
+    /// writer's db, so a single writer can fan out to several databases.
+    /// Requires an http sink (i.e. not `udp` or a custom `sink`), otherwise
+    /// building the writer fails with `Error::Config`. See `RoutingSink`.
+    ///
+    pub fn route(mut self, key: &str, db: &str) -> Self {
+        let default_db = self.db.clone();
+        self.routes.entry(key.to_string()).or_insert((default_db, None)).0 = db.to_string();
+        self
+    }
+
+    /// Write to retention policy `rp` (the `rp=` parameter of the write
+    /// api) instead of the database's default retention policy. Has no
+    /// effect with `udp` or a custom `sink`.
+    ///
+    pub fn retention_policy(mut self, rp: &str) -> Self {
+        self.retention_policy = Some(rp.to_string());
+        self
+    }
+
+    /// Write measurements with `key` to retention policy `rp`, overriding
+    /// `retention_policy` (e.g. to send high-frequency measurements to a
+    /// short retention policy). Like `route`, requires an http sink.
+    ///
+    pub fn route_retention_policy(mut self, key: &str, rp: &str) -> Self {
+        let default_db = self.db.clone();
+        self.routes.entry(key.to_string()).or_insert((default_db, None)).1 = Some(rp.to_string());
+        self
+    }
+
+    /// Compress request bodies with gzip, to reduce bandwidth to remote
+    /// servers. Has no effect with `udp`, `file` or a custom `sink`.
+    /// Requires the "gzip" feature.
+    ///
+    #[cfg(feature = "gzip")]
+    pub fn gzip(mut self, gzip: bool) -> Self {
+        self.gzip = gzip;
+        self
+    }
+
+    /// Write batches to `sink` instead of posting them to the server
+    /// over http.
+    ///
+    pub fn sink<S: Sink>(mut self, sink: S) -> Self {
+        self.sink = SinkConfig::Custom(Arc::new(sink));
+        self
+    }
+
+    /// Append batches rejected by the server as malformed (status 400) to
+    /// the file at `path`, each preceded by a comment line with the time,
+    /// status and server response. Rejected batches are not retried, and
+    /// are discarded if no dead letter destination is configured.
+    ///
+    pub fn dead_letter_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.dead_letter = Some(DeadLetter::File(path.as_ref().to_path_buf(), None));
+        self
+    }
+
+    /// Send batches rejected by the server as malformed (status 400) on
+    /// `tx`. Batches are discarded if the channel is full.
+    ///
+    pub fn dead_letter_channel(mut self, tx: Sender<RejectedBatch>) -> Self {
+        self.dead_letter = Some(DeadLetter::Channel(tx));
+        self
+    }
+
+    /// Post batches with `client` (see `ReqwestSink`), rather than one
+    /// built by the writer. The client's own settings (tls, timeouts,
+    /// proxies) apply; `https` still selects the url scheme and `gzip`
+    /// still applies, but `ca_cert` is ignored. Requires the "reqwest"
+    /// feature.
+    ///
+    #[cfg(feature = "reqwest")]
+    pub fn reqwest_client(mut self, client: reqwest::blocking::Client) -> Self {
+        self.sink = SinkConfig::Reqwest(client);
+        self
+    }
+
+    /// Post batches with hyper 0.10's client (`HttpSink`) instead of
+    /// reqwest (`ReqwestSink`), the default with the "reqwest" feature (a
+    /// default feature). Without it, or with a `resolver` or
+    /// `re_resolve_interval`, hyper is always used.
+    ///
+    pub fn hyper_client(mut self, hyper_client: bool) -> Self {
+        self.hyper_client = hyper_client;
+        self
+    }
+
+    /// How failed writes are retried (see `RetryPolicy`).
+    ///
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Listen for websocket connections on `addr`, and stream every
+    /// `sample_every`th line sent by the writer to connected clients as it
+    /// is flushed. The listener is closed, and clients disconnected, when
+    /// the writer shuts down. Requires the "live-tail" feature.
+    ///
+    #[cfg(feature = "live-tail")]
+    pub fn live_tail(mut self, addr: &str, sample_every: usize) -> Self {
+        self.live_tail = Some((addr.to_string(), sample_every));
+        self
+    }
+
+    /// Every `interval`, write an "influx_writer" measurement with the
+    /// writer's own counters (see `WriterStats`) and queue depth to the same
+    /// database, so the health of the pipeline can be graphed alongside
+    /// the data it carries.
+    ///
+    pub fn self_monitoring(mut self, interval: Duration) -> Self {
+        self.self_monitoring = Some(interval);
+        self
+    }
+
+    /// Time one in every `sample_every` calls to `send` and `try_send` (and
+    /// so `measure!`), and report the wall-clock cost of the enqueue by
+    /// measurement key as an "influx_writer_enqueue" measurement (tag "key",
+    /// fields "n_samples", "mean_ns" and "max_ns") every `self_monitoring`
+    /// interval, which is required. Useful for finding call sites on a
+    /// latency-sensitive path that block on a full queue.
+    ///
+    pub fn audit_enqueue(mut self, sample_every: u32) -> Self {
+        self.audit_enqueue = Some(sample_every.max(1));
+        self
+    }
+
+    /// Count the distinct values of each tag, by measurement key, and log a
+    /// warning and call `hook` with (key, tag, number of values) the first
+    /// time one has more than `limit`. Meant to catch a high-cardinality
+    /// tag (e.g. one from `OwnedMeasurement::add_tag_uuid`) before it
+    /// swamps the database's series index.
+    ///
+    /// Values are remembered as 8-byte hashes, up to `limit` per tag.
+    ///
+    pub fn cardinality_warning<F>(mut self, limit: usize, hook: F) -> Self
+        where F: Fn(&str, &str, usize) + Send + Sync + 'static
+    {
+        self.cardinality_warning = Some((limit, Arc::new(hook)));
+        self
+    }
+
+    /// Maximum number of measurements queued for the writer thread
+    /// (default `DEFAULT_QUEUE_CAPACITY`). When the queue is full, `send` blocks, and
+    /// `try_send` returns `Error::QueueFull`.
+    ///
+    pub fn queue_capacity(mut self, n: usize) -> Self {
+        self.queue_capacity = n.max(1);
+        self
+    }
+
+    /// Write batches that could not be sent (after retries are exhausted) to
+    /// files in `dir`, rather than holding them in memory, and replay them,
+    /// oldest first, once writes succeed again. Batches still spooled on
+    /// shutdown are replayed by the next writer started with the same `dir`.
+    ///
+    /// Spooled batches are replayed one at a time, each after a successful
+    /// write, and only while fewer than half of the maximum outstanding
+    /// requests are in flight, so live batches are never starved by the
+    /// backlog. While writes are failing and the writer is otherwise idle,
+    /// a spooled batch is replayed every second or so to find out when
+    /// the server is back. See also `spool_replay_interval`.
+    ///
+    pub fn spool_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.spool_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Compress spooled batches (default: don't). Batches are decompressed
+    /// according to their file's extension, so the spool can be reopened
+    /// with a different compression, or without one. See `spool_dir`.
+    ///
+    pub fn spool_compression(mut self, compression: FileCompression) -> Self {
+        self.spool_compression = Some(compression);
+        self
+    }
+
+    /// Encrypt spooled batches (see `spool_dir`) and the dead letter file
+    /// (see `dead_letter_file`) with `key`, for when the measurements they
+    /// hold mustn't be stored on local disk in the clear. `Replay` reads
+    /// them given the same key. Requires the "encryption" feature.
+    ///
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, key: EncryptionKey) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Minimum time between replays of spooled batches (default: none), to
+    /// limit the extra load on the server while a backlog drains.
+    ///
+    pub fn spool_replay_interval(mut self, interval: Duration) -> Self {
+        self.spool_replay_interval = interval;
+        self
+    }
+
+    /// Start an http listener on `addr` for inspecting and controlling the
+    /// writer at runtime. Endpoints:
+    ///
+    /// - `GET /stats`: counters and current settings, as json
+    /// - `POST /pause`, `POST /resume`: while paused, measurements sent to
+    ///   the writer are discarded
+    /// - `POST /flush`: send the active buffer immediately
+    /// - `POST /log-level/<level>`: set the max level of the writer's
+    ///   logging (e.g. "debug", "warn", "off")
+    /// - `POST /keys/<key>/disable`, `POST /keys/<key>/enable`: discard (or
+    ///   stop discarding) measurements with key `<key>` (percent-encoded,
+    ///   e.g. "a%2Fb" for "a/b")
+    ///
+    /// Bind to a localhost address (e.g. "127.0.0.1:9998"): the endpoints
+    /// are plain http, and without `admin_token` anyone who can connect can
+    /// pause the writer. The listener stops when the writer shuts down.
+    ///
+    pub fn admin(mut self, addr: &str) -> Self {
+        self.admin = Some(addr.to_string());
+        self
+    }
+
+    /// Require bearer token `token` (`Authorization: Bearer <token>`) for
+    /// the `admin` endpoints that change settings (all but `GET /stats`).
+    /// Requests without it are rejected with status 401.
+    ///
+    pub fn admin_token(mut self, token: &str) -> Self {
+        self.admin_token = Some(token.to_string());
+        self
+    }
+
+    /// Apply runtime settings from the file at `path` when the writer is
+    /// built (failing if it can't be read or parsed), then check it every
+    /// `poll_interval` and apply it again whenever it changes, logging each
+    /// setting that changed. Settings left out of the file are left as
+    /// they are. The file has one `setting = value` per line, with `#`
+    /// comments:
+    ///
+    /// ```text
+    /// log_level = info            # see `admin`
+    /// paused = false
+    /// disabled_keys = a, b
+    /// buffer_size = 4096          # lines per batch
+    /// flush_interval_ms = 3000    # max wait before a partial batch is sent
+    /// sample.<key> = 10           # keep 1 in 10, see `sample`
+    /// route.<key> = <db>          # see `route`
+    /// ```
+    ///
+    /// `route.<key>` may only name `db` or a database given to `route`
+    /// when the writer was built; routes to other databases are logged and
+    /// ignored.
+    ///
+    pub fn watch_config<P: AsRef<Path>>(mut self, path: P, poll_interval: Duration) -> Self {
+        self.watch_config = Some((path.as_ref().to_path_buf(), poll_interval));
+        self
+    }
+
+    /// Round `Float` values to `digits` significant digits when serializing
+    /// (see `SerializeOptions::float_precision`).
+    ///
+    pub fn float_precision(mut self, digits: usize) -> Self {
+        self.serialize_opts.float_precision = Some(digits);
+        self
+    }
+
+    /// Write `DateTime` values in `format` (see
+    /// `SerializeOptions::datetime_format`, default: nanoseconds).
+    ///
+    pub fn datetime_format(mut self, format: DateTimeFormat) -> Self {
+        self.serialize_opts.datetime_format = format;
+        self
+    }
+
+    /// Send a "process_start" measurement when the writer starts, tagged
+    /// with `version`, `git_hash` and the hostname, with the process id and
+    /// a summary of the writer's configuration as fields. Gives dashboards
+    /// a marker for each deploy or restart.
+    ///
+    pub fn process_start(mut self, version: &'static str, git_hash: &'static str) -> Self {
+        self.process_start = Some(BuildInfo::new(version, git_hash));
+        self
+    }
+
+    /// Send the "process_start" measurement (see `process_start`) using
+    /// `info`, usually from `build_info!()`, which also adds the rustc
+    /// version and build time as fields.
+    ///
+    pub fn build_info(mut self, info: BuildInfo) -> Self {
+        self.process_start = Some(info);
+        self
+    }
+
+    /// Prepend `prefix` (e.g. "prod_" or "appname.") to the key of every
+    /// measurement written. Keys given to other options (e.g. `route`,
+    /// `sample`) are without the prefix. See `SerializeOptions::key_prefix`.
+    ///
+    pub fn key_prefix(mut self, prefix: &str) -> Self {
+        self.serialize_opts.key_prefix = prefix.to_string();
+        self
+    }
+
+    /// Add the tag `key=value` to every measurement written (unless the
+    /// measurement already has a `key` tag), e.g. for "host", "env" or
+    /// "service" tags that would otherwise be repeated at every call site.
+    /// See `SerializeOptions::default_tags`.
+    ///
+    pub fn default_tag(mut self, key: &str, value: &str) -> Self {
+        self.serialize_opts.default_tags.retain(|(k, _)| k != key);
+        self.serialize_opts.default_tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Recycle measurements (and their tag and string field values) once
+    /// serialized, keeping up to `capacity` for reuse by
+    /// `InfluxWriter::acquire` and `InfluxWriter::acquire_string`. Saves an
+    /// allocation per string for producers sending at high rates, if they
+    /// build measurements with those methods.
+    ///
+    pub fn measurement_pool(mut self, capacity: usize) -> Self {
+        self.measurement_pool = Some(capacity);
+        self
+    }
+
+    /// Send `CREATE DATABASE` for the database (and any databases
+    /// measurements are routed to) when the writer is built, so writes to a
+    /// fresh server don't all fail with "database not found". Does nothing
+    /// if the database exists. Requires an http sink, and credentials with
+    /// admin privileges if auth is enabled.
+    ///
+    pub fn create_database(mut self, create: bool) -> Self {
+        self.create_database = create;
+        self
+    }
+
+    /// Check that the server accepts writes (with an empty write) when the
+    /// writer is built, so that `try_build` returns an error if the server
+    /// is unreachable, the credentials are rejected or the database doesn't
+    /// exist, rather than the first batch failing later. Each database
+    /// measurements are routed to is checked. With an http sink, the
+    /// `/ping` endpoint of every host (each of the `load_balance` hosts)
+    /// is checked first.
+    ///
+    /// Note: with a udp sink, the check can't detect any of these.
+    ///
+    pub fn check_on_start(mut self, check: bool) -> Self {
+        self.check_on_start = check;
+        self
+    }
+
+    /// Write measurements that would otherwise be lost to stderr, as line
+    /// protocol, at most `max_lines_per_sec` lines a second: measurements
+    /// sent after the writer thread has exited, and batches left unsent
+    /// when it exits. Also writes a warning whenever a failed batch is
+    /// requeued in memory, because there is no spool (see `spool_dir`) or
+    /// it couldn't be written to. A last resort, so the data can still be
+    /// found in container logs when everything else is down.
+    ///
+    pub fn stderr_fallback(mut self, max_lines_per_sec: f64) -> Self {
+        self.stderr_fallback = Some(max_lines_per_sec);
+        self
+    }
+
+    /// Add "hostname" and "pid" tags to every measurement written, plus a
+    /// "version" tag if a version was given with `build_info` or
+    /// `process_start`. Tags set with `default_tag` take precedence.
+    ///
+    pub fn with_standard_tags(mut self) -> Self {
+        self.standard_tags = true;
+        self
+    }
+
+    /// Generate a random id for this run of the process and add it to every
+    /// measurement as a `key` tag (in `UuidFormat::Simple`), so data from
+    /// overlapping restarts of the same service can be told apart. The id
+    /// is logged at startup and available from `InfluxWriter::config`.
+    ///
+    /// Every run is a new set of series, so this suits short-lived or
+    /// low-volume measurements better than high-cardinality ones.
+    ///
+    pub fn run_id_tag(mut self, key: &str) -> Self {
+        let id = Uuid::new_v4();
+        self.run_id = Some(id);
+        self.default_tag(key, &id.to_simple().to_string())
+    }
+
+    /// Adds the tags requested by `with_standard_tags` to the default tags.
+    ///
+    fn add_standard_tags(&mut self) {
+        if !self.standard_tags { return }
+        let mut tags = vec![("hostname", hostname().to_string()), ("pid", std::process::id().to_string())];
+        if let Some(build) = self.process_start {
+            tags.push(("version", build.version.to_string()));
+        }
+        let default_tags = &mut self.serialize_opts.default_tags;
+        for (key, value) in tags {
+            if !default_tags.iter().any(|(k, _)| k == key) {
+                default_tags.push((key.to_string(), value));
+            }
+        }
+    }
+
+    /// Starts the writer thread.
+    ///
+    /// Panics if the writer can't be started (see `try_build`).
+    ///
+    pub fn build(self) -> InfluxWriter {
+        match self.try_build() {
+            Ok(influx) => influx,
+            Err(e) => panic!("failed to start InfluxWriter: {}", e),
+        }
+    }
+
+    /// Starts the writer thread, returning an error if the configured host
+    /// does not produce a valid url or the thread can't be spawned.
+    ///
+    pub fn try_build(mut self) -> Result<InfluxWriter, Error> {
+        self.add_standard_tags();
+        InfluxWriter::spawn(self)
+    }
+
+    /// Starts an `AsyncInfluxWriter` on the current tokio runtime. Requires
+    /// the "tokio" feature.
+    ///
+    /// Panics if called outside of a tokio runtime.
+    ///
+    #[cfg(feature = "tokio")]
+    pub fn build_async(mut self) -> Result<AsyncInfluxWriter, Error> {
+        self.add_standard_tags();
+        AsyncInfluxWriter::spawn(self)
+    }
+}
+
+impl Drop for InfluxWriter {
+    fn drop(&mut self) {
+        if let Some(arc) = self.thread.take() {
+            if let Ok(thread) = Arc::try_unwrap(arc) {
+                let _ = self.tx.send(None);
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref WRITERS: RwLock<HashMap<String, InfluxWriter>> = RwLock::new(HashMap::new());
+    static ref HOSTNAME: String = {
+        std::env::var("HOSTNAME").ok()
+            .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+            .or_else(|| fs::read_to_string("/etc/hostname").ok())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "unknown".to_string())
+    };
+}
+
+/// The machine's hostname (from `$HOSTNAME`, or `/proc/sys/kernel/hostname`
+/// or `/etc/hostname`), or "unknown".
+///
+pub fn hostname() -> &'static str {
+    HOSTNAME.as_str()
+}
+
+/// Registers `writer` under `name` in a global registry, so that code
+/// without access to the handle can look it up with `writer(name)`.
+///
+/// Returns the writer previously registered under `name`, if any.
+///
+/// Note: registered writers are never dropped automatically, and the
+/// final flush of an `InfluxWriter` happens when its last handle is dropped.
+/// Call `unregister_writer` (and drop the result) prior to exit to ensure
+/// the remaining buffer is sent.
+///
+/// # Examples
+///
+/// ```no_run
+/// #[macro_use]
+/// extern crate influx_writer;
+///
+/// use influx_writer::{InfluxWriter, register_writer, writer, unregister_writer};
+///
+/// fn main() {
+///     register_writer("prod", InfluxWriter::new("localhost", "test"));
+///
+///     if let Some(influx) = writer("prod") {
+///         measure!(influx, example, i(n, 1));
+///     }
+///
+///     assert!(writer("dev").is_none());
+///
+///     drop(unregister_writer("prod"));
+/// }
+/// ```
+///
+pub fn register_writer(name: &str, writer: InfluxWriter) -> Option<InfluxWriter> {
+    let mut writers = WRITERS.write().unwrap_or_else(|e| e.into_inner());
+    writers.insert(name.to_string(), writer)
+}
+
+/// Returns a clone of the writer registered under `name` (see `register_writer`).
+///
+pub fn writer(name: &str) -> Option<InfluxWriter> {
+    let writers = WRITERS.read().unwrap_or_else(|e| e.into_inner());
+    writers.get(name).cloned()
+}
+
+/// Removes the writer registered under `name` from the global registry,
+/// returning it.
+///
+pub fn unregister_writer(name: &str) -> Option<InfluxWriter> {
+    let mut writers = WRITERS.write().unwrap_or_else(|e| e.into_inner());
+    writers.remove(name)
+}
+
+/// This removes offending things rather than escaping them.
+///
+fn escape_tag(s: &str) -> String {
+    s.replace(" ", "")
+     .replace(",", "")
+     .replace("\"", "")
+}
+
+fn escape(s: &str) -> String {
+    s.replace(" ", "\\ ")
+     .replace(",", "\\,")
+}
+
+fn as_string(s: &str) -> String {
+    // the second replace removes double escapes
+    //
+    format!("\"{}\"", s.replace("\"", "\\\"")
+                       .replace(r#"\\""#, r#"\""#))
+}
+
+#[test]
+fn it_checks_as_string_does_not_double_escape() {
+    let raw = "this is \\\"an escaped string\\\" so it's problematic";
     let escaped = as_string(&raw);
     assert_eq!(escaped, format!("\"{}\"", raw).as_ref());
 }
 
-fn as_boolean(b: &bool) -> &str {
-    if *b { "t" } else { "f" }
-}
+fn as_boolean(b: &bool) -> &str {
+    if *b { "t" } else { "f" }
+}
+
+pub fn now() -> i64 {
+    nanos(Utc::now()) as i64
+}
+
+/// Returns the series (measurement key and tags) portion of a serialized
+/// line, i.e. everything prior to the first unescaped space.
+///
+fn series_of_line(line: &str) -> &str {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            ' ' if !escaped => return &line[..i],
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+/// Reads gauge lines saved by `save_gauges`, one per line.
+///
+fn load_gauges(path: &Path) -> io::Result<Vec<String>> {
+    match fs::File::open(path) {
+        Ok(f) => {
+            BufReader::new(f).lines()
+                .filter(|line| line.as_ref().map(|l| !l.is_empty()).unwrap_or(true))
+                .collect()
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes gauge `lines` (serialized without timestamps) to `path`, one per line.
+///
+fn save_gauges<'a, I: Iterator<Item = &'a String>>(path: &Path, lines: I) -> io::Result<()> {
+    let mut f = io::BufWriter::new(fs::File::create(path)?);
+    for line in lines {
+        writeln!(f, "{}", line)?;
+    }
+    f.flush()
+}
+
+/// Serializes `m` onto the end of `buf` (preceded by a newline if `sep` is `true`).
+///
+/// If the serialized measurement is longer than `max_bytes`, it is split back
+/// off of `buf` (which is left as it was) and returned instead.
+///
+fn append_line(m: &OwnedMeasurement, buf: &mut String, sep: bool, max_bytes: usize, opts: &SerializeOptions) -> Option<String> {
+    let start = buf.len();
+    if sep { buf.push_str("\n"); }
+    serialize_owned_with(m, buf, opts);
+    if buf.len() - start > max_bytes {
+        let mut line = buf.split_off(start);
+        if sep { line.remove(0); }
+        Some(line)
+    } else {
+        None
+    }
+}
+
+/// Options controlling how measurements are serialized (see
+/// `serialize_owned_with`). The default options match `serialize_owned`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct SerializeOptions {
+    /// if set, `Float` values are rounded to this many significant digits,
+    /// which can substantially shrink measurements dominated by long `f64`
+    /// representations.
+    pub float_precision: Option<usize>,
+    /// tags added to every measurement that doesn't already have a tag
+    /// with the same key (e.g. "host" or "service").
+    pub default_tags: Vec<(String, String)>,
+    /// prepended to every measurement's key (e.g. "prod_"), so that
+    /// measurements from different environments sharing a database don't
+    /// collide.
+    pub key_prefix: String,
+    /// how `DateTime` values are written.
+    pub datetime_format: DateTimeFormat,
+}
+
+/// How `OwnedValue::DateTime` fields are serialized.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DateTimeFormat {
+    /// an integer of nanoseconds since the unix epoch, e.g.
+    /// `1500000000000000000i`. Values outside the range of an i64 (about
+    /// 1677-2262) are skipped.
+    #[default]
+    Nanos,
+    /// an RFC3339 string, e.g. `"2017-07-14T02:40:00Z"`
+    Rfc3339,
+}
+
+/// Rounds `x` to `digits` significant digits (minimum one), by formatting
+/// it in exponent notation, which rounds correctly, and parsing that back
+/// (on the stack: this runs for every float field with `float_precision`).
+///
+fn round_sig(x: f64, digits: usize) -> f64 {
+    use std::fmt::Write;
+
+    // an f64 has at most 17 significant digits, so more leave `x` as is
+    // (and at most 17, the formatted value fits the buffer)
+    if digits >= 17 { return x }
+    let mut buf = FmtBuf { buf: [0; 64], len: 0 };
+    write!(buf, "{:.*e}", digits.max(1) - 1, x).ok()
+        .and_then(|_| std::str::from_utf8(&buf.buf[..buf.len]).ok())
+        .and_then(|s| s.parse().ok())
+        // rounding up the largest values overflows
+        .filter(|rounded: &f64| rounded.is_finite())
+        .unwrap_or(x)
+}
+
+/// Fixed-size buffer for formatting a `d128` (or float, see `round_sig`)
+/// without allocating.
+///
+struct FmtBuf {
+    buf: [u8; 64],
+    len: usize,
+}
+
+impl fmt::Write for FmtBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() { return Err(fmt::Error) }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Writes a finite `d` to `line` in fixed-point notation (see
+/// `write_fixed_point`), rather than the exponent notation `d128` can
+/// format as (e.g. "1.2E+3").
+///
+fn write_d128(d: &d128, line: &mut String) {
+    use std::fmt::Write;
+
+    let mut buf = FmtBuf { buf: [0; 64], len: 0 };
+    let formatted = write!(buf, "{}", d).ok()
+        .and_then(|_| std::str::from_utf8(&buf.buf[..buf.len]).ok());
+    match formatted {
+        Some(s) => if !write_fixed_point(s, line) { line.push_str(s) },
+        None => { let _ = write!(line, "{}", d); }
+    }
+}
+
+/// Most digits `write_fixed_point` writes before or after the decimal point
+/// (more than a `d128`'s 34 digits of precision, so only values of extreme
+/// magnitude are left in exponent notation).
+///
+const MAX_FIXED_POINT_DIGITS: i64 = 64;
 
-pub fn now() -> i64 {
-    nanos(Utc::now()) as i64
+/// Writes the decimal number `s` (e.g. "-1.2500", "1.2E+3", "5E-7") to
+/// `line` in normalized fixed-point notation: no exponent, no leading zeros
+/// before the point or trailing zeros after it, and "0" for any zero (e.g.
+/// "-1.25", "1200", "0.0000005"). Returns `false`, without writing
+/// anything, if `s` isn't a decimal number or its magnitude is too extreme.
+///
+fn write_fixed_point(s: &str, line: &mut String) -> bool {
+    let (negative, s) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let (mantissa, exp) = match s.find(['E', 'e']) {
+        Some(i) => match s[i + 1..].parse::<i64>() {
+            Ok(exp) => (&s[..i], exp),
+            Err(_) => return false,
+        },
+        None => (s, 0),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, ""),
+    };
+    let (int, frac) = (int.as_bytes(), frac.as_bytes());
+    let n = (int.len() + frac.len()) as i64;
+    if n == 0 || !int.iter().chain(frac).all(u8::is_ascii_digit) {
+        return false
+    }
+    let digit = |i: i64| -> u8 {
+        if i < 0 || i >= n { b'0' }
+        else if (i as usize) < int.len() { int[i as usize] }
+        else { frac[i as usize - int.len()] }
+    };
+    // the digits are digit(0..n), with the decimal point before digit(point)
+    let point = int.len() as i64 + exp;
+    let first = match (0..n).find(|&i| digit(i) != b'0') {
+        Some(first) => first,
+        None => {
+            line.push('0');
+            return true
+        }
+    };
+    let last = (0..n).rev().find(|&i| digit(i) != b'0').unwrap_or(first);
+    if point - first > MAX_FIXED_POINT_DIGITS || last + 1 - point > MAX_FIXED_POINT_DIGITS {
+        return false
+    }
+    if negative { line.push('-') }
+    let int_start = first.min(point - 1);
+    for i in int_start..point.max(int_start + 1) {
+        line.push(digit(i) as char);
+    }
+    if last >= point {
+        line.push('.');
+        for i in point..=last {
+            line.push(digit(i) as char);
+        }
+    }
+    true
 }
 
 /// Serializes an `&OwnedMeasurement` as influx line protocol into `line`.
@@ -938,7 +3911,28 @@ pub fn now() -> i64 {
 /// any regard for what exited in it previously.
 ///
 pub fn serialize_owned(measurement: &OwnedMeasurement, line: &mut String) {
-    line.push_str(&escape_tag(measurement.key));
+    serialize_owned_with(measurement, line, &SerializeOptions::default())
+}
+
+/// Like `serialize_owned`, with the serialization controlled by `opts`.
+///
+/// # Examples
+///
+/// ```
+/// use influx_writer::{OwnedMeasurement, OwnedValue, SerializeOptions, serialize_owned_with};
+///
+/// let m = OwnedMeasurement::new("test").add_field("x", OwnedValue::Float(1.23456789));
+/// let opts = SerializeOptions { float_precision: Some(4), ..Default::default() };
+/// let mut buf = String::new();
+/// serialize_owned_with(&m, &mut buf, &opts);
+/// assert_eq!(buf, "test x=1.235");
+/// ```
+///
+pub fn serialize_owned_with(measurement: &OwnedMeasurement, line: &mut String, opts: &SerializeOptions) {
+    if !opts.key_prefix.is_empty() {
+        line.push_str(&escape_tag(&opts.key_prefix));
+    }
+    line.push_str(&escape_tag(&measurement.key));
 
     let add_tag = |line: &mut String, key: &str, value: &str| {
         line.push_str(",");
@@ -955,9 +3949,26 @@ pub fn serialize_owned(measurement: &OwnedMeasurement, line: &mut String) {
         add_tag(line, key, value.as_str());
     }
 
+    for (key, value) in opts.default_tags.iter() {
+        if !measurement.tags.iter().any(|(k, _)| *k == key.as_str()) {
+            add_tag(line, key, value);
+        }
+    }
+
     let add_field = |line: &mut String, key: &str, value: &OwnedValue, is_first: bool| -> bool {
 
         if SKIP_NAN_VALUES && ! value.is_finite() { return false }
+
+        // nanoseconds since the epoch only fit in an i64 for roughly
+        // 1677-2262; writing these fields as strings instead would conflict
+        // with the field's integer type, so they are skipped
+        let nanos = match *value {
+            OwnedValue::DateTime(ref t) if opts.datetime_format == DateTimeFormat::Nanos => match t.timestamp_nanos_opt() {
+                Some(nanos) => Some(nanos),
+                None => return false,
+            },
+            _ => None,
+        };
         
         if is_first { line.push_str(" "); } else { line.push_str(","); }
         line.push_str(&escape_tag(key));
@@ -969,7 +3980,7 @@ pub fn serialize_owned(measurement: &OwnedMeasurement, line: &mut String) {
 
             OwnedValue::D128(ref d) => {
                 if d.is_finite() {
-                    line.push_str(&format!("{}", d));
+                    write_d128(d, line);
                 } else {
                     line.push_str("-999.0");
                 }
@@ -977,13 +3988,25 @@ pub fn serialize_owned(measurement: &OwnedMeasurement, line: &mut String) {
 
             OwnedValue::Float(ref f)   => {
                 if f.is_finite() {
-                    line.push_str(&format!("{}", f));
+                    match opts.float_precision {
+                        Some(digits) => line.push_str(&format!("{}", round_sig(*f, digits))),
+                        None => line.push_str(&format!("{}", f)),
+                    }
                 } else {
                     line.push_str("-999.0");
                 }
             }
 
             OwnedValue::Uuid(ref u)    => line.push_str(&format!("\"{}\"", u)),
+
+            OwnedValue::DateTime(ref t) => match opts.datetime_format {
+                DateTimeFormat::Nanos => line.push_str(&format!("{}i", nanos.expect("checked above"))),
+                DateTimeFormat::Rfc3339 => {
+                    line.push('"');
+                    line.push_str(&t.to_rfc3339_opts(SecondsFormat::AutoSi, true));
+                    line.push('"');
+                }
+            }
         };
 
         true
@@ -1000,7 +4023,7 @@ pub fn serialize_owned(measurement: &OwnedMeasurement, line: &mut String) {
     let mut n_fields_serialized = 0;
 
     for kv in measurement.fields.iter() {
-        if add_field(line, kv.0, &kv.1, n_fields_serialized == 0) {
+        if add_field(line, &kv.0, &kv.1, n_fields_serialized == 0) {
             n_fields_serialized += 1;
         }
     }
@@ -1017,14 +4040,27 @@ pub fn serialize_owned(measurement: &OwnedMeasurement, line: &mut String) {
     }
 }
 
+/// How `OwnedMeasurement::add_tag_uuid` formats the id.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UuidFormat {
+    /// e.g. "67e55044-10b1-426f-9247-bb680e5fe0c8"
+    Hyphenated,
+    /// e.g. "67e5504410b1426f9247bb680e5fe0c8"
+    Simple,
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OwnedValue {
     String(String),
     Float(f64),
     Integer(i64),
     Boolean(bool),
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::d128_string"))]
     D128(d128),
     Uuid(Uuid),
+    DateTime(DateTime<Utc>),
 }
 
 impl OwnedValue {
@@ -1059,35 +4095,53 @@ impl OwnedValue {
     }
 }
 
+/// A measurement key, tag key or field name: borrowed for string literals
+/// (the usual case, which costs no allocation), or owned for names only
+/// known at runtime, e.g. metric names read from a config file.
+///
+/// Anything that converts `Into<Key>`, `&'static str` and `String`
+/// included, can be passed where a key is expected:
+///
+/// ```
+/// use influx_writer::{OwnedMeasurement, OwnedValue};
+///
+/// let metric = String::from("queue_depth");
+/// let m = OwnedMeasurement::new(format!("svc_{}", metric)).add_field(metric, OwnedValue::Integer(3));
+/// assert_eq!(m.key, "svc_queue_depth");
+/// assert_eq!(m.get_field("queue_depth"), Some(&OwnedValue::Integer(3)));
+/// ```
+///
+pub type Key = Cow<'static, str>;
+
 /// Holds data meant for an influxdb measurement in transit to the
 /// writing thread.
 ///
 #[derive(Clone, Debug)]
 pub struct OwnedMeasurement {
-    pub key: &'static str,
+    pub key: Key,
     pub timestamp: Option<i64>,
     //pub fields: Map<&'static str, OwnedValue>,
     //pub tags: Map<&'static str, &'static str>,
-    pub fields: SmallVec<[(&'static str, OwnedValue); 8]>,
+    pub fields: SmallVec<[(Key, OwnedValue); 8]>,
     #[cfg(not(feature = "string-tags"))]
-    pub tags: SmallVec<[(&'static str, &'static str); 8]>,
+    pub tags: SmallVec<[(Key, &'static str); 8]>,
     #[cfg(feature = "string-tags")]
-    pub tags: SmallVec<[(&'static str, String); 8]>,
+    pub tags: SmallVec<[(Key, String); 8]>,
 }
 
 impl OwnedMeasurement {
-    pub fn with_capacity(key: &'static str, n_tags: usize, n_fields: usize) -> Self {
+    pub fn with_capacity<K: Into<Key>>(key: K, n_tags: usize, n_fields: usize) -> Self {
         OwnedMeasurement {
-            key,
+            key: key.into(),
             timestamp: None,
             tags: SmallVec::with_capacity(n_tags),
             fields: SmallVec::with_capacity(n_fields),
         }
     }
 
-    pub fn new(key: &'static str) -> Self {
+    pub fn new<K: Into<Key>>(key: K) -> Self {
         OwnedMeasurement {
-            key,
+            key: key.into(),
             timestamp: None,
             tags: SmallVec::new(),
             fields: SmallVec::new(),
@@ -1097,21 +4151,43 @@ impl OwnedMeasurement {
     /// Unusual consuming `self` signature because primarily used by
     /// the `measure!` macro.
     #[cfg(not(feature = "string-tags"))]
-    pub fn add_tag(mut self, key: &'static str, value: &'static str) -> Self {
-        self.tags.push((key, value));
+    pub fn add_tag<K: Into<Key>>(mut self, key: K, value: &'static str) -> Self {
+        self.tags.push((key.into(), value));
+        self
+    }
+
+    #[cfg(feature = "string-tags")]
+    pub fn add_tag<K: Into<Key>, S: ToString>(mut self, key: K, value: S) -> Self {
+        self.tags.push((key.into(), value.to_string()));
         self
     }
 
+    /// Like `add_tag`, but takes ownership of `value` rather than copying
+    /// it (e.g. a string from `InfluxWriter::acquire_string`).
+    ///
     #[cfg(feature = "string-tags")]
-    pub fn add_tag<S: ToString>(mut self, key: &'static str, value: S) -> Self {
-        self.tags.push((key, value.to_string()));
+    pub fn add_tag_string<K: Into<Key>>(mut self, key: K, value: String) -> Self {
+        self.tags.push((key.into(), value));
         self
     }
 
+    /// Adds `uuid` as a tag, rather than a field (`OwnedValue::Uuid`), so
+    /// it can be grouped by, e.g. for a session or run id. Every distinct
+    /// id is a new series, see `InfluxWriterBuilder::cardinality_warning`.
+    ///
+    #[cfg(feature = "string-tags")]
+    pub fn add_tag_uuid<K: Into<Key>>(self, key: K, uuid: Uuid, format: UuidFormat) -> Self {
+        let value = match format {
+            UuidFormat::Hyphenated => uuid.to_hyphenated().to_string(),
+            UuidFormat::Simple => uuid.to_simple().to_string(),
+        };
+        self.add_tag_string(key, value)
+    }
+
     /// Unusual consuming `self` signature because primarily used by
     /// the `measure!` macro.
-    pub fn add_field(mut self, key: &'static str, value: OwnedValue) -> Self {
-        self.fields.push((key, value));
+    pub fn add_field<K: Into<Key>>(mut self, key: K, value: OwnedValue) -> Self {
+        self.fields.push((key.into(), value));
         self
     }
 
@@ -1121,12 +4197,13 @@ impl OwnedMeasurement {
     }
 
     #[cfg(not(feature = "string-tags"))]
-    pub fn set_tag(mut self, key: &'static str, value: &'static str) -> Self {
+    pub fn set_tag<K: Into<Key>>(mut self, key: K, value: &'static str) -> Self {
+        let key = key.into();
         match self.tags.iter().position(|kv| kv.0 == key) {
             Some(i) => {
                 self.tags.get_mut(i)
                     .map(|x| {
-                        x.0 = value;
+                        x.1 = value;
                     });
                 self
             }
@@ -1137,21 +4214,21 @@ impl OwnedMeasurement {
         }
     }
 
-    pub fn get_field(&self, key: &'static str) -> Option<&OwnedValue> {
+    pub fn get_field(&self, key: &str) -> Option<&OwnedValue> {
         self.fields.iter()
             .find(|kv| kv.0 == key)
             .map(|kv| &kv.1)
     }
 
     #[cfg(feature = "string-tags")]
-    pub fn get_tag(&self, key: &'static str) -> Option<&str> {
+    pub fn get_tag(&self, key: &str) -> Option<&str> {
         self.tags.iter()
             .find(|kv| kv.0 == key)
             .map(|kv| kv.1.as_str())
     }
 
     #[cfg(not(feature = "string-tags"))]
-    pub fn get_tag(&self, key: &'static str) -> Option<&'static str> {
+    pub fn get_tag(&self, key: &str) -> Option<&'static str> {
         self.tags.iter()
             .find(|kv| kv.0 == key)
             .map(|kv| kv.1)
@@ -1162,10 +4239,100 @@ impl OwnedMeasurement {
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
+    use hyper::status::StatusCode;
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicBool;
     use super::*;
     #[cfg(feature = "unstable")]
     use test::{black_box, Bencher};
 
+    /// A builder for a writer to db "test" that writes to the returned
+    /// `MemorySink`.
+    ///
+    fn memory_builder() -> (InfluxWriterBuilder, MemorySink) {
+        let sink = MemorySink::new(Duration::from_secs(60));
+        (InfluxWriter::builder("localhost", "test").sink(sink.clone()), sink)
+    }
+
+    /// Polls `done` until it returns `true`, panicking if it hasn't within
+    /// `timeout`.
+    ///
+    fn wait_until<F: FnMut() -> bool>(timeout: Duration, what: &str, mut done: F) {
+        let deadline = Instant::now() + timeout;
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out after {:?} waiting until {}", timeout, what);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// A `Sink` standing in for the server. It records the batches written,
+    /// and counts writes. Clones share their state, so a test keeps a clone
+    /// of the sink it passes to the builder.
+    ///
+    #[derive(Clone, Default)]
+    struct FakeSink {
+        batches: Arc<Mutex<Vec<String>>>,
+        n_writes: Arc<AtomicUsize>,
+        /// while set, writes fail as if the server were unreachable
+        down: Arc<AtomicBool>,
+        n_failed: Arc<AtomicUsize>,
+        /// if set, every write fails with this status and message
+        reject: Option<(u16, &'static str)>,
+        /// if set, each successful write takes up to this long, ending early
+        /// once this many are in progress
+        hold: Option<(usize, Duration)>,
+        n_writing: Arc<AtomicUsize>,
+        max_writing: Arc<AtomicUsize>,
+    }
+
+    impl FakeSink {
+        fn rejecting(status: u16, message: &'static str) -> Self {
+            FakeSink { reject: Some((status, message)), ..Default::default() }
+        }
+
+        fn down() -> Self {
+            let sink = FakeSink::default();
+            sink.set_down(true);
+            sink
+        }
+
+        fn set_down(&self, down: bool) {
+            self.down.store(down, Ordering::SeqCst);
+        }
+
+        fn batches(&self) -> Vec<String> {
+            self.batches.lock().unwrap().clone()
+        }
+
+        fn lines(&self) -> Vec<String> {
+            self.batches().iter().flat_map(|batch| batch.lines().map(str::to_string).collect::<Vec<_>>()).collect()
+        }
+    }
+
+    impl Sink for FakeSink {
+        fn write(&self, body: &str) -> Result<(), Error> {
+            self.n_writes.fetch_add(1, Ordering::SeqCst);
+            if let Some((status, message)) = self.reject {
+                return Err(Error::Transport { status: Some(status), message: message.to_string() })
+            }
+            if self.down.load(Ordering::SeqCst) {
+                self.n_failed.fetch_add(1, Ordering::SeqCst);
+                return Err(Error::Transport { status: None, message: "down".to_string() })
+            }
+            if let Some((n, max_wait)) = self.hold {
+                let n_writing = self.n_writing.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_writing.fetch_max(n_writing, Ordering::SeqCst);
+                let deadline = Instant::now() + max_wait;
+                while self.max_writing.load(Ordering::SeqCst) < n && Instant::now() < deadline {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                self.n_writing.fetch_sub(1, Ordering::SeqCst);
+            }
+            self.batches.lock().unwrap().push(body.to_string());
+            Ok(())
+        }
+    }
+
     #[ignore]
     #[cfg(feature = "unstable")]
     #[bench]
@@ -1205,6 +4372,18 @@ mod tests {
         assert_eq!(m.timestamp, Some(time));
     }
 
+    #[cfg(not(feature = "string-tags"))]
+    #[test]
+    fn it_replaces_the_value_of_an_existing_tag_with_set_tag() {
+        let m = OwnedMeasurement::new("test")
+            .add_tag("color", "red")
+            .set_tag("color", "blue")
+            .set_tag("size", "big");
+        assert_eq!(m.get_tag("color"), Some("blue"));
+        assert_eq!(m.get_tag("size"), Some("big"));
+        assert_eq!(m.tags.len(), 2);
+    }
+
     #[test]
     fn it_uses_the_new_tag_k_only_shortcut() {
         let tag_value = "one";
@@ -1243,6 +4422,457 @@ mod tests {
         a.f();
     }
 
+    #[test]
+    fn it_looks_up_a_registered_writer_by_name() {
+        assert!(writer("registry_test").is_none());
+        assert!(register_writer("registry_test", InfluxWriter::new("localhost", "registry_test")).is_none());
+        assert_eq!(writer("registry_test").map(|w| w.db().to_string()), Some("registry_test".to_string()));
+        assert!(unregister_writer("registry_test").is_some());
+        assert!(writer("registry_test").is_none());
+    }
+
+    #[cfg(feature = "string-tags")]
+    #[test]
+    fn it_tags_a_measurement_with_the_sending_thread_name() {
+        let m = thread::Builder::new().name("tag-thread-test".into()).spawn(|| {
+            tag_current_thread(OwnedMeasurement::new("test"))
+        }).unwrap().join().unwrap();
+        assert_eq!(m.get_tag("thread"), Some("tag-thread-test"));
+
+        let m = thread::Builder::new().name("tag-thread-test".into()).spawn(|| {
+            tag_current_thread(OwnedMeasurement::new("test").add_tag("thread", "explicit"))
+        }).unwrap().join().unwrap();
+        assert_eq!(m.get_tag("thread"), Some("explicit"));
+        assert_eq!(m.tags.len(), 1);
+    }
+
+    #[test]
+    fn it_returns_the_measurement_when_the_writer_thread_is_gone() {
+        let influx = InfluxWriter::placeholder(); // receiver dropped immediately
+        let m = OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1));
+        match influx.send(m) {
+            Err(Error::Queue(m)) => assert_eq!(m.key, "test"),
+            other => panic!("expected Err(Error::Queue(..)), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_flushes_remaining_measurements_on_close() {
+        let sink = FakeSink::default();
+        let influx = InfluxWriter::builder("localhost", "test").sink(sink.clone()).try_build().unwrap();
+        let other_handle = influx.clone();
+        for i in 0..3 {
+            influx.send(OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(i))).unwrap();
+        }
+        let stats = influx.close(Duration::from_secs(5)).unwrap();
+        assert_eq!(stats.n_rcvd, 3);
+        assert_eq!(stats.n_batches_unsent, 0);
+        let stats = other_handle.stats();
+        assert_eq!(stats.n_rcvd, 3);
+        assert!(stats.n_batches_ok >= 1);
+        assert_eq!(stats.n_request_errors, 0);
+        assert!(stats.n_bytes_sent > 0);
+        assert!(sink.lines().iter().filter(|l| l.starts_with("test ")).count() == 3);
+        assert!(other_handle.send(OwnedMeasurement::new("test")).is_err());
+    }
+
+    #[test]
+    fn it_sends_a_batch_once_it_reaches_flush_bytes() {
+        let sink = FakeSink::default();
+        let influx = InfluxWriter::builder("localhost", "test")
+            .sink(sink.clone())
+            .flush_bytes(40)
+            .try_build()
+            .unwrap();
+        for i in 0..6 {
+            let m = OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(i)).set_timestamp(1);
+            influx.send(m).unwrap(); // "test n=0i 1" is 11 bytes
+        }
+        influx.close(Duration::from_secs(5)).unwrap();
+        let batches = sink.batches();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0], "test n=0i 1\ntest n=1i 1\ntest n=2i 1\ntest n=3i 1");
+        assert!(batches[1].starts_with("test n=4i 1\ntest n=5i 1\n"));
+    }
+
+    #[test]
+    fn it_sends_rejected_batches_to_the_dead_letter_channel_without_retrying() {
+        let sink = FakeSink::rejecting(400, "unable to parse");
+        let (tx, rx) = bounded(8);
+        let influx = InfluxWriter::builder("localhost", "test")
+            .sink(sink.clone())
+            .dead_letter_channel(tx)
+            .try_build()
+            .unwrap();
+        influx.send(OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1))).unwrap();
+        let stats = influx.close(Duration::from_secs(5)).unwrap();
+        let rejected = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(rejected.body.starts_with("test n=1i "));
+        assert_eq!(rejected.status, 400);
+        assert_eq!(rejected.response, "unable to parse");
+        assert_eq!(sink.n_writes.load(Ordering::SeqCst), 1);
+        assert_eq!(stats.n_batches_unsent, 0);
+    }
+
+    #[test]
+    fn it_sends_a_process_start_measurement_when_configured() {
+        let (builder, sink) = memory_builder();
+        let influx = builder
+            .process_start("1.2.3", "abc123")
+            .try_build()
+            .unwrap();
+        influx.close(Duration::from_secs(5)).unwrap();
+        let points = sink.filter(|p| p.key == "process_start");
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].tag("version"), Some("1.2.3"));
+        assert_eq!(points[0].tag("git_hash"), Some("abc123"));
+        assert_eq!(points[0].tag("hostname"), Some(hostname()));
+        assert_eq!(points[0].field("pid"), Some(format!("{}i", std::process::id()).as_str()));
+        assert_eq!(points[0].field("sink"), Some("\"custom\""));
+        assert_eq!(points[0].field("rustc_version"), Some("\"unknown\""));
+    }
+
+    #[test]
+    fn it_adds_standard_tags_without_overriding_default_tags() {
+        let (builder, sink) = memory_builder();
+        let influx = builder
+            .with_standard_tags()
+            .default_tag("hostname", "web-1")
+            .build_info(BuildInfo::new("1.2.3", "abc123"))
+            .try_build()
+            .unwrap();
+        influx.send(OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1))).unwrap();
+        influx.close(Duration::from_secs(5)).unwrap();
+        let points = sink.filter(|p| p.key == "test");
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].tag("hostname"), Some("web-1"));
+        assert_eq!(points[0].tag("pid"), Some(std::process::id().to_string().as_str()));
+        assert_eq!(points[0].tag("version"), Some("1.2.3"));
+    }
+
+    #[test]
+    fn it_tags_every_measurement_with_the_run_id() {
+        let (builder, sink) = memory_builder();
+        let influx = builder
+            .run_id_tag("run")
+            .try_build()
+            .unwrap();
+        let run_id = influx.config().run_id.unwrap().to_simple().to_string();
+        influx.send(OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1))).unwrap();
+        influx.close(Duration::from_secs(5)).unwrap();
+        let points = sink.filter(|p| p.key == "test");
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].tag("run"), Some(run_id.as_str()));
+    }
+
+    #[test]
+    fn it_returns_an_error_from_try_build_if_the_startup_check_fails() {
+        let no_db = FakeSink::rejecting(404, "database not found: \"test\"");
+        match InfluxWriter::builder("localhost", "test").sink(no_db.clone()).check_on_start(true).try_build() {
+            Err(Error::Transport { status: Some(404), message }) => assert!(message.starts_with("startup check for db test: database not found")),
+            other => panic!("expected Err(Error::Transport {{ status: Some(404), .. }}), got {:?}", other),
+        }
+        assert!(InfluxWriter::builder("localhost", "test").sink(no_db).try_build().is_ok());
+    }
+
+    #[test]
+    fn it_pings_every_load_balanced_host_in_the_startup_check() {
+        use std::io::Write;
+        use std::net::TcpListener;
+
+        // answers every request with 204
+        let up = TcpListener::bind("127.0.0.1:0").unwrap();
+        let up_host = up.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for mut stream in up.incoming().flatten() {
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf);
+                let _ = stream.write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+            }
+        });
+        let down_host = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+
+        let build = |hosts: &[&str]| InfluxWriter::builder("localhost", "test")
+            .load_balance(hosts, Balance::KeyHash)
+            .check_on_start(true)
+            .try_build();
+        assert!(build(&[&up_host]).is_ok());
+        match build(&[&up_host, &down_host]) {
+            Err(Error::Transport { status: None, message }) => {
+                assert!(message.starts_with(&format!("startup check for host {}: server unreachable", down_host)), "{}", message)
+            }
+            other => panic!("expected Err(Error::Transport {{ status: None, .. }}), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_returns_queue_full_from_try_send() {
+        let (tx, _rx) = bounded(1);
+        let influx = InfluxWriter::test_writer(tx);
+        let m = OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1));
+        influx.try_send(m.clone()).unwrap();
+        match influx.try_send(m) {
+            Err(Error::QueueFull(m)) => assert_eq!(m.key, "test"),
+            other => panic!("expected Err(Error::QueueFull(..)), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_sheds_measurements_at_the_high_water_mark() {
+        let (tx, rx) = bounded(8);
+        let mut influx = InfluxWriter::test_writer(tx);
+        influx.high_water_mark = 2;
+        for i in 0..4 {
+            measure!(@shed influx, test, i(n, i));
+        }
+        assert_eq!(rx.len(), 2);
+        assert!(influx.should_shed());
+        // asking doesn't count as a drop
+        assert!(influx.should_shed());
+        assert_eq!(influx.stats().n_dropped, 2);
+        rx.recv().unwrap();
+        assert!(!influx.should_shed());
+    }
+
+    #[test]
+    fn it_sends_a_snapshot_as_one_timestamped_measurement() {
+        let (tx, rx) = bounded(8);
+        let influx = InfluxWriter::test_writer(tx);
+        let state = Mutex::new((1, 2.5));
+        let before = now();
+        influx.snapshot("test", |m| {
+            let state = state.lock().unwrap();
+            m.add_field("a", OwnedValue::Integer(state.0)).add_field("b", OwnedValue::Float(state.1))
+        }).unwrap();
+        let m = rx.try_recv().unwrap().unwrap();
+        assert_eq!(rx.len(), 0);
+        assert_eq!(m.key, "test");
+        assert_eq!(m.fields.len(), 2);
+        assert!(m.timestamp.unwrap() >= before);
+    }
+
+    #[test]
+    fn it_audits_a_sample_of_sends_by_key() {
+        let (tx, _rx) = bounded(8);
+        let audit = Arc::new(EnqueueAudit::new(2));
+        let mut influx = InfluxWriter::test_writer(tx);
+        influx.audit = Some(Arc::clone(&audit));
+        for i in 0..4 {
+            measure!(influx, a, i(n, i));
+        }
+        influx.try_send(OwnedMeasurement::new("b")).unwrap();
+        let samples = audit.take();
+        assert_eq!(samples["a"].n, 2);
+        assert_eq!(samples["b"].n, 1);
+    }
+
+    #[cfg(feature = "string-tags")]
+    #[test]
+    fn it_tags_measurements_with_the_current_correlation_id() {
+        let (tx, rx) = bounded(8);
+        let mut influx = InfluxWriter::test_writer(tx);
+        influx.tag_correlation_id = true;
+        let m = OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1));
+        let recv = || rx.try_recv().unwrap().unwrap();
+        influx.send(m.clone()).unwrap();
+        assert!(recv().get_tag("correlation_id").is_none());
+        {
+            let _outer = set_correlation_id("a");
+            {
+                let _inner = set_correlation_id("b");
+                influx.send(m.clone()).unwrap();
+                assert_eq!(recv().get_tag("correlation_id"), Some("b"));
+            }
+            influx.send(m.clone()).unwrap();
+            assert_eq!(recv().get_tag("correlation_id"), Some("a"));
+            influx.send(m.clone().add_tag("correlation_id", "c")).unwrap();
+            assert_eq!(recv().get_tag("correlation_id"), Some("c"));
+        }
+        assert_eq!(current_correlation_id(), None);
+    }
+
+    #[test]
+    fn it_returns_a_config_error_for_an_invalid_host() {
+        match InfluxWriter::try_new("not a valid host", "test") {
+            Err(Error::Config(_)) => {}
+            other => panic!("expected Err(Error::Config(..)), got {:?}", other),
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    #[test]
+    fn it_returns_a_config_error_for_https_without_the_tls_feature() {
+        match InfluxWriter::builder("localhost", "test").https(true).hyper_client(true).try_build() {
+            Err(Error::Config(_)) => {}
+            other => panic!("expected Err(Error::Config(..)), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_samples_one_in_n_measurements_per_key() {
+        let mut sampling: HashMap<String, (u32, u64)> = HashMap::new();
+        sampling.insert("ticks".to_string(), (3, 0));
+        let kept: Vec<bool> = (0..7).map(|_| !sampled_out(&mut sampling, "ticks")).collect();
+        assert_eq!(kept, vec![true, false, false, true, false, false, true]);
+        assert!((0..7).all(|_| !sampled_out(&mut sampling, "fills")));
+    }
+
+    #[test]
+    fn it_skips_unchanged_gauge_values_until_the_keepalive() {
+        let start = Instant::now();
+        let opts = SerializeOptions::default();
+        let mut dedup = Dedup::new(vec!["g".to_string()], Duration::from_secs(60));
+        let g = |v: i64, t: i64| OwnedMeasurement::new("g").add_tag("host", "a").add_field("v", OwnedValue::Integer(v)).set_timestamp(t);
+        // as the writer thread does: record only what isn't skipped
+        let mut write = |m: &OwnedMeasurement, now: Instant| {
+            let repeat = dedup.is_repeat(m, &opts, now);
+            if !repeat { dedup.record(m, &opts, now) }
+            !repeat
+        };
+        assert!(write(&g(1, 1), start));
+        assert!(!write(&g(1, 2), start + Duration::from_secs(1)));
+        assert!(write(&g(2, 3), start + Duration::from_secs(2)));
+        assert!(!write(&g(2, 4), start + Duration::from_secs(3)));
+        assert!(write(&g(2, 5), start + Duration::from_secs(63)));
+        let other_host = OwnedMeasurement::new("g").add_tag("host", "b").add_field("v", OwnedValue::Integer(2));
+        assert!(write(&other_host, start + Duration::from_secs(63)));
+        let other_key = OwnedMeasurement::new("h").add_field("v", OwnedValue::Integer(1));
+        assert!(write(&other_key, start));
+        assert!(write(&other_key, start));
+        // a value that is checked but never recorded doesn't suppress the next
+        assert!(!dedup.is_repeat(&g(3, 6), &opts, start + Duration::from_secs(64)));
+        assert!(!dedup.is_repeat(&g(3, 7), &opts, start + Duration::from_secs(65)));
+    }
+
+    #[cfg(feature = "string-tags")]
+    #[test]
+    fn it_warns_once_when_a_uuid_tag_exceeds_the_cardinality_limit() {
+        let id = Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+        let m = OwnedMeasurement::new("runs").add_tag_uuid("run", id, UuidFormat::Simple);
+        assert_eq!(m.get_tag("run"), Some("67e5504410b1426f9247bb680e5fe0c8"));
+        let m = OwnedMeasurement::new("runs").add_tag_uuid("run", id, UuidFormat::Hyphenated);
+        assert_eq!(m.get_tag("run"), Some("67e55044-10b1-426f-9247-bb680e5fe0c8"));
+
+        let hooked = Arc::new(Mutex::new(Vec::new()));
+        let h = hooked.clone();
+        let mut cardinality = Cardinality::new(2, Arc::new(move |key: &str, tag: &str, n| h.lock().unwrap().push((key.to_string(), tag.to_string(), n))));
+        let mut n_warned = 0;
+        for _ in 0..2 {
+            for i in 0..4 {
+                let m = OwnedMeasurement::new("runs").add_tag("host", "a").add_tag_uuid("run", Uuid::from_u128(i), UuidFormat::Simple);
+                cardinality.observe(&m, |_, _, _| n_warned += 1);
+            }
+        }
+        assert_eq!(n_warned, 1);
+        assert_eq!(&hooked.lock().unwrap()[..], &[("runs".to_string(), "run".to_string(), 3)]);
+    }
+
+    #[test]
+    fn it_doesnt_dedup_against_a_value_dropped_by_the_rate_limit() {
+        let (builder, sink) = memory_builder();
+        let influx = builder
+            .rate_limit(2.0)
+            .dedup_gauges(&["g"], Duration::from_secs(60))
+            .flush_interval(Duration::from_millis(10))
+            .try_build()
+            .unwrap();
+        let g = || OwnedMeasurement::new("g").add_field("v", OwnedValue::Integer(5));
+        influx.send(OwnedMeasurement::new("other").add_field("n", OwnedValue::Integer(1))).unwrap();
+        influx.send(OwnedMeasurement::new("other").add_field("n", OwnedValue::Integer(2))).unwrap();
+        // no tokens left: dropped
+        influx.send(g()).unwrap();
+        wait_until(Duration::from_secs(10), "g is dropped", || influx.stats().n_dropped == 1);
+        // written once the bucket refills, rather than skipped as a repeat
+        // of the dropped value (and skipped as a repeat of that after)
+        wait_until(Duration::from_secs(10), "g is written", || {
+            influx.send(g()).unwrap();
+            !sink.filter(|p| p.key == "g").is_empty()
+        });
+        influx.close(Duration::from_secs(5)).unwrap();
+        assert_eq!(sink.filter(|p| p.key == "other").len(), 2);
+        assert_eq!(sink.filter(|p| p.key == "g").len(), 1);
+    }
+
+    #[test]
+    fn it_sends_several_backlog_batches_at_once() {
+        // after the outage, each write takes up to 50ms, ending early once
+        // four are in progress
+        let sink = FakeSink { hold: Some((4, Duration::from_millis(50))), ..FakeSink::down() };
+        let influx = InfluxWriter::builder("localhost", "test")
+            .sink(sink.clone())
+            .retry_policy(RetryPolicy { max_attempts: 1, ..Default::default() })
+            .max_request_bytes(MAX_BATCH_BYTES, 4)
+            .buffer_size(0)
+            .try_build()
+            .unwrap();
+        for i in 0..8 {
+            influx.send(OwnedMeasurement::new("a").add_field("n", OwnedValue::Integer(i))).unwrap();
+        }
+        wait_until(Duration::from_secs(10), "every batch has failed", || sink.n_failed.load(Ordering::SeqCst) >= 8);
+        sink.set_down(false);
+        wait_until(Duration::from_secs(10), "four backlog batches are sent at once", || sink.max_writing.load(Ordering::SeqCst) >= 4);
+        influx.close(Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn it_replays_the_spool_when_idle_after_an_outage() {
+        let dir = std::env::temp_dir().join(format!("influx-writer-idle-replay-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let sink = FakeSink::down();
+        let influx = InfluxWriter::builder("localhost", "test")
+            .sink(sink.clone())
+            .retry_policy(RetryPolicy { max_attempts: 1, ..Default::default() })
+            .spool_dir(&dir)
+            .buffer_size(0)
+            .try_build()
+            .unwrap();
+        influx.send(OwnedMeasurement::new("a").add_field("n", OwnedValue::Integer(1))).unwrap();
+        wait_until(Duration::from_secs(10), "the failed batch is spooled", || {
+            std::fs::read_dir(&dir)
+                .map(|mut files| files.any(|f| f.map(|f| f.path().extension() == Some("lp".as_ref())).unwrap_or(false)))
+                .unwrap_or(false)
+        });
+        assert!(sink.batches().is_empty());
+
+        // the server recovers, but nothing else is sent
+        sink.set_down(false);
+        wait_until(Duration::from_secs(10), "the spool is replayed", || !sink.batches().is_empty());
+        assert_eq!(sink.lines().iter().filter(|l| l.starts_with("a ")).count(), 1);
+        influx.close(Duration::from_secs(5)).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn it_refills_the_token_bucket_at_the_configured_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, start);
+        assert!(bucket.take(start));
+        assert!(bucket.take(start));
+        assert!(!bucket.take(start));
+        assert!(bucket.take(start + Duration::from_millis(500)));
+        assert!(!bucket.take(start + Duration::from_millis(600)));
+        // tokens don't accumulate past one second's worth
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.take(later) && bucket.take(later));
+        assert!(!bucket.take(later));
+    }
+
+    #[test]
+    fn it_backs_off_exponentially_up_to_max_delay() {
+        let retry = RetryPolicy {
+            max_attempts: 100,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+        };
+        assert_eq!(retry.delay(0), Duration::from_secs(0));
+        assert_eq!(retry.delay(1), Duration::from_millis(500));
+        assert_eq!(retry.delay(2), Duration::from_secs(1));
+        assert_eq!(retry.delay(3), Duration::from_secs(2));
+        assert_eq!(retry.delay(5), Duration::from_secs(8));
+        assert_eq!(retry.delay(6), Duration::from_secs(10));
+        assert_eq!(retry.delay(64), Duration::from_secs(10));
+    }
+
     #[test]
     fn it_clones_an_influx_writer_to_check_both_drop() {
         let influx = InfluxWriter::default();
@@ -1321,6 +4951,74 @@ mod tests {
         assert_eq!(meas.timestamp, Some(time));
     }
 
+    #[test]
+    fn it_serializes_keys_known_only_at_runtime() {
+        let names: Vec<String> = "svc.latency,p99".split(',').map(String::from).collect();
+        let meas = OwnedMeasurement::new(names[0].clone())
+            .add_tag(names[1].clone(), "a")
+            .add_field(names[1].clone(), OwnedValue::Integer(1))
+            .set_timestamp(1);
+        assert!(matches!(meas.fields[0].0, Cow::Owned(_)));
+        let mut line = String::new();
+        serialize_owned(&meas, &mut line);
+        assert_eq!(line, "svc.latency,p99=a p99=1i 1");
+    }
+
+    #[test]
+    fn it_writes_datetime_fields_as_nanos_or_rfc3339() {
+        let at = nanos_utc(1_500_000_000_005_000_000);
+        let meas: OwnedMeasurement = measure!(@make_meas fills, dt(exchange_time, at), tm(1));
+        assert_eq!(meas.get_field("exchange_time"), Some(&OwnedValue::DateTime(at)));
+        let mut line = String::new();
+        serialize_owned(&meas, &mut line);
+        assert_eq!(line, "fills exchange_time=1500000000005000000i 1");
+        let opts = SerializeOptions { datetime_format: DateTimeFormat::Rfc3339, ..Default::default() };
+        line.clear();
+        serialize_owned_with(&meas, &mut line, &opts);
+        assert_eq!(line, r#"fills exchange_time="2017-07-14T02:40:00.005Z" 1"#);
+        let before_epoch = OwnedMeasurement::new("x").add_field("t", OwnedValue::DateTime(nanos_utc(0) - chrono::Duration::nanoseconds(1)));
+        line.clear();
+        serialize_owned(&before_epoch, &mut line);
+        assert_eq!(line, "x t=-1i");
+        // a "never" sentinel doesn't fit in i64 nanos: skipped, not overflowed
+        let never = chrono::NaiveDate::from_ymd_opt(9999, 12, 31).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let meas = OwnedMeasurement::new("x").add_field("n", OwnedValue::Integer(1)).add_field("expires", OwnedValue::DateTime(never));
+        line.clear();
+        serialize_owned(&meas, &mut line);
+        assert_eq!(line, "x n=1i");
+        line.clear();
+        serialize_owned_with(&meas, &mut line, &opts);
+        assert_eq!(line, r#"x n=1i,expires="9999-12-31T00:00:00Z""#);
+    }
+
+    #[test]
+    fn it_uses_a_runtime_measurement_name_in_the_measure_macro() {
+        let names = vec!["alpha".to_string(), "beta".to_string()];
+        for name in &names {
+            let meas = measure!(@make_meas @name(format!("strategy_{}", name)), t(color, "red"), i(n, 1));
+            assert_eq!(meas.key, format!("strategy_{}", name));
+            assert_eq!(meas.get_tag("color"), Some("red"));
+        }
+        let influx = InfluxWriter::placeholder();
+        measure!(@shed influx, @name(names[0].clone()), i(n, 1));
+    }
+
+    #[test]
+    fn it_only_adds_optional_fields_that_are_some() {
+        let fill_id: Option<Uuid> = None;
+        let fee: Option<f64> = Some(0.25);
+        let meas = measure!(@make_meas fills,
+            t[venue, "plnx"], i[n, 1], of[fee], ou[fill_id],
+            oi[size, Some(3u32)], os[note; None::<String>], ob[maker; Some(true)]
+        );
+        assert_eq!(meas.get_field("fee"), Some(&OwnedValue::Float(0.25)));
+        assert_eq!(meas.get_field("size"), Some(&OwnedValue::Integer(3)));
+        assert_eq!(meas.get_field("maker"), Some(&OwnedValue::Boolean(true)));
+        assert_eq!(meas.get_field("fill_id"), None);
+        assert_eq!(meas.get_field("note"), None);
+        assert_eq!(meas.fields.len(), 4);
+    }
+
     #[test]
     fn it_uses_the_measure_macro_alt_syntax() {
 
@@ -1424,6 +5122,25 @@ mod tests {
         });
     }
 
+    #[cfg(feature = "unstable")]
+    #[bench]
+    fn serialize_owned_with_float_precision(b: &mut Bencher) {
+        let mut buf = String::with_capacity(1024);
+        let opts = SerializeOptions { float_precision: Some(6), ..Default::default() };
+        let m =
+            OwnedMeasurement::new("test")
+                .add_tag("ticker", "xmr_btc")
+                .add_tag("exchange", "plnx")
+                .add_field("price", OwnedValue::Float(0.012345678901))
+                .add_field("amount", OwnedValue::Float(1234.5678901))
+                .add_field("fee", OwnedValue::Float(-0.000012345678901))
+                .set_timestamp(now());
+        b.iter(|| {
+            serialize_owned_with(&m, &mut buf, &opts);
+            buf.clear()
+        });
+    }
+
     #[cfg(feature = "unstable")]
     #[bench]
     fn serialize_owned_simple(b: &mut Bencher) {
@@ -1510,9 +5227,23 @@ mod tests {
         //let drain = slog_term::FullFormat::new(decorator).use_utc_timestamp().build().fuse();
         //let drain = slog_async::Async::new(drain).chan_size(1024 * 64).thread_name("recv".into()).build().fuse();
         //let root = slog::Logger::root(drain, o!("version" => "0.1"));
-        //let influx = InfluxWriter::with_logger_and_opt_creds("localhost", "auth_test", Some(creds), &root);
+        let influx = InfluxWriter::with_logger_and_opt_creds("localhost", "auth_test", Some(creds), &noop_logger);
         measure!(influx, auth_test_meas, i(n, 1));
         drop(influx);
+
+        let influx = InfluxWriter::builder("localhost", "auth_test")
+            .basic_auth("auth_test_user", "hot dog")
+            .build();
+        measure!(influx, auth_test_meas, i(n, 2));
+        drop(influx);
+    }
+
+    #[test]
+    fn it_keeps_the_password_out_of_builder_debug_output() {
+        let builder = InfluxWriter::builder("localhost", "test").basic_auth("user", "hunter2");
+        let debug = format!("{:?}", builder);
+        assert!(debug.contains("user"), "debug = {}", debug);
+        assert!( ! debug.contains("hunter2"), "debug = {}", debug);
     }
 
     #[test]
@@ -1539,6 +5270,108 @@ mod tests {
         assert!( ! buf.contains("nan_d128="));
     }
 
+    #[test]
+    fn it_writes_decimals_in_normalized_fixed_point() {
+        let fixed = |s: &str| {
+            let mut line = String::new();
+            if write_fixed_point(s, &mut line) { Some(line) } else { None }
+        };
+        assert_eq!(fixed("1.2E+3").as_deref(), Some("1200"));
+        assert_eq!(fixed("12.345E+1").as_deref(), Some("123.45"));
+        assert_eq!(fixed("5E-7").as_deref(), Some("0.0000005"));
+        assert_eq!(fixed("-1.2500").as_deref(), Some("-1.25"));
+        assert_eq!(fixed("0042.0").as_deref(), Some("42"));
+        assert_eq!(fixed("0.010").as_deref(), Some("0.01"));
+        assert_eq!(fixed("-0E-5").as_deref(), Some("0"));
+        assert_eq!(fixed("1E+6144"), None);
+        assert_eq!(fixed("abc"), None);
+        let mut line = String::new();
+        write_d128(&d128::from_str("3.4560").unwrap(), &mut line);
+        assert_eq!(line, "3.456");
+    }
+
+    #[test]
+    fn it_splits_off_a_measurement_too_large_for_the_batch() {
+        let small = OwnedMeasurement::new("rust_test").add_field("n", OwnedValue::Integer(1));
+        let big = OwnedMeasurement::new("rust_test")
+            .add_field("s", OwnedValue::String("x".repeat(256)));
+        let mut buf = String::new();
+        assert!(append_line(&small, &mut buf, false, 128, &Default::default()).is_none());
+        let before = buf.clone();
+        let line = append_line(&big, &mut buf, true, 128, &Default::default()).unwrap();
+        assert_eq!(buf, before);
+        assert!(line.starts_with("rust_test s=\"xxx"), "line = {}", line);
+        assert!(append_line(&small, &mut buf, true, 128, &Default::default()).is_none());
+        assert_eq!(buf.lines().count(), 2);
+    }
+
+    #[test]
+    fn it_limits_float_precision_when_configured() {
+        let m = OwnedMeasurement::new("test")
+            .add_field("a", OwnedValue::Float(1.23456789))
+            .add_field("b", OwnedValue::Float(123456789.0))
+            .add_field("c", OwnedValue::Float(-0.000123456789))
+            .add_field("d", OwnedValue::Float(0.5));
+        let opts = SerializeOptions { float_precision: Some(6), ..Default::default() };
+        let mut buf = String::new();
+        serialize_owned_with(&m, &mut buf, &opts);
+        assert_eq!(buf, "test a=1.23457,b=123457000,c=-0.000123457,d=0.5");
+
+        assert_eq!(round_sig(1.0e-300 / 3.0, 2), 3.3e-301);
+        assert_eq!(round_sig(-f64::MAX, 1), -f64::MAX);
+        assert_eq!(round_sig(0.1 + 0.2, 16), 0.3);
+        assert_eq!(round_sig(0.1 + 0.2, 17), 0.1 + 0.2);
+        assert_eq!(round_sig(f64::MIN_POSITIVE, 0), 2e-308);
+    }
+
+    #[test]
+    fn it_adds_default_tags_missing_from_a_measurement() {
+        let opts = InfluxWriterBuilder::new("localhost", "test")
+            .default_tag("host", "a")
+            .default_tag("env", "dev")
+            .default_tag("env", "prod")
+            .serialize_opts;
+        let mut buf = String::new();
+        serialize_owned_with(&OwnedMeasurement::new("test").add_tag("host", "b").add_field("n", OwnedValue::Integer(1)), &mut buf, &opts);
+        assert_eq!(buf, "test,host=b,env=prod n=1i");
+    }
+
+    #[test]
+    fn it_rejects_names_with_spaces_commas_or_a_leading_underscore() {
+        assert!(is_valid_name("trades"));
+        assert!(is_valid_name("app.trades_v2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("_trades"));
+        assert!(!is_valid_name("all trades"));
+        assert!(!is_valid_name("trades,v2"));
+    }
+
+    #[test]
+    fn it_prepends_the_key_prefix() {
+        let opts = SerializeOptions { key_prefix: "prod_".to_string(), ..Default::default() };
+        let m = OwnedMeasurement::new("trades").add_tag("venue", "x").add_field("n", OwnedValue::Integer(1)).set_timestamp(1);
+        let mut line = String::new();
+        serialize_owned_with(&m, &mut line, &opts);
+        assert_eq!(line, "prod_trades,venue=x n=1i 1");
+    }
+
+    #[test]
+    fn it_finds_the_series_of_a_serialized_line() {
+        assert_eq!(series_of_line("a,b=c d=1i"), "a,b=c");
+        assert_eq!(series_of_line("a,b=c\\ d e=1i"), "a,b=c\\ d");
+        assert_eq!(series_of_line("a"), "a");
+    }
+
+    #[test]
+    fn it_saves_and_loads_gauges_for_warm_up() {
+        let path = std::env::temp_dir().join(format!("influx-writer-gauges-{}.txt", Uuid::new_v4()));
+        assert!(load_gauges(&path).unwrap().is_empty());
+        let lines = vec!["position,ticker=btc_usd qty=1.5".to_string(), "balance usd=100".to_string()];
+        save_gauges(&path, lines.iter()).unwrap();
+        assert_eq!(load_gauges(&path).unwrap(), lines);
+        fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn it_supplies_a_field_if_every_field_is_skipped_because_nan() {
         assert!(SKIP_NAN_VALUES, "otherwise this test is worthless");
@@ -0,0 +1,166 @@
+//! Streams (a sample of) the lines sent by a writer to websocket clients,
+//! for "tailing" production telemetry with a browser or `wscat`, e.g.
+//!
+//! ```console
+//! wscat -c ws://localhost:9999
+//! ```
+//!
+//! Requires the "live-tail" feature.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+use slog::Logger;
+use tungstenite::Message;
+use crate::Error;
+
+/// max lines queued per client before lines are dropped for that client
+const CLIENT_QUEUE_SIZE: usize = 4096;
+
+/// Stops listening and disconnects its clients when dropped, i.e. when
+/// the writer thread exits.
+///
+pub(crate) struct LiveTail {
+    clients: Arc<Mutex<Vec<Sender<String>>>>,
+    sample_every: usize,
+    n: AtomicUsize,
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl LiveTail {
+    /// Listens for websocket connections on `addr`. Every `sample_every`th
+    /// line passed to `publish` is sent to each connected client.
+    ///
+    pub fn bind(addr: &str, sample_every: usize, logger: &Logger) -> Result<Self, Error> {
+        let listener = TcpListener::bind(addr)
+            .map_err(|e| Error::Config(format!("failed to bind live tail listener to {}: {}", addr, e)))?;
+        let local_addr = listener.local_addr()
+            .map_err(|e| Error::Config(format!("failed to bind live tail listener to {}: {}", addr, e)))?;
+        let clients: Arc<Mutex<Vec<Sender<String>>>> = Arc::new(Mutex::new(Vec::new()));
+        let logger = logger.new(o!("thread" => "InfluxWriter:live-tail"));
+        info!(logger, "live tail listening for websocket connections"; "addr" => addr);
+        let accept_clients = Arc::clone(&clients);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&shutdown);
+        let accept = thread::Builder::new().name("inflx-tail".to_string()).spawn(move || {
+            for stream in listener.incoming() {
+                // woken by `drop` connecting to the listener
+                if stop.load(Ordering::Acquire) { return }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!(logger, "live tail: failed to accept connection: {}", e);
+                        continue
+                    }
+                };
+                let (tx, rx) = bounded(CLIENT_QUEUE_SIZE);
+                let client_logger = logger.clone();
+                let spawned = thread::Builder::new().name("inflx-tail-client".to_string()).spawn(move || {
+                    serve_client(stream, rx, &client_logger)
+                });
+                match spawned {
+                    Ok(_) => accept_clients.lock().unwrap_or_else(|e| e.into_inner()).push(tx),
+                    Err(e) => warn!(logger, "live tail: failed to spawn client thread: {}", e),
+                }
+            }
+        }).map_err(Error::Spawn)?;
+        Ok(LiveTail {
+            clients,
+            sample_every: sample_every.max(1),
+            n: AtomicUsize::new(0),
+            addr: local_addr,
+            shutdown,
+            accept: Some(accept),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Sends the sampled lines of `batch` to connected clients. Lines are
+    /// dropped for any client that is falling behind.
+    ///
+    pub fn publish(&self, batch: &str) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if clients.is_empty() { return }
+        for line in batch.lines() {
+            let i = self.n.fetch_add(1, Ordering::Relaxed) % self.sample_every;
+            if i > 0 { continue }
+            clients.retain(|tx| {
+                match tx.try_send(line.to_string()) {
+                    Ok(()) | Err(TrySendError::Full(_)) => true,
+                    Err(TrySendError::Disconnected(_)) => false,
+                }
+            });
+        }
+    }
+}
+
+impl Drop for LiveTail {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Release);
+        // wake the accept thread, which closes the listener on returning
+        let mut wake = self.addr;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        if TcpStream::connect(wake).is_ok() {
+            if let Some(accept) = self.accept.take() {
+                let _ = accept.join();
+            }
+        }
+        // client threads close their websocket when their queue is dropped
+        self.clients.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+fn serve_client(stream: TcpStream, rx: Receiver<String>, logger: &Logger) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let mut ws = match tungstenite::accept(stream) {
+        Ok(ws) => ws,
+        Err(e) => {
+            warn!(logger, "live tail: websocket handshake failed: {}", e; "peer" => &peer);
+            return
+        }
+    };
+    debug!(logger, "live tail: client connected"; "peer" => &peer);
+    for line in rx.iter() {
+        if let Err(e) = ws.send(Message::Text(line)) {
+            debug!(logger, "live tail: client disconnected: {}", e; "peer" => &peer);
+            return
+        }
+    }
+    let _ = ws.close(None);
+    let _ = ws.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_closes_the_listener_and_clients_when_dropped() {
+        let logger = Logger::root(slog::Discard, o!());
+        let tail = LiveTail::bind("127.0.0.1:0", 1, &logger).unwrap();
+        let addr = tail.local_addr();
+        let (mut client, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+        // wait for the accept thread to register the client
+        while tail.clients.lock().unwrap().is_empty() { thread::yield_now() }
+        tail.publish("test n=1i 1");
+        assert_eq!(client.read().unwrap(), Message::Text("test n=1i 1".to_string()));
+        drop(tail);
+        assert!(matches!(client.read(), Ok(Message::Close(_)) | Err(_)));
+        // the port is free for the next writer
+        let tail = LiveTail::bind(&addr.to_string(), 1, &logger).unwrap();
+        drop(tail);
+    }
+}
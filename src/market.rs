@@ -0,0 +1,149 @@
+//! Standard tags and measurements for trading telemetry, see `MarketTags`
+//! and `book_snapshot`.
+
+use std::fmt;
+use crate::{Key, OwnedMeasurement, OwnedValue};
+
+/// Side of the book an order or trade is on, written as the "side" tag.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+impl Side {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Side::Bid => "bid",
+            Side::Ask => "ask",
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The "exchange", "ticker" and (optionally) "side" tags, so every
+/// measurement in a trading codebase names them the same way.
+///
+/// Added to a measurement with the `mkt` kind of `measure!`, or `add_to`.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate influx_writer;
+///
+/// use influx_writer::{AsF64, MarketTags, OwnedMeasurement, Side};
+///
+/// fn main() {
+///     let mkt = MarketTags::new("plnx", "xmr_btc").side(Side::Bid);
+///     let meas: OwnedMeasurement = measure!(@make_meas fills, mkt(mkt), f(price, 1.2345));
+///     assert_eq!(meas.get_tag("exchange"), Some("plnx"));
+///     assert_eq!(meas.get_tag("ticker"), Some("xmr_btc"));
+///     assert_eq!(meas.get_tag("side"), Some("bid"));
+/// }
+/// ```
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MarketTags {
+    pub exchange: &'static str,
+    pub ticker: &'static str,
+    pub side: Option<Side>,
+}
+
+impl MarketTags {
+    pub const fn new(exchange: &'static str, ticker: &'static str) -> Self {
+        MarketTags { exchange, ticker, side: None }
+    }
+
+    pub const fn side(mut self, side: Side) -> Self {
+        self.side = Some(side);
+        self
+    }
+
+    /// `m` with the tags added.
+    ///
+    pub fn add_to(self, m: OwnedMeasurement) -> OwnedMeasurement {
+        let m = m.add_tag("exchange", self.exchange).add_tag("ticker", self.ticker);
+        match self.side {
+            Some(side) => m.add_tag("side", side.as_str()),
+            None => m,
+        }
+    }
+}
+
+/// Number of levels whose field names `book_snapshot` has as static
+/// strings. The names of deeper levels are allocated for each snapshot.
+///
+pub const MAX_BOOK_DEPTH: usize = 20;
+
+const BID_PRICE: [&str; MAX_BOOK_DEPTH] = ["bp0", "bp1", "bp2", "bp3", "bp4", "bp5", "bp6", "bp7", "bp8", "bp9", "bp10", "bp11", "bp12", "bp13", "bp14", "bp15", "bp16", "bp17", "bp18", "bp19"];
+const BID_SIZE: [&str; MAX_BOOK_DEPTH] = ["bs0", "bs1", "bs2", "bs3", "bs4", "bs5", "bs6", "bs7", "bs8", "bs9", "bs10", "bs11", "bs12", "bs13", "bs14", "bs15", "bs16", "bs17", "bs18", "bs19"];
+const ASK_PRICE: [&str; MAX_BOOK_DEPTH] = ["ap0", "ap1", "ap2", "ap3", "ap4", "ap5", "ap6", "ap7", "ap8", "ap9", "ap10", "ap11", "ap12", "ap13", "ap14", "ap15", "ap16", "ap17", "ap18", "ap19"];
+const ASK_SIZE: [&str; MAX_BOOK_DEPTH] = ["as0", "as1", "as2", "as3", "as4", "as5", "as6", "as7", "as8", "as9", "as10", "as11", "as12", "as13", "as14", "as15", "as16", "as17", "as18", "as19"];
+
+/// One measurement holding the top `depth` levels of an order book, rather
+/// than a measurement per level, with the `MarketTags` as tags.
+///
+/// `bids` and `asks` are (price, size) levels, best first. The fields of
+/// level `n` (from 0) are "bp{n}" and "bs{n}" for the bid price and size,
+/// and "ap{n}" and "as{n}" for the ask; levels beyond the end of a side are
+/// left out.
+///
+/// # Examples
+///
+/// ```
+/// use influx_writer::{book_snapshot, MarketTags, OwnedValue};
+///
+/// let bids = [(99.5, 2.0), (99.0, 5.0)];
+/// let asks = [(100.5, 1.0)];
+/// let m = book_snapshot("book", MarketTags::new("plnx", "xmr_btc"), &bids, &asks, 5);
+/// assert_eq!(m.get_field("bp1"), Some(&OwnedValue::Float(99.0)));
+/// assert_eq!(m.get_field("as0"), Some(&OwnedValue::Float(1.0)));
+/// assert_eq!(m.get_field("ap1"), None);
+/// ```
+///
+pub fn book_snapshot<K: Into<Key>>(key: K, mkt: MarketTags, bids: &[(f64, f64)], asks: &[(f64, f64)], depth: usize) -> OwnedMeasurement {
+    let (bids, asks) = (&bids[..bids.len().min(depth)], &asks[..asks.len().min(depth)]);
+    let mut m = mkt.add_to(OwnedMeasurement::with_capacity(key, 3, (bids.len() + asks.len()) * 2));
+    for (i, &(price, size)) in bids.iter().enumerate() {
+        m = m.add_field(level_key(&BID_PRICE, "bp", i), OwnedValue::Float(price))
+            .add_field(level_key(&BID_SIZE, "bs", i), OwnedValue::Float(size));
+    }
+    for (i, &(price, size)) in asks.iter().enumerate() {
+        m = m.add_field(level_key(&ASK_PRICE, "ap", i), OwnedValue::Float(price))
+            .add_field(level_key(&ASK_SIZE, "as", i), OwnedValue::Float(size));
+    }
+    m
+}
+
+/// The field name for level `i`: from `names` if it's that deep, otherwise
+/// "{prefix}{i}".
+///
+fn level_key(names: &[&'static str; MAX_BOOK_DEPTH], prefix: &str, i: usize) -> Key {
+    match names.get(i) {
+        Some(&name) => Key::Borrowed(name),
+        None => Key::Owned(format!("{}{}", prefix, i)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_writes_levels_deeper_than_the_static_field_names() {
+        let bids: Vec<(f64, f64)> = (0..30).map(|i| (100.0 - i as f64, 1.0)).collect();
+        let m = book_snapshot("book", MarketTags::new("plnx", "xmr_btc"), &bids, &[], 25);
+        assert_eq!(m.fields.len(), 50);
+        assert_eq!(m.get_field("bp19"), Some(&OwnedValue::Float(81.0)));
+        assert_eq!(m.get_field("bp24"), Some(&OwnedValue::Float(76.0)));
+        assert_eq!(m.get_field("bs24"), Some(&OwnedValue::Float(1.0)));
+        assert_eq!(m.get_field("bp25"), None);
+    }
+}
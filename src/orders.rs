@@ -0,0 +1,102 @@
+//! Standard measurements of an order's lifecycle, so every trading service
+//! writes them with the same names, tags and fields. Requires the "orders"
+//! feature.
+//!
+//! Each helper writes one measurement, named for the event, with the
+//! order's `MarketTags` as tags and its id as the "order_id" field:
+//!
+//! - "order_submitted": "price", "qty"
+//! - "order_acked": "ack_latency_ns", the time since the order was created
+//! - "order_filled": "price" and "qty" of the fill, "order_price"
+//! - "order_rejected": "reason"
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use influx_writer::{InfluxWriter, MarketTags, Side};
+//! use influx_writer::orders::{self, Order};
+//! use uuid::Uuid;
+//!
+//! let influx = InfluxWriter::new("localhost", "test");
+//! let order = Order::new(MarketTags::new("plnx", "xmr_btc").side(Side::Bid), Uuid::new_v4(), 0.0123, 10.0);
+//! orders::order_submitted(&influx, &order).unwrap();
+//! orders::order_acked(&influx, &order).unwrap();
+//! orders::order_filled(&influx, &order, 0.0123, 4.0).unwrap();
+//! ```
+
+use std::time::Instant;
+use uuid::Uuid;
+use crate::{Error, InfluxWriter, MarketTags, OwnedMeasurement, OwnedValue};
+
+/// What the helpers need to know about an order.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Order {
+    pub mkt: MarketTags,
+    pub id: Uuid,
+    pub price: f64,
+    pub qty: f64,
+    /// when the order was created, for "ack_latency_ns"
+    pub created: Instant,
+}
+
+impl Order {
+    pub fn new(mkt: MarketTags, id: Uuid, price: f64, qty: f64) -> Self {
+        Order { mkt, id, price, qty, created: Instant::now() }
+    }
+
+    fn measurement(&self, influx: &InfluxWriter, key: &'static str) -> OwnedMeasurement {
+        self.mkt.add_to(influx.acquire(key)).add_field("order_id", OwnedValue::Uuid(self.id))
+    }
+}
+
+pub fn order_submitted(influx: &InfluxWriter, order: &Order) -> Result<(), Error> {
+    influx.send(order.measurement(influx, "order_submitted")
+        .add_field("price", OwnedValue::Float(order.price))
+        .add_field("qty", OwnedValue::Float(order.qty)))
+}
+
+pub fn order_acked(influx: &InfluxWriter, order: &Order) -> Result<(), Error> {
+    influx.send(order.measurement(influx, "order_acked")
+        .add_field("ack_latency_ns", OwnedValue::Integer(order.created.elapsed().as_nanos() as i64)))
+}
+
+/// A fill (or partial fill) of `qty` at `price`.
+///
+pub fn order_filled(influx: &InfluxWriter, order: &Order, price: f64, qty: f64) -> Result<(), Error> {
+    influx.send(order.measurement(influx, "order_filled")
+        .add_field("price", OwnedValue::Float(price))
+        .add_field("qty", OwnedValue::Float(qty))
+        .add_field("order_price", OwnedValue::Float(order.price)))
+}
+
+pub fn order_rejected(influx: &InfluxWriter, order: &Order, reason: &str) -> Result<(), Error> {
+    influx.send(order.measurement(influx, "order_rejected")
+        .add_field("reason", OwnedValue::String(influx.acquire_string(reason))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::bounded;
+    use crate::{Key, Side};
+    use super::*;
+
+    #[test]
+    fn it_writes_consistent_measurements_for_each_event() {
+        let (tx, rx) = bounded(8);
+        let influx = InfluxWriter::test_writer(tx);
+        let order = Order::new(MarketTags::new("plnx", "xmr_btc").side(Side::Ask), Uuid::new_v4(), 1.5, 10.0);
+        order_submitted(&influx, &order).unwrap();
+        order_acked(&influx, &order).unwrap();
+        order_filled(&influx, &order, 1.5, 4.0).unwrap();
+        order_rejected(&influx, &order, "post only").unwrap();
+        let keys: Vec<Key> = rx.try_iter().map(|m| {
+            let m = m.unwrap();
+            assert_eq!(m.get_tag("ticker"), Some("xmr_btc"));
+            assert_eq!(m.get_tag("side"), Some("ask"));
+            assert_eq!(m.get_field("order_id"), Some(&OwnedValue::Uuid(order.id)));
+            m.key
+        }).collect();
+        assert_eq!(keys, vec!["order_submitted", "order_acked", "order_filled", "order_rejected"]);
+    }
+}
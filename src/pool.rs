@@ -0,0 +1,104 @@
+//! Recycling of measurements and their strings between the writer thread
+//! and producers, enabled with `InfluxWriterBuilder::measurement_pool`.
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+use crate::{Key, OwnedMeasurement, OwnedValue};
+
+/// pooled strings per pooled measurement
+const STRINGS_PER_MEASUREMENT: usize = 4;
+
+/// strings that have grown larger than this are dropped rather than pooled
+const MAX_STRING_CAPACITY: usize = 1024;
+
+/// Measurements and strings returned by the writer thread once serialized,
+/// to be reused by `InfluxWriter::acquire` and `acquire_string` instead of
+/// allocating new ones. When the pool is empty, new ones are allocated;
+/// when it is full, returned ones are dropped.
+///
+#[derive(Debug)]
+pub(crate) struct MeasurementPool {
+    measurements: (Sender<OwnedMeasurement>, Receiver<OwnedMeasurement>),
+    strings: (Sender<String>, Receiver<String>),
+}
+
+impl MeasurementPool {
+    pub fn new(capacity: usize) -> Self {
+        MeasurementPool {
+            measurements: bounded(capacity),
+            strings: bounded(capacity * STRINGS_PER_MEASUREMENT),
+        }
+    }
+
+    pub fn measurement<K: Into<Key>>(&self, key: K) -> OwnedMeasurement {
+        match self.measurements.1.try_recv() {
+            Ok(mut m) => {
+                m.key = key.into();
+                m
+            }
+
+            Err(_) => OwnedMeasurement::new(key),
+        }
+    }
+
+    pub fn string(&self, value: &str) -> String {
+        match self.strings.1.try_recv() {
+            Ok(mut s) => {
+                s.push_str(value);
+                s
+            }
+
+            Err(_) => value.to_string(),
+        }
+    }
+
+    /// Clears `m`, returning it and its strings to the pool.
+    ///
+    pub fn recycle(&self, mut m: OwnedMeasurement) {
+        #[cfg(feature = "string-tags")]
+        for (_, value) in m.tags.drain() {
+            self.recycle_string(value);
+        }
+        #[cfg(not(feature = "string-tags"))]
+        m.tags.clear();
+        for (_, value) in m.fields.drain() {
+            if let OwnedValue::String(s) = value {
+                self.recycle_string(s);
+            }
+        }
+        m.timestamp = None;
+        let _ = self.measurements.0.try_send(m);
+    }
+
+    fn recycle_string(&self, mut s: String) {
+        if s.capacity() > 0 && s.capacity() <= MAX_STRING_CAPACITY {
+            s.clear();
+            let _ = self.strings.0.try_send(s);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reuses_recycled_measurements_and_strings() {
+        let pool = MeasurementPool::new(1);
+        let s = pool.string("abc");
+        let ptr = s.as_ptr();
+        let m = pool.measurement("a")
+            .add_field("s", OwnedValue::String(s))
+            .set_timestamp(1);
+        pool.recycle(m);
+        let m = pool.measurement("b");
+        assert_eq!(m.key, "b");
+        assert!(m.fields.is_empty());
+        assert!(m.timestamp.is_none());
+        let s = pool.string("xyz");
+        assert_eq!(s, "xyz");
+        assert_eq!(s.as_ptr(), ptr);
+        // pool is empty again
+        assert_eq!(pool.string("new"), "new");
+        assert_eq!(pool.measurement("c").key, "c");
+    }
+}
@@ -0,0 +1,201 @@
+//! Re-sending line protocol files, e.g. to backfill a gap after an outage
+//! from the spool or a `FileSink`'s files.
+//!
+//! Lines are written to a `Sink` as they are, rather than through an
+//! `InfluxWriter`: its queue carries measurements, so each line would be
+//! parsed only to be serialized again, and would come out with the
+//! writer's default tags and key prefix added to what was already written.
+
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::{EncryptionKey, Error, FileCompression, RetryPolicy, Sink};
+use crate::compression;
+
+/// Reads line protocol files and writes their lines to a sink in
+/// timestamp order, in batches, at a limited rate.
+///
+/// Comment lines (starting with "#", as in a dead letter file) and blank
+/// lines are skipped. Lines without a timestamp are written last. Files
+/// ending in ".gz", ".zst" or ".sz" are decompressed (requires the "gzip",
+/// "zstd" or "snappy" feature, see `FileCompression`), and encrypted files
+/// are decrypted given their `key`.
+///
+/// Since influxdb overwrites a point with the same series and timestamp,
+/// a replay that fails part way through can safely be run again.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use influx_writer::{MemorySink, Replay};
+///
+/// let sink = MemorySink::new(Duration::from_secs(3600));
+/// let stats = Replay::new()
+///     .max_lines_per_sec(50_000.0)
+///     .run(&["/var/spool/influx/00000000000000000000.lp"], &sink)
+///     .unwrap();
+/// println!("replayed {} lines", stats.n_lines);
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct Replay {
+    batch_size: usize,
+    max_lines_per_sec: Option<f64>,
+    retry: RetryPolicy,
+    key: Option<EncryptionKey>,
+}
+
+/// Counts of what a `Replay` wrote.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    pub n_lines: usize,
+    pub n_batches: usize,
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Replay { batch_size: crate::DEFAULT_BUFFER_SIZE, max_lines_per_sec: None, retry: RetryPolicy::default(), key: None }
+    }
+}
+
+impl Replay {
+    pub fn new() -> Self { Self::default() }
+
+    /// Lines written per batch (default: `DEFAULT_BUFFER_SIZE`).
+    ///
+    pub fn batch_size(mut self, n: usize) -> Self {
+        self.batch_size = n.max(1);
+        self
+    }
+
+    /// Upper bound on the rate lines are written at (default: none), to
+    /// avoid overwhelming a server that is also taking live writes.
+    ///
+    pub fn max_lines_per_sec(mut self, n: f64) -> Self {
+        self.max_lines_per_sec = Some(n);
+        self
+    }
+
+    /// How failed batches are retried (default: `RetryPolicy::default()`).
+    ///
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Decrypt encrypted files (see `InfluxWriterBuilder::encryption_key`)
+    /// with `key`. Requires the "encryption" feature.
+    ///
+    #[cfg(feature = "encryption")]
+    pub fn key(mut self, key: EncryptionKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Writes the lines of every file in `paths` to `sink`. Stops at the
+    /// first batch that fails after retries, or the first file that can't
+    /// be read.
+    ///
+    pub fn run<P: AsRef<Path>, S: Sink + ?Sized>(&self, paths: &[P], sink: &S) -> Result<ReplayStats, Error> {
+        let mut contents = Vec::with_capacity(paths.len());
+        for path in paths {
+            contents.push(read(path.as_ref(), self.key.as_ref())?);
+        }
+        let mut lines: Vec<(i64, &str)> = contents.iter()
+            .flat_map(|text| text.lines())
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| (timestamp(line).unwrap_or(i64::MAX), line))
+            .collect();
+        lines.sort_by_key(|(timestamp, _)| *timestamp); // stable, so file order breaks ties
+
+        let start = Instant::now();
+        let mut stats = ReplayStats::default();
+        let mut batch = String::new();
+        for chunk in lines.chunks(self.batch_size) {
+            if let Some(per_sec) = self.max_lines_per_sec {
+                let due = Duration::from_secs_f64(stats.n_lines as f64 / per_sec);
+                if let Some(wait) = due.checked_sub(start.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+            batch.clear();
+            for (i, (_, line)) in chunk.iter().enumerate() {
+                if i > 0 { batch.push('\n') }
+                batch.push_str(line);
+            }
+            self.write(sink, &batch)
+                .map_err(|e| match e {
+                    Error::Transport { status, message } => {
+                        Error::Transport { status, message: format!("replay stopped after {} lines: {}", stats.n_lines, message) }
+                    }
+                    e => e,
+                })?;
+            stats.n_lines += chunk.len();
+            stats.n_batches += 1;
+        }
+        Ok(stats)
+    }
+
+    fn write<S: Sink + ?Sized>(&self, sink: &S, batch: &str) -> Result<(), Error> {
+        let mut n_req = 0;
+        loop {
+            match sink.write(batch) {
+                Err(Error::Transport { status: Some(status), message }) if status < 500 => {
+                    return Err(Error::Transport { status: Some(status), message })
+                }
+
+                Err(_) if n_req + 1 < self.retry.max_attempts.max(1) => {
+                    n_req += 1;
+                    thread::sleep(self.retry.delay(n_req));
+                }
+
+                result => return result,
+            }
+        }
+    }
+}
+
+fn read(path: &Path, key: Option<&EncryptionKey>) -> Result<String, Error> {
+    FileCompression::of_path(path).map_err(Error::Config)?;
+    compression::read_to_string(path, key)
+        .map_err(|e| Error::Io(io::Error::new(e.kind(), format!("failed to read {}: {}", path.display(), e))))
+}
+
+/// The timestamp at the end of `line`, if it has one (a line without one
+/// ends with a field, which can't parse as an integer).
+///
+fn timestamp(line: &str) -> Option<i64> {
+    line.rsplit(' ').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+    use crate::MemorySink;
+    use super::*;
+
+    #[test]
+    fn it_replays_lines_from_several_files_in_timestamp_order() {
+        let dir = std::env::temp_dir().join(format!("influx-writer-replay-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let now = crate::now();
+        fs::write(dir.join("a.lp"), format!("a n=3i {}\n# a comment\na n=1i {}\n", now + 3, now + 1)).unwrap();
+        fs::write(dir.join("b.lp"), format!("b n=2i {}\n\nb s=\"x 4\" {}\n", now + 2, now + 4)).unwrap();
+        let sink = MemorySink::new(Duration::from_secs(60));
+        let stats = Replay::new().batch_size(3).run(&[dir.join("a.lp"), dir.join("b.lp")], &sink).unwrap();
+        assert_eq!(stats, ReplayStats { n_lines: 4, n_batches: 2 });
+        let timestamps: Vec<i64> = sink.points().iter().map(|p| p.timestamp - now).collect();
+        assert_eq!(timestamps, vec![1, 2, 3, 4]);
+        match Replay::new().run(&[dir.join("missing.lp")], &sink) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            other => panic!("unexpected: {:?}", other),
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
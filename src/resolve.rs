@@ -0,0 +1,266 @@
+//! Resolving the server's hostname, see `InfluxWriterBuilder::resolver`.
+//!
+//! hyper resolves the host each time it opens a connection, but the
+//! writer keeps connections alive between requests, so a writer pointed at
+//! a DNS-load-balanced or failover endpoint can keep sending to an address
+//! the name no longer resolves to. With a resolver configured, connections
+//! are opened to addresses from a cache that is refreshed when they expire
+//! (per the record's TTL, if the resolver knows it), and kept-alive
+//! connections to addresses that dropped out are closed.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use hyper::net::{HttpStream, NetworkConnector};
+use slog::Logger;
+
+/// The addresses a hostname resolved to.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    pub addrs: Vec<IpAddr>,
+    /// how long the addresses may be cached, if known (e.g. the TTL of the
+    /// DNS records). Otherwise they are kept for the writer's
+    /// `re_resolve_interval`.
+    pub ttl: Option<Duration>,
+}
+
+/// Resolves the server's hostname to its addresses.
+///
+/// `resolve` is called from the threads sending batches, so it should
+/// return (or fail) promptly.
+///
+pub trait Resolver: Send + Sync + 'static {
+    fn resolve(&self, host: &str) -> io::Result<Resolved>;
+}
+
+/// Resolves with the system resolver (`getaddrinfo`), which doesn't report
+/// TTLs.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str) -> io::Result<Resolved> {
+        let addrs = (host, 0).to_socket_addrs()?.map(|addr| addr.ip()).collect();
+        Ok(Resolved { addrs, ttl: None })
+    }
+}
+
+/// Cached addresses are kept at least this long, whatever the TTL, so a
+/// TTL of zero doesn't mean a lookup per connection.
+///
+const MIN_TTL: Duration = Duration::from_secs(1);
+
+struct Entry {
+    addrs: Vec<IpAddr>,
+    /// `None` for ip literals, which are never re-resolved
+    expires: Option<Instant>,
+}
+
+impl Entry {
+    fn expired(&self, now: Instant) -> bool {
+        self.expires.map(|t| t <= now).unwrap_or(false)
+    }
+}
+
+struct Inner {
+    resolver: Arc<dyn Resolver>,
+    interval: Duration,
+    cache: Mutex<HashMap<String, Entry>>,
+    logger: Logger,
+}
+
+/// A hyper connector opening connections to cached addresses, shared by the
+/// writer's http clients.
+///
+#[derive(Clone)]
+pub(crate) struct Resolving(Arc<Inner>);
+
+impl Resolving {
+    pub fn new(resolver: Arc<dyn Resolver>, interval: Duration, logger: &Logger) -> Self {
+        Resolving(Arc::new(Inner {
+            resolver,
+            interval,
+            cache: Mutex::new(HashMap::new()),
+            logger: logger.new(o!("thread" => "InfluxWriter:resolve")),
+        }))
+    }
+
+    /// Resolves `host`, which previously resolved to `addrs`, into a new
+    /// entry, keeping `addrs` if the lookup fails or finds none. Called
+    /// without the cache locked, since the lookup may block.
+    ///
+    fn refresh(&self, host: &str, addrs: Vec<IpAddr>, now: Instant) -> Entry {
+        let Inner { ref resolver, interval, ref logger, .. } = *self.0;
+        match resolver.resolve(host) {
+            Ok(Resolved { addrs: resolved, .. }) if resolved.is_empty() && !addrs.is_empty() => {
+                warn!(logger, "InfluxWriter: host resolved to no addresses, keeping the previous ones"; "host" => host);
+                Entry { addrs, expires: Some(now + interval) }
+            }
+
+            Ok(Resolved { addrs: resolved, ttl }) => {
+                if resolved != addrs {
+                    info!(logger, "InfluxWriter: host resolved to new addresses"; "host" => host,
+                        "old" => ?addrs, "new" => ?resolved, "ttl" => ?ttl);
+                }
+                Entry { addrs: resolved, expires: Some(now + ttl.unwrap_or(interval).max(MIN_TTL)) }
+            }
+
+            Err(e) => {
+                warn!(logger, "InfluxWriter: failed to resolve host, keeping the previous addresses: {}", e; "host" => host,
+                    "addrs" => ?addrs);
+                Entry { addrs, expires: Some(now + interval) }
+            }
+        }
+    }
+
+    fn cache(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+        self.0.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The addresses of `host`, resolving it if it isn't cached or its
+    /// addresses have expired.
+    ///
+    pub fn lookup(&self, host: &str) -> Vec<IpAddr> {
+        // ipv6 hosts come from urls in brackets
+        let host = crate::host::unbracket(host);
+        let now = Instant::now();
+        let previous = {
+            let mut cache = self.cache();
+            let entry = cache.entry(host.to_string()).or_insert_with(|| match host.parse() {
+                Ok(ip) => Entry { addrs: vec![ip], expires: None },
+                Err(_) => Entry { addrs: Vec::new(), expires: Some(now) },
+            });
+            if !entry.expired(now) { return entry.addrs.clone() }
+            entry.addrs.clone()
+        };
+        let entry = self.refresh(host, previous, now);
+        let addrs = entry.addrs.clone();
+        self.cache().insert(host.to_string(), entry);
+        addrs
+    }
+
+    /// Whether `ip` is a current address of any host connected to,
+    /// re-resolving any host whose addresses have expired.
+    ///
+    pub fn is_current(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let expired: Vec<(String, Vec<IpAddr>)> = self.cache().iter()
+            .filter(|(_, entry)| entry.expired(now))
+            .map(|(host, entry)| (host.clone(), entry.addrs.clone()))
+            .collect();
+        let refreshed: Vec<(String, Entry)> = expired.into_iter()
+            .map(|(host, addrs)| {
+                let entry = self.refresh(&host, addrs, now);
+                (host, entry)
+            })
+            .collect();
+        let mut cache = self.cache();
+        cache.extend(refreshed);
+        cache.is_empty() || cache.values().any(|entry| entry.addrs.contains(&ip))
+    }
+}
+
+impl NetworkConnector for Resolving {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<HttpStream> {
+        if scheme != "http" {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid scheme for Http").into())
+        }
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("{} resolved to no addresses", host));
+        for ip in self.lookup(host) {
+            match TcpStream::connect(SocketAddr::new(ip, port)) {
+                Ok(stream) => return Ok(HttpStream(stream)),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::RwLock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    struct FakeResolver {
+        resolved: RwLock<Resolved>,
+        n_lookups: AtomicUsize,
+    }
+
+    impl Resolver for FakeResolver {
+        fn resolve(&self, _: &str) -> io::Result<Resolved> {
+            self.n_lookups.fetch_add(1, Ordering::Relaxed);
+            Ok(self.resolved.read().unwrap().clone())
+        }
+    }
+
+    #[test]
+    fn it_re_resolves_when_the_ttl_expires() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let resolver = Arc::new(FakeResolver {
+            resolved: RwLock::new(Resolved { addrs: vec![localhost], ttl: Some(MIN_TTL) }),
+            n_lookups: AtomicUsize::new(0),
+        });
+        let logger = Logger::root(slog::Discard, o!());
+        let resolving = Resolving::new(Arc::clone(&resolver) as Arc<dyn Resolver>, Duration::from_secs(60), &logger);
+
+        let mut stream = resolving.connect("influx.example", port, "http").unwrap();
+        assert_eq!(hyper::net::NetworkStream::peer_addr(&mut stream).unwrap().ip(), localhost);
+        assert!(resolving.connect("influx.example", port, "https").is_err());
+        assert_eq!(resolving.lookup("influx.example"), vec![localhost]);
+        assert_eq!(resolver.n_lookups.load(Ordering::Relaxed), 1);
+        assert!(resolving.is_current(localhost));
+
+        let moved: IpAddr = "10.1.2.3".parse().unwrap();
+        resolver.resolved.write().unwrap().addrs = vec![moved];
+        std::thread::sleep(MIN_TTL);
+        assert!(!resolving.is_current(localhost));
+        assert_eq!(resolving.lookup("influx.example"), vec![moved]);
+        assert_eq!(resolver.n_lookups.load(Ordering::Relaxed), 2);
+
+        // ip literals aren't passed to the resolver
+        assert_eq!(resolving.lookup("[::1]"), vec!["::1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(resolver.n_lookups.load(Ordering::Relaxed), 2);
+    }
+
+    /// blocks in `resolve` until `release` is sent on
+    struct BlockingResolver {
+        entered: crossbeam_channel::Sender<()>,
+        release: crossbeam_channel::Receiver<()>,
+    }
+
+    impl Resolver for BlockingResolver {
+        fn resolve(&self, _: &str) -> io::Result<Resolved> {
+            let _ = self.entered.send(());
+            let _ = self.release.recv();
+            Ok(Resolved { addrs: vec!["10.1.2.3".parse().unwrap()], ttl: None })
+        }
+    }
+
+    #[test]
+    fn it_doesnt_hold_the_cache_lock_while_resolving() {
+        let (entered_tx, entered_rx) = crossbeam_channel::bounded(1);
+        let (release_tx, release_rx) = crossbeam_channel::bounded(1);
+        let resolver = Arc::new(BlockingResolver { entered: entered_tx, release: release_rx });
+        let logger = Logger::root(slog::Discard, o!());
+        let resolving = Resolving::new(resolver, Duration::from_secs(60), &logger);
+
+        let slow = resolving.clone();
+        let lookup = std::thread::spawn(move || slow.lookup("slow.example"));
+        entered_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        // would deadlock if the lookup above held the lock
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        assert_eq!(resolving.lookup("127.0.0.1"), vec![localhost]);
+        release_tx.send(()).unwrap();
+        assert_eq!(lookup.join().unwrap(), vec!["10.1.2.3".parse::<IpAddr>().unwrap()]);
+    }
+}
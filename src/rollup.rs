@@ -0,0 +1,422 @@
+//! Client-side downsampling, for influxdb OSS without Kapacitor or
+//! continuous queries: roll high-resolution data up into a long-retention
+//! database, then delete it. Requires the "rollup" feature, which also
+//! builds the `influx-rollup` binary.
+
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::time::Duration;
+use hyper::Url;
+use serde_json::Value;
+use crate::{dur_nanos, now, Credentials, Error, InfluxWriter, OwnedMeasurement, OwnedValue};
+
+/// The influxql function a field is rolled up with.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Mean,
+    Median,
+    Min,
+    Max,
+    Sum,
+    Count,
+    First,
+    Last,
+}
+
+impl Aggregate {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Aggregate::Mean => "mean",
+            Aggregate::Median => "median",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+            Aggregate::Sum => "sum",
+            Aggregate::Count => "count",
+            Aggregate::First => "first",
+            Aggregate::Last => "last",
+        }
+    }
+
+    /// The `Aggregate` named `s` (as in `as_str`), if any.
+    ///
+    pub fn parse(s: &str) -> Option<Aggregate> {
+        [Aggregate::Mean, Aggregate::Median, Aggregate::Min, Aggregate::Max,
+         Aggregate::Sum, Aggregate::Count, Aggregate::First, Aggregate::Last]
+            .iter().copied().find(|agg| agg.as_str() == s)
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Queries one measurement's points older than `older_than`, aggregated
+/// into `interval` buckets by series, writes the buckets through an
+/// `InfluxWriter` (normally one for a database with a long retention
+/// policy), and then deletes the points it rolled up.
+///
+/// The cutoff is rounded down to a multiple of `interval`, so a bucket is
+/// never split between two runs. Rolled-up values are `Float`s, except
+/// for `Aggregate::Count` (`Integer`) and non-numeric `First` or `Last`
+/// values, which keep their type.
+///
+/// Nothing is deleted unless every rollup was written, nor if the server
+/// returned partial results (e.g. because of its `max-row-limit`).
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use influx_writer::{Aggregate, InfluxWriter, Rollup};
+///
+/// let archive = InfluxWriter::builder("localhost", "archive").try_build().unwrap();
+/// let stats = Rollup::new("localhost", "ticks", "trades")
+///     .older_than(Duration::from_secs(30 * 24 * 3600))
+///     .interval(Duration::from_secs(3600))
+///     .aggregate("price", Aggregate::Mean)
+///     .aggregate("amount", Aggregate::Sum)
+///     .run(archive, Duration::from_secs(60))
+///     .unwrap();
+/// println!("wrote {} rollups of {} series", stats.n_rollups, stats.n_series);
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct Rollup {
+    host: String,
+    db: String,
+    measurement: String,
+    into: Option<String>,
+    older_than: Duration,
+    interval: Duration,
+    aggregates: Vec<(String, Aggregate)>,
+    delete: bool,
+    https: bool,
+    creds: Option<Credentials>,
+}
+
+/// Counts of what a `Rollup` did.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RollupStats {
+    /// the time (nanoseconds since the unix epoch) before which points
+    /// were rolled up
+    pub cutoff: i64,
+    pub n_series: usize,
+    /// rolled-up points written
+    pub n_rollups: usize,
+    /// whether the source points were deleted
+    pub deleted: bool,
+}
+
+impl Rollup {
+    /// Rolls up `measurement` in `db` on `host` (default: points older
+    /// than 30 days, into one hour buckets). `host` takes the same forms
+    /// as in `InfluxWriter::builder`.
+    ///
+    pub fn new(host: &str, db: &str, measurement: &str) -> Self {
+        Rollup {
+            host: host.to_string(),
+            db: db.to_string(),
+            measurement: measurement.to_string(),
+            into: None,
+            older_than: Duration::from_secs(30 * 24 * 3600),
+            interval: Duration::from_secs(3600),
+            aggregates: Vec::new(),
+            delete: true,
+            https: false,
+            creds: None,
+        }
+    }
+
+    pub fn older_than(mut self, age: Duration) -> Self {
+        self.older_than = age;
+        self
+    }
+
+    /// Width of the rollup buckets, at least one second.
+    ///
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(Duration::from_secs(1));
+        self
+    }
+
+    /// Roll `field` up with `agg`. At least one field is required; fields
+    /// without an aggregate are dropped.
+    ///
+    pub fn aggregate(mut self, field: &str, agg: Aggregate) -> Self {
+        self.aggregates.push((field.to_string(), agg));
+        self
+    }
+
+    /// Measurement the rollups are written to (default: the source
+    /// measurement's name).
+    ///
+    pub fn into_measurement(mut self, key: &str) -> Self {
+        self.into = Some(key.to_string());
+        self
+    }
+
+    /// Whether to delete the rolled-up points (default: true). With
+    /// `false`, running again rolls up the same points, which overwrite
+    /// the earlier rollups.
+    ///
+    pub fn delete(mut self, delete: bool) -> Self {
+        self.delete = delete;
+        self
+    }
+
+    pub fn https(mut self, https: bool) -> Self {
+        self.https = https;
+        self
+    }
+
+    pub fn credentials(mut self, creds: Credentials) -> Self {
+        self.creds = Some(creds);
+        self
+    }
+
+    /// The cutoff for a run at `now` (nanoseconds since the unix epoch).
+    ///
+    pub fn cutoff(&self, now: i64) -> i64 {
+        let interval = dur_nanos(self.interval);
+        let t = now - dur_nanos(self.older_than);
+        t - t.rem_euclid(interval)
+    }
+
+    /// The query selecting the rollups of points before `cutoff`.
+    ///
+    pub fn select_query(&self, cutoff: i64) -> String {
+        let fields: Vec<String> = self.aggregates.iter()
+            .map(|(field, agg)| format!("{}({}) AS {}", agg, quote_ident(field), quote_ident(field)))
+            .collect();
+        format!("SELECT {} FROM {} WHERE time < {} GROUP BY time({}s), * fill(none)",
+            fields.join(", "), quote_ident(&self.measurement), cutoff, self.interval.as_secs())
+    }
+
+    /// The query deleting points before `cutoff`.
+    ///
+    pub fn delete_query(&self, cutoff: i64) -> String {
+        format!("DELETE FROM {} WHERE time < {}", quote_ident(&self.measurement), cutoff)
+    }
+
+    /// Rolls up, writes the rollups with `influx`, closes it (waiting up to
+    /// `timeout` for the rollups to be written) and deletes the source
+    /// points.
+    ///
+    pub fn run(&self, influx: InfluxWriter, timeout: Duration) -> Result<RollupStats, Error> {
+        if self.aggregates.is_empty() {
+            return Err(Error::Config("a rollup requires at least one aggregate".to_string()))
+        }
+        let cutoff = self.cutoff(now());
+        let mut stats = RollupStats { cutoff, ..Default::default() };
+        let https = self.https || crate::host::is_https(&self.host);
+        let client = InfluxWriter::http_client(https, None, None)?;
+        let url = crate::host::endpoint(&self.host, https, "query")?;
+
+        // a handle to read the writer's counters after closing it
+        let counters = influx.clone();
+        let before = counters.stats();
+        let into = self.into.as_deref().unwrap_or(&self.measurement);
+        // rollups are sent as each chunk of the response arrives, rather
+        // than after reading all of it
+        self.query(&client, &url, &self.select_query(cutoff), true, |chunk| {
+            for m in parse_rollups(chunk, into, &self.aggregates) {
+                influx.send(m)?;
+                stats.n_rollups += 1;
+            }
+            stats.n_series += count_series(chunk);
+            Ok(())
+        })?;
+
+        let flushed = influx.close(timeout)?;
+        let after = counters.stats();
+        let n_lost = flushed.n_batches_unsent
+            + (after.n_batches_rejected - before.n_batches_rejected)
+            + (after.n_dropped - before.n_dropped);
+        if n_lost > 0 {
+            return Err(Error::Transport {
+                status: None,
+                message: format!("{} batches or measurements of rollups were not written, nothing deleted", n_lost),
+            })
+        }
+        if self.delete {
+            self.query(&client, &url, &self.delete_query(cutoff), false, |_| Ok(()))?;
+            stats.deleted = true;
+        }
+        Ok(stats)
+    }
+
+    /// Sends `q` to the `/query` endpoint, calling `each` with each json
+    /// object in the response (one per chunk of `CHUNK_SIZE` rows if
+    /// `chunked`).
+    ///
+    fn query<F>(&self, client: &hyper::Client, url: &Url, q: &str, chunked: bool, mut each: F) -> Result<(), Error>
+        where F: FnMut(&Value) -> Result<(), Error>
+    {
+        let mut url = url.clone();
+        url.query_pairs_mut()
+            .append_pair("db", &self.db)
+            .append_pair("epoch", "ns")
+            .append_pair("q", q);
+        if chunked {
+            url.query_pairs_mut().append_pair("chunked", "true").append_pair("chunk_size", &CHUNK_SIZE.to_string());
+        }
+        let mut resp = InfluxWriter::http_req(client, url, "", &self.creds).send()?;
+        let status = resp.status;
+        if status != hyper::status::StatusCode::Ok {
+            let mut body = String::new();
+            let _ = resp.read_to_string(&mut body);
+            return Err(Error::Transport { status: Some(status.to_u16()), message: format!("query failed: {}: {}", q, body) })
+        }
+        for line in BufReader::new(resp).lines() {
+            let line = line?;
+            if line.trim().is_empty() { continue }
+            let json: Value = serde_json::from_str(&line)
+                .map_err(|e| Error::Serialization(format!("invalid query response: {}", e)))?;
+            // errors in the query itself are returned in the json body, with
+            // status 200
+            if let Some(e) = query_error(&json) {
+                return Err(Error::Transport { status: Some(status.to_u16()), message: format!("query failed: {}: {}", q, e) })
+            }
+            each(&json)?;
+        }
+        Ok(())
+    }
+}
+
+/// `s` as a double-quoted influxql identifier.
+///
+fn quote_ident(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Rows per chunk of a chunked query response. Chunking also avoids the
+/// server's `max-row-limit`, which truncates unchunked responses.
+///
+const CHUNK_SIZE: usize = 10_000;
+
+/// The error in a `/query` response: for the request as a whole, or for
+/// one of its statements.
+///
+fn query_error(json: &Value) -> Option<String> {
+    if let Some(e) = json["error"].as_str() { return Some(e.to_string()) }
+    json["results"].as_array()?.iter().find_map(|result| result["error"].as_str().map(String::from))
+}
+
+/// The number of series ending in `chunk` of a chunked response (a series
+/// continued in the next chunk is marked "partial").
+///
+fn count_series(chunk: &Value) -> usize {
+    chunk["results"][0]["series"].as_array()
+        .map(|series| series.iter().filter(|s| s["partial"].as_bool() != Some(true)).count())
+        .unwrap_or(0)
+}
+
+/// The measurements (with key `into`) in `chunk` of a `/query` response to
+/// a `select_query` of `aggregates`.
+///
+fn parse_rollups(chunk: &Value, into: &str, aggregates: &[(String, Aggregate)]) -> Vec<OwnedMeasurement> {
+    let mut out = Vec::new();
+    for series in chunk["results"][0]["series"].as_array().map(|s| &s[..]).unwrap_or(&[]) {
+        let columns: Vec<&str> = series["columns"].as_array().map(|c| &c[..]).unwrap_or(&[])
+            .iter().filter_map(|c| c.as_str()).collect();
+        let counts: Vec<bool> = columns.iter()
+            .map(|c| aggregates.iter().any(|(field, agg)| field == c && *agg == Aggregate::Count))
+            .collect();
+        for row in series["values"].as_array().map(|v| &v[..]).unwrap_or(&[]) {
+            let row = row.as_array().map(|r| &r[..]).unwrap_or(&[]);
+            let mut m = OwnedMeasurement::new(into.to_string());
+            if let Some(tags) = series["tags"].as_object() {
+                for (k, v) in tags {
+                    match v.as_str() {
+                        Some(v) if !v.is_empty() => { m = add_tag(m, k, v); }
+                        _ => {}
+                    }
+                }
+            }
+            for (i, (column, value)) in columns.iter().zip(row).enumerate() {
+                if *column == "time" {
+                    if let Some(t) = value.as_i64() { m = m.set_timestamp(t) }
+                    continue
+                }
+                let value = match value {
+                    Value::Number(n) if counts[i] => n.as_i64().map(OwnedValue::Integer),
+                    Value::Number(n) => n.as_f64().map(OwnedValue::Float),
+                    Value::String(s) => Some(OwnedValue::String(s.clone())),
+                    Value::Bool(b) => Some(OwnedValue::Boolean(*b)),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    m = m.add_field(column.to_string(), value);
+                }
+            }
+            if !m.fields.is_empty() { out.push(m) }
+        }
+    }
+    out
+}
+
+#[cfg(feature = "string-tags")]
+fn add_tag(m: OwnedMeasurement, k: &str, v: &str) -> OwnedMeasurement {
+    m.add_tag(k.to_string(), v)
+}
+
+/// Without "string-tags", tag values must be `&'static str`, so they are
+/// leaked; rollups are run rarely, over a bounded set of series.
+///
+#[cfg(not(feature = "string-tags"))]
+fn add_tag(m: OwnedMeasurement, k: &str, v: &str) -> OwnedMeasurement {
+    m.add_tag(k.to_string(), Box::leak(v.to_string().into_boxed_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_queries_and_parses_rollups() {
+        let rollup = Rollup::new("localhost", "ticks", "trades")
+            .interval(Duration::from_secs(60))
+            .older_than(Duration::from_secs(3600))
+            .aggregate("price", Aggregate::Mean)
+            .aggregate("n", Aggregate::Count);
+        let cutoff = rollup.cutoff(7_250_000_000_000);
+        assert_eq!(cutoff, 3_600_000_000_000);
+        assert_eq!(rollup.select_query(cutoff), r#"SELECT mean("price") AS "price", count("n") AS "n" FROM "trades" WHERE time < 3600000000000 GROUP BY time(60s), * fill(none)"#);
+        assert_eq!(rollup.delete_query(cutoff), r#"DELETE FROM "trades" WHERE time < 3600000000000"#);
+
+        let body: Value = serde_json::from_str(r#"{"results":[{"statement_id":0,"series":[
+            {"name":"trades","tags":{"exchange":"plnx","side":""},"columns":["time","price","n"],"values":[[0,1.5,3],[60000000000,2,null]]}
+        ]}]}"#).unwrap();
+        let ms = parse_rollups(&body, "trades_1m", &rollup.aggregates);
+        assert_eq!(ms.len(), 2);
+        assert_eq!(ms[0].key, "trades_1m");
+        assert_eq!(ms[0].get_tag("exchange"), Some("plnx"));
+        assert_eq!(ms[0].get_tag("side"), None);
+        assert_eq!(ms[0].get_field("price"), Some(&OwnedValue::Float(1.5)));
+        assert_eq!(ms[0].get_field("n"), Some(&OwnedValue::Integer(3)));
+        assert_eq!(ms[1].timestamp, Some(60_000_000_000));
+        assert_eq!(ms[1].get_field("price"), Some(&OwnedValue::Float(2.0)));
+        assert_eq!(ms[1].get_field("n"), None);
+        assert_eq!(count_series(&body), 1);
+        assert_eq!(query_error(&body), None);
+    }
+
+    #[test]
+    fn it_finds_query_errors_without_matching_data() {
+        let json = |s: &str| serde_json::from_str::<Value>(s).unwrap();
+        // a tag value of "error" is data, not an error
+        let chunk = json(r#"{"results":[{"statement_id":0,"series":[
+            {"name":"logs","tags":{"level":"error"},"columns":["time","n"],"values":[[0,1]],"partial":true}
+        ],"partial":true}]}"#);
+        assert_eq!(query_error(&chunk), None);
+        assert_eq!(parse_rollups(&chunk, "logs_1h", &[]).len(), 1);
+        // continued in the next chunk
+        assert_eq!(count_series(&chunk), 0);
+        assert_eq!(query_error(&json(r#"{"results":[{"statement_id":0,"error":"database not found: x"}]}"#)).as_deref(), Some("database not found: x"));
+        assert_eq!(query_error(&json(r#"{"error":"missing required parameter q"}"#)).as_deref(), Some("missing required parameter q"));
+    }
+}
@@ -0,0 +1,139 @@
+//! `Serialize` and `Deserialize` for `OwnedMeasurement` and `OwnedValue`.
+//! Requires the "serde" feature.
+//!
+//! A measurement is represented as a struct with "key", "timestamp" (null
+//! if unset), "tags" and "fields", the last two as sequences of
+//! `[key, value]` pairs, in order. Values are externally tagged (e.g.
+//! `{"Float": 1.5}`), with `D128` values as strings.
+//!
+//! This is deliberately not the format of `to_json`, which is for
+//! consumers that don't know this crate: it writes plain JSON values, so
+//! it can't be read back exactly (an `Integer` and a whole `Float`, or a
+//! `String` and a `D128`, look the same). This format keeps the type of
+//! each value and the order of tags and fields, so a measurement
+//! round-trips, e.g. through a queue between two processes using this
+//! crate.
+//!
+//! Deserialized keys are owned (see `Key`). Without the "string-tags"
+//! feature, tag values must be `&'static str`, so they are interned: each
+//! distinct value is leaked once and reused after that. This is fine for
+//! the usual small, fixed set of values, but deserializing untrusted input
+//! with unbounded distinct tag values will leak memory.
+
+#[cfg(not(feature = "string-tags"))]
+use std::collections::HashSet;
+#[cfg(not(feature = "string-tags"))]
+use std::sync::Mutex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::{OwnedMeasurement, OwnedValue};
+
+#[cfg(not(feature = "string-tags"))]
+lazy_static! {
+    static ref INTERNED: Mutex<HashSet<&'static str>> = Mutex::new(HashSet::new());
+}
+
+/// A `&'static str` equal to `s`, leaked the first time it's seen.
+///
+#[cfg(not(feature = "string-tags"))]
+fn intern(s: &str) -> &'static str {
+    let mut interned = INTERNED.lock().unwrap();
+    match interned.get(s) {
+        Some(s) => s,
+        None => {
+            let s: &'static str = Box::leak(s.to_string().into_boxed_str());
+            interned.insert(s);
+            s
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MeasurementRef<'a> {
+    key: &'a str,
+    timestamp: Option<i64>,
+    tags: Vec<(&'a str, &'a str)>,
+    fields: Vec<(&'a str, &'a OwnedValue)>,
+}
+
+#[derive(Deserialize)]
+struct MeasurementBuf {
+    key: String,
+    timestamp: Option<i64>,
+    tags: Vec<(String, String)>,
+    fields: Vec<(String, OwnedValue)>,
+}
+
+impl Serialize for OwnedMeasurement {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MeasurementRef {
+            key: &self.key,
+            timestamp: self.timestamp,
+            tags: self.tags.iter().map(|(k, v)| (&k[..], &v[..])).collect(),
+            fields: self.fields.iter().map(|(k, v)| (&k[..], v)).collect(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for OwnedMeasurement {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let buf = MeasurementBuf::deserialize(deserializer)?;
+        let mut m = OwnedMeasurement::with_capacity(buf.key, buf.tags.len(), buf.fields.len());
+        m.timestamp = buf.timestamp;
+        for (k, v) in buf.tags {
+            #[cfg(feature = "string-tags")]
+            { m = m.add_tag_string(k, v); }
+            #[cfg(not(feature = "string-tags"))]
+            { m = m.add_tag(k, intern(&v)); }
+        }
+        for (k, v) in buf.fields {
+            m = m.add_field(k, v);
+        }
+        Ok(m)
+    }
+}
+
+/// (De)serializes a `d128` as a string, for `OwnedValue::D128`.
+///
+pub(crate) mod d128_string {
+    use std::str::FromStr;
+    use decimal::d128;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &d128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<d128, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        d128::from_str(&s).map_err(|_| de::Error::custom(format!("invalid decimal: {}", s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use decimal::d128;
+    use uuid::Uuid;
+    use super::*;
+
+    #[test]
+    fn it_round_trips_a_measurement_through_json() {
+        let m = OwnedMeasurement::new("serde_test")
+            .add_tag("color", "red")
+            .add_field("s", OwnedValue::String("x".to_string()))
+            .add_field("f", OwnedValue::Float(1.5))
+            .add_field("i", OwnedValue::Integer(-2))
+            .add_field("b", OwnedValue::Boolean(true))
+            .add_field("d", OwnedValue::D128(d128::from_str("1.25").unwrap()))
+            .add_field("u", OwnedValue::Uuid(Uuid::nil()))
+            .set_timestamp(1);
+        let json = serde_json::to_string(&m).unwrap();
+        assert!(json.starts_with(r#"{"key":"serde_test","timestamp":1,"tags":[["color","red"]],"fields":[["s",{"String":"x"}],"#));
+        assert!(json.contains(r#"["d",{"D128":"1.25"}]"#));
+        let back: OwnedMeasurement = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.key, "serde_test");
+        assert_eq!(back.timestamp, Some(1));
+        assert_eq!(back.get_tag("color"), Some("red"));
+        assert_eq!(&back.fields[..], &m.fields[..]);
+    }
+}
@@ -0,0 +1,1828 @@
+//! Destinations for batches of serialized measurements.
+//!
+//! The writer thread serializes measurements into batches of line protocol,
+//! which are handed to a `Sink` on a separate thread, with retries on failure.
+//! Unless otherwise configured, batches are sent to influxdb's http api via
+//! `HttpSink`.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use hyper::client::Client;
+use hyper::client::response::Response;
+use hyper::status::StatusCode;
+use hyper::Url;
+use slog::Logger;
+use crate::{Credentials, Error, InfluxWriter};
+use crate::compression::{self, FileCompression};
+
+/// A destination for batches of serialized measurements.
+///
+/// `write` is called from multiple threads concurrently, and is retried
+/// (with the same `body`) when it returns an error.
+///
+pub trait Sink: Send + Sync + 'static {
+    /// Writes `body`, which holds one or more newline-separated lines
+    /// of influx line protocol.
+    fn write(&self, body: &str) -> Result<(), Error>;
+}
+
+/// Posts batches to an influxdb server's http `/write` endpoint.
+///
+pub struct HttpSink {
+    client: Client,
+    url: Url,
+    creds: Option<Credentials>,
+    #[cfg(feature = "gzip")]
+    gzip: bool,
+}
+
+impl HttpSink {
+    pub fn new(client: Client, url: Url, creds: Option<Credentials>) -> Self {
+        HttpSink {
+            client,
+            url,
+            creds,
+            #[cfg(feature = "gzip")]
+            gzip: false,
+        }
+    }
+
+    /// Compress request bodies with gzip (sent with `Content-Encoding: gzip`).
+    /// Requires the "gzip" feature.
+    ///
+    #[cfg(feature = "gzip")]
+    pub fn gzip(mut self, gzip: bool) -> Self {
+        self.gzip = gzip;
+        self
+    }
+
+    #[cfg(feature = "gzip")]
+    fn send_gzip(&self, body: &str) -> Result<Response, Error> {
+        use hyper::header::{ContentEncoding, Encoding};
+
+        let compressed = gzip_body(body)?;
+        let mut req = self.client.post(self.url.clone())
+            .header(ContentEncoding(vec![Encoding::Gzip]))
+            .body(&compressed[..]);
+        if let Some(ref auth) = self.creds {
+            req = req.header(auth.clone());
+        }
+        Ok(req.send()?)
+    }
+
+    fn check(resp: Response) -> Result<(), Error> {
+        match resp {
+            Response { status, .. } if status == StatusCode::NoContent => Ok(()),
+            mut resp => Err(Error::from_response(&mut resp)),
+        }
+    }
+}
+
+impl Sink for HttpSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        #[cfg(feature = "gzip")]
+        {
+            if self.gzip {
+                return Self::check(self.send_gzip(body)?)
+            }
+        }
+        let resp = InfluxWriter::http_req(&self.client, self.url.clone(), body, &self.creds).send()?;
+        Self::check(resp)
+    }
+}
+
+/// `body`, compressed with gzip.
+///
+#[cfg(feature = "gzip")]
+fn gzip_body(body: &str) -> Result<Vec<u8>, Error> {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::default());
+    encoder.write_all(body.as_bytes())
+        .map_err(|e| Error::Serialization(format!("gzip failed: {}", e)))?;
+    encoder.finish()
+        .map_err(|e| Error::Serialization(format!("gzip failed: {}", e)))
+}
+
+/// Posts batches to an influxdb server's http `/write` endpoint with a
+/// blocking `reqwest` client. This is how the writer posts batches by
+/// default (with a client it builds, or the one given to
+/// `InfluxWriterBuilder::reqwest_client`), unless hyper 0.10's `HttpSink`
+/// is chosen with `InfluxWriterBuilder::hyper_client`. Requires the
+/// "reqwest" feature.
+///
+#[cfg(feature = "reqwest")]
+pub struct ReqwestSink {
+    client: reqwest::blocking::Client,
+    url: Url,
+    creds: Option<Credentials>,
+    #[cfg(feature = "gzip")]
+    gzip: bool,
+}
+
+#[cfg(feature = "reqwest")]
+impl ReqwestSink {
+    pub fn new(client: reqwest::blocking::Client, url: Url, creds: Option<Credentials>) -> Self {
+        ReqwestSink {
+            client,
+            url,
+            creds,
+            #[cfg(feature = "gzip")]
+            gzip: false,
+        }
+    }
+
+    /// Compress request bodies with gzip (sent with `Content-Encoding: gzip`).
+    /// Requires the "gzip" feature.
+    ///
+    #[cfg(feature = "gzip")]
+    pub fn gzip(mut self, gzip: bool) -> Self {
+        self.gzip = gzip;
+        self
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl Sink for ReqwestSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        let req = self.client.post(self.url.as_str());
+        #[cfg(feature = "gzip")]
+        let req = if self.gzip {
+            req.header(reqwest::header::CONTENT_ENCODING, "gzip").body(gzip_body(body)?)
+        } else {
+            req.body(body.to_string())
+        };
+        #[cfg(not(feature = "gzip"))]
+        let req = req.body(body.to_string());
+        let mut req = req;
+        if let Some(ref auth) = self.creds {
+            req = req.basic_auth(&auth.0.username, auth.0.password.as_ref());
+        }
+        let resp = req.send()
+            .map_err(|e| Error::Transport { status: e.status().map(|s| s.as_u16()), message: e.to_string() })?;
+        match resp.status().as_u16() {
+            204 => Ok(()),
+            status => Err(Error::Transport { status: Some(status), message: resp.text().unwrap_or_default() }),
+        }
+    }
+}
+
+/// Sends batches to an influxdb server's udp listener, split into
+/// datagrams of at most `max_datagram_size` bytes on line boundaries.
+///
+/// Note: with udp, the database is determined by the server's
+/// configuration of the listener, rather than by the writer.
+///
+/// Datagrams are fire-and-forget: one that fails to send is skipped and
+/// counted (see `n_errors`). A write only fails, so the batch is retried,
+/// if none of its datagrams were sent, so a retry never sends a datagram
+/// twice.
+///
+#[derive(Debug)]
+pub struct UdpSink {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    max_datagram_size: usize,
+    n_errors: AtomicUsize,
+}
+
+impl UdpSink {
+    /// `addr` is the `host:port` of the server's udp listener.
+    ///
+    pub fn new(addr: &str, max_datagram_size: usize) -> Result<Self, Error> {
+        let addr = addr.to_socket_addrs()
+            .map_err(|e| Error::Config(format!("failed to resolve udp address {}: {}", addr, e)))?
+            .next()
+            .ok_or_else(|| Error::Config(format!("no addresses found for {}", addr)))?;
+        let bind_addr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_addr)?;
+        Ok(UdpSink { socket, addr, max_datagram_size, n_errors: AtomicUsize::new(0) })
+    }
+
+    /// Number of datagrams that failed to send.
+    ///
+    pub fn n_errors(&self) -> usize {
+        self.n_errors.load(Ordering::Relaxed)
+    }
+}
+
+impl Sink for UdpSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        let mut n_sent = 0;
+        let mut first_err = None;
+        for datagram in split_lines(body, self.max_datagram_size) {
+            match self.socket.send_to(datagram.as_bytes(), self.addr) {
+                Ok(_) => n_sent += 1,
+                Err(e) => {
+                    self.n_errors.fetch_add(1, Ordering::Relaxed);
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        match first_err {
+            // nothing went out, so retrying the batch can't duplicate anything
+            Some(e) if n_sent == 0 => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How a statsd server treats a metric, see `StatsdSink::metric_type`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsdType {
+    /// "g", the value is the current level
+    Gauge,
+    /// "c", the value is added to a count
+    Counter,
+    /// "ms", the value is a duration in milliseconds
+    Timer,
+}
+
+impl StatsdType {
+    fn suffix(self) -> &'static str {
+        match self {
+            StatsdType::Gauge => "g",
+            StatsdType::Counter => "c",
+            StatsdType::Timer => "ms",
+        }
+    }
+}
+
+/// Sends batches over udp to a statsd server (or a datadog agent),
+/// converted from line protocol to one statsd metric per field, so the same
+/// instrumentation can feed statsd without changing call sites.
+///
+/// Each numeric or boolean field becomes a metric named
+/// "{measurement key}.{field}", a gauge unless configured otherwise with
+/// `metric_type`. String fields and timestamps are dropped, as are tags
+/// unless `datadog_tags` is set. statsd reads a signed gauge value as a
+/// change, so a negative gauge is sent as "name:0|g" then "name:-5|g".
+///
+#[derive(Debug)]
+pub struct StatsdSink {
+    udp: UdpSink,
+    types: HashMap<String, StatsdType>,
+    datadog_tags: bool,
+}
+
+impl StatsdSink {
+    /// `addr` is the `host:port` of the statsd server. Datagrams are split
+    /// on line boundaries into at most `max_datagram_size` bytes.
+    ///
+    pub fn new(addr: &str, max_datagram_size: usize) -> Result<Self, Error> {
+        Ok(StatsdSink { udp: UdpSink::new(addr, max_datagram_size)?, types: HashMap::new(), datadog_tags: false })
+    }
+
+    /// Send the metric `name` ("{measurement key}.{field}") as `ty`
+    /// instead of as a gauge.
+    ///
+    pub fn metric_type(mut self, name: &str, ty: StatsdType) -> Self {
+        self.types.insert(name.to_string(), ty);
+        self
+    }
+
+    /// Append tags in the dogstatsd format ("|#tag:value,..."), which plain
+    /// statsd servers don't accept (default: false).
+    ///
+    pub fn datadog_tags(mut self, datadog_tags: bool) -> Self {
+        self.datadog_tags = datadog_tags;
+        self
+    }
+
+    /// Converts a batch of line protocol to newline-separated statsd
+    /// metrics. Malformed lines are skipped.
+    ///
+    fn serialize(&self, body: &str) -> String {
+        let mut out = String::with_capacity(body.len());
+        for point in body.lines().filter_map(MemoryPoint::parse) {
+            for (field, value) in &point.fields {
+                let value = match numeric_value(value) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let name = format!("{}.{}", point.key, field);
+                let ty = self.types.get(&name).copied().unwrap_or(StatsdType::Gauge);
+                // a signed gauge value is read as a change to the gauge, so
+                // a negative one is set by zeroing the gauge first
+                if ty == StatsdType::Gauge && value.starts_with('-') {
+                    self.push_metric(&mut out, &name, "0", ty, &point.tags);
+                }
+                self.push_metric(&mut out, &name, value, ty, &point.tags);
+            }
+        }
+        out
+    }
+
+    fn push_metric(&self, out: &mut String, name: &str, value: &str, ty: StatsdType, tags: &[(String, String)]) {
+        if !out.is_empty() { out.push('\n') }
+        out.push_str(&statsd_name(name));
+        out.push(':');
+        out.push_str(value);
+        out.push('|');
+        out.push_str(ty.suffix());
+        if self.datadog_tags && !tags.is_empty() {
+            out.push_str("|#");
+            for (i, (k, v)) in tags.iter().enumerate() {
+                if i > 0 { out.push(',') }
+                out.push_str(&statsd_name(k));
+                out.push(':');
+                out.push_str(&statsd_name(v));
+            }
+        }
+    }
+}
+
+/// A serialized field value as a plain number (booleans as 1 or 0), or
+/// `None` for a string.
+///
+fn numeric_value(value: &str) -> Option<&str> {
+    match value {
+        "t" | "T" | "true" | "True" | "TRUE" => Some("1"),
+        "f" | "F" | "false" | "False" | "FALSE" => Some("0"),
+        v if v.starts_with('"') => None,
+        v if v.ends_with('i') || v.ends_with('u') => Some(&v[..v.len() - 1]),
+        v => Some(v),
+    }
+}
+
+/// `s` with the characters that delimit parts of a statsd metric replaced
+/// by underscores.
+///
+fn statsd_name(s: &str) -> String {
+    s.replace(|c: char| matches!(c, ':' | '|' | '@' | '#' | ',') || c.is_whitespace(), "_")
+}
+
+impl Sink for StatsdSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        self.udp.write(&self.serialize(body))
+    }
+}
+
+/// Sends batches to a graphite (carbon) server over tcp, in the plaintext
+/// protocol ("{metric path} {value} {timestamp}"), one metric per numeric
+/// or boolean field. String fields are dropped.
+///
+/// Metric paths are built from a template (see `template`), by default
+/// "{key}.{tags}.{field}": the measurement key, the values of its tags
+/// (ordered by tag key) and the field name, with dots and whitespace
+/// within each replaced by underscores.
+///
+#[derive(Debug)]
+pub struct GraphiteSink {
+    addr: String,
+    template: Vec<String>,
+    conn: Mutex<Option<TcpStream>>,
+}
+
+impl GraphiteSink {
+    pub const DEFAULT_TEMPLATE: &'static str = "{key}.{tags}.{field}";
+
+    /// `addr` is the `host:port` of the server's plaintext listener
+    /// (usually port 2003). Connects on the first write, and reconnects
+    /// after a failed one.
+    ///
+    pub fn new(addr: &str) -> Self {
+        GraphiteSink { addr: addr.to_string(), template: Vec::new(), conn: Mutex::new(None) }
+            .template(Self::DEFAULT_TEMPLATE)
+    }
+
+    /// Dot-separated parts of the metric path, each one of "{key}",
+    /// "{field}", "{tags}" (every tag value, ordered by tag key),
+    /// "{tag:<name>}" (the value of one tag, omitted if it's missing), or
+    /// literal text, e.g. "servers.{tag:host}.{key}.{field}".
+    ///
+    pub fn template(mut self, template: &str) -> Self {
+        self.template = template.split('.').filter(|part| !part.is_empty()).map(String::from).collect();
+        self
+    }
+
+    /// Converts a batch of line protocol to graphite plaintext lines.
+    /// Malformed lines are skipped.
+    ///
+    fn serialize(&self, body: &str) -> String {
+        let mut out = String::with_capacity(body.len());
+        for mut point in body.lines().filter_map(MemoryPoint::parse) {
+            point.tags.sort();
+            for (field, value) in &point.fields {
+                let value = match numeric_value(value) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let mut path: Vec<String> = Vec::with_capacity(self.template.len() + point.tags.len());
+                for part in &self.template {
+                    match part.as_str() {
+                        "{key}" => path.push(graphite_name(&point.key)),
+                        "{field}" => path.push(graphite_name(field)),
+                        "{tags}" => path.extend(point.tags.iter().map(|(_, v)| graphite_name(v))),
+                        part if part.starts_with("{tag:") && part.ends_with('}') => {
+                            let name = &part[5..part.len() - 1];
+                            path.extend(point.tags.iter().find(|(k, _)| k == name).map(|(_, v)| graphite_name(v)));
+                        }
+                        part => path.push(part.to_string()),
+                    }
+                }
+                out.push_str(&path.join("."));
+                out.push_str(&format!(" {} {}\n", value, point.timestamp / 1_000_000_000));
+            }
+        }
+        out
+    }
+}
+
+/// `s` with dots and whitespace, which delimit graphite paths and lines,
+/// replaced by underscores.
+///
+fn graphite_name(s: &str) -> String {
+    s.replace(|c: char| c == '.' || c.is_whitespace(), "_")
+}
+
+impl Sink for GraphiteSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        let text = self.serialize(body);
+        if text.is_empty() {
+            return Ok(())
+        }
+        let mut conn = self.conn.lock().unwrap();
+        if conn.is_none() {
+            let stream = TcpStream::connect(&self.addr)
+                .map_err(|e| Error::Transport { status: None, message: format!("failed to connect to graphite at {}: {}", self.addr, e) })?;
+            stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+            *conn = Some(stream);
+        }
+        let result = conn.as_mut().map(|stream| stream.write_all(text.as_bytes())).unwrap_or(Ok(()));
+        if result.is_err() {
+            *conn = None;
+        }
+        result.map_err(|e| Error::Transport { status: None, message: format!("graphite: {}", e) })
+    }
+}
+
+/// Appends batches (as line protocol, one line per measurement) to a local
+/// file, for another process to ship to the server later.
+///
+/// The file is reopened for each batch, so it can be rotated (renamed) by
+/// the shipping process, and a new one is created by the next write. Or
+/// the sink can rotate it itself (see `rotate`).
+///
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    /// when the current file was started. Also held while writing, so that
+    /// batches written concurrently aren't interleaved
+    started: Mutex<Instant>,
+}
+
+#[derive(Debug)]
+struct Rotation {
+    max_bytes: u64,
+    max_age: Duration,
+    max_segments: usize,
+    compression: Option<FileCompression>,
+}
+
+impl FileSink {
+    /// Fails if the file can't be created or opened for appending.
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        OpenOptions::new().create(true).append(true).open(&path)
+            .map_err(|e| Error::Config(format!("failed to open {}: {}", path.display(), e)))?;
+        Ok(FileSink { path, rotation: None, started: Mutex::new(Instant::now()) })
+    }
+
+    /// Rename the file once it reaches `max_bytes`, or once `max_age` has
+    /// passed since it was started, to `<path>.<timestamp>` (nanoseconds
+    /// since the epoch), and start a new one.
+    ///
+    pub fn rotate(mut self, max_bytes: u64, max_age: Duration) -> Self {
+        self.rotation = Some(Rotation {
+            max_bytes,
+            max_age,
+            max_segments: usize::MAX,
+            compression: None,
+        });
+        self
+    }
+
+    /// Delete the oldest rotated files beyond the most recent `n` (default:
+    /// keep all). Only applies with `rotate`.
+    ///
+    pub fn max_segments(mut self, n: usize) -> Self {
+        if let Some(ref mut rotation) = self.rotation {
+            rotation.max_segments = n;
+        }
+        self
+    }
+
+    /// Compress rotated files with gzip (adding ".gz" to their names). Only
+    /// applies with `rotate`. Requires the "gzip" feature.
+    ///
+    #[cfg(feature = "gzip")]
+    pub fn gzip_rotated(mut self, gzip: bool) -> Self {
+        if let Some(ref mut rotation) = self.rotation {
+            rotation.compression = if gzip { Some(FileCompression::Gzip) } else { None };
+        }
+        self
+    }
+
+    /// Compress rotated files (adding the compression's extension to their
+    /// names, e.g. ".zst"). Only applies with `rotate`. `Replay` reads them
+    /// back according to their extension.
+    ///
+    pub fn compress_rotated(mut self, compression: FileCompression) -> Self {
+        if let Some(ref mut rotation) = self.rotation {
+            rotation.compression = Some(compression);
+        }
+        self
+    }
+
+    /// Rotates the file if it's due, given `n_bytes` are about to be written.
+    ///
+    fn rotate_if_due(&self, started: &mut Instant, n_bytes: usize) -> io::Result<()> {
+        let rotation = match self.rotation {
+            Some(ref rotation) => rotation,
+            None => return Ok(()),
+        };
+        let len = match fs::metadata(&self.path) {
+            Ok(meta) => meta.len(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        if len == 0 || (len + n_bytes as u64 <= rotation.max_bytes && started.elapsed() < rotation.max_age) {
+            return Ok(())
+        }
+        let rotated = self.segment_path(&crate::now().to_string());
+        fs::rename(&self.path, &rotated)?;
+        *started = Instant::now();
+        if let Some(compression) = rotation.compression {
+            Self::compress(&rotated, compression)?;
+        }
+        self.prune(rotation.max_segments)
+    }
+
+    fn segment_path(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+        name.push(".");
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+
+    /// Rotated files, oldest first.
+    ///
+    fn segments(&self) -> io::Result<Vec<PathBuf>> {
+        let prefix = match self.path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => format!("{}.", name),
+            None => return Ok(Vec::new()),
+        };
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut segments: Vec<(u64, PathBuf)> = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let timestamp = path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(prefix.as_str()))
+                .map(compression::strip_extension)
+                .and_then(|rest| rest.parse().ok());
+            if let Some(timestamp) = timestamp {
+                segments.push((timestamp, path));
+            }
+        }
+        segments.sort();
+        Ok(segments.into_iter().map(|(_, path)| path).collect())
+    }
+
+    fn prune(&self, max_segments: usize) -> io::Result<()> {
+        let segments = self.segments()?;
+        let n_extra = segments.len().saturating_sub(max_segments);
+        for path in &segments[..n_extra] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn compress(path: &Path, compression: FileCompression) -> io::Result<()> {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(".");
+        name.push(compression.extension());
+        fs::write(&name, compression.compress(&fs::read(path)?)?)?;
+        fs::remove_file(path)
+    }
+}
+
+impl Sink for FileSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        let body = body.trim_end_matches('\n');
+        if body.is_empty() {
+            return Ok(())
+        }
+        let mut started = self.started.lock().unwrap();
+        self.rotate_if_due(&mut started, body.len() + 1)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let mut buf = String::with_capacity(body.len() + 1);
+        buf.push_str(body);
+        buf.push('\n');
+        file.write_all(buf.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Publishes each batch as a message to a kafka topic. Requires the "kafka"
+/// feature.
+///
+#[cfg(feature = "kafka")]
+pub struct KafkaSink {
+    producer: Mutex<kafka::producer::Producer>,
+    topic: String,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaSink {
+    /// Connects to the kafka cluster at `brokers` (`host:port`), waiting
+    /// for the partition leader to acknowledge each message.
+    ///
+    pub fn new(brokers: &[String], topic: &str) -> Result<Self, Error> {
+        use kafka::producer::{Producer, RequiredAcks};
+
+        let producer = Producer::from_hosts(brokers.to_vec())
+            .with_ack_timeout(Duration::from_secs(5))
+            .with_required_acks(RequiredAcks::One)
+            .create()
+            .map_err(|e| Error::Config(format!("failed to connect to kafka brokers {:?}: {}", brokers, e)))?;
+        Ok(KafkaSink { producer: Mutex::new(producer), topic: topic.to_string() })
+    }
+}
+
+#[cfg(feature = "kafka")]
+impl Sink for KafkaSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        if body.is_empty() {
+            return Ok(())
+        }
+        let record = kafka::producer::Record::from_value(self.topic.as_str(), body.as_bytes());
+        self.producer.lock().unwrap().send(&record)
+            .map_err(|e| Error::Transport { status: None, message: format!("kafka: {}", e) })
+    }
+}
+
+/// Which topic(s) an `MqttSink` publishes to.
+///
+#[cfg(feature = "mqtt")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MqttTopic {
+    /// Each batch is published as one message to this topic.
+    Single(String),
+    /// The lines of each batch are published to "{prefix}/{measurement key}",
+    /// one message per key.
+    PerKey(String),
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttTopic {
+    /// The messages to publish for `body`, as (topic, payload).
+    ///
+    fn messages(&self, body: &str) -> Vec<(String, String)> {
+        match self {
+            MqttTopic::Single(_) if body.is_empty() => Vec::new(),
+            MqttTopic::Single(topic) => vec![(topic.clone(), body.to_string())],
+            MqttTopic::PerKey(prefix) => {
+                let mut messages: Vec<(String, String)> = Vec::new();
+                for line in body.lines().filter(|line| !line.is_empty()) {
+                    let series = split_unescaped(line, ' ', false).map(|(series, _)| series).unwrap_or(line);
+                    let key = split_unescaped(series, ',', false).map(|(key, _)| key).unwrap_or(series);
+                    let topic = format!("{}/{}", prefix, unescape(key));
+                    match messages.iter_mut().find(|(t, _)| *t == topic) {
+                        Some((_, payload)) => {
+                            payload.push('\n');
+                            payload.push_str(line);
+                        }
+                        None => messages.push((topic, line.to_string())),
+                    }
+                }
+                messages
+            }
+        }
+    }
+}
+
+/// Publishes batches to an mqtt broker (with qos 1), e.g. for a telegraf
+/// `mqtt_consumer` input to forward to influxdb. Requires the "mqtt"
+/// feature.
+///
+/// Publishing hands messages to a background thread ("inflx-mqtt"), which
+/// maintains the connection, reconnecting as needed. Messages published
+/// while the broker is unreachable are held until it's back, up to
+/// `QUEUE_CAPACITY`. Once the queue is full (or if that thread has
+/// stopped), `write` fails rather than blocking, so the batch is retried
+/// per the writer's `RetryPolicy`. With `MqttTopic::PerKey`, the retry
+/// publishes the batch's messages that were queued before it failed again.
+///
+#[cfg(feature = "mqtt")]
+pub struct MqttSink {
+    client: rumqttc::Client,
+    topic: MqttTopic,
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttSink {
+    /// Maximum number of messages waiting to be sent to the broker before
+    /// `write` fails.
+    ///
+    pub const QUEUE_CAPACITY: usize = 64;
+
+    pub fn new(host: &str, port: u16, topic: MqttTopic) -> Result<Self, Error> {
+        use rumqttc::{Client, MqttOptions};
+
+        let client_id = format!("influx-writer-{}-{}", crate::hostname(), std::process::id());
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        let (client, mut connection) = Client::new(options, Self::QUEUE_CAPACITY);
+        std::thread::Builder::new().name("inflx-mqtt".to_string()).spawn(move || {
+            // ends once the client (and so the sink) is dropped
+            for event in connection.iter() {
+                if event.is_err() {
+                    std::thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }).map_err(Error::Spawn)?;
+        Ok(MqttSink { client, topic })
+    }
+}
+
+#[cfg(feature = "mqtt")]
+impl Sink for MqttSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        for (topic, payload) in self.topic.messages(body) {
+            self.client.try_publish(topic, rumqttc::QoS::AtLeastOnce, false, payload)
+                .map_err(|_| Error::Transport {
+                    status: None,
+                    message: "mqtt: request queue is full (or the connection thread has stopped)".to_string(),
+                })?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes each batch to every one of its sinks in turn, e.g. to send to
+/// influxdb and also publish to kafka (see `InfluxWriterBuilder::tee`).
+///
+/// Only the first sink's result is returned, so the batch is retried only
+/// if the first sink fails. Writes to the other sinks are best-effort:
+/// failures are logged (at most every 10 seconds) and counted (see
+/// `n_errors`), but the batch is not retried for them.
+///
+pub struct TeeSink {
+    sinks: Vec<Arc<dyn Sink>>,
+    n_errors: Arc<AtomicUsize>,
+    logger: Logger,
+}
+
+impl TeeSink {
+    /// Panics if `sinks` is empty.
+    ///
+    pub fn new(sinks: Vec<Arc<dyn Sink>>) -> Self {
+        assert!(!sinks.is_empty(), "TeeSink requires at least one sink");
+        TeeSink { sinks, n_errors: Arc::default(), logger: Logger::root(slog::Discard, o!()) }
+    }
+
+    /// Where failed writes to the sinks after the first are logged.
+    ///
+    pub fn logger(mut self, logger: &Logger) -> Self {
+        self.logger = logger.new(o!("sink" => "tee"));
+        self
+    }
+
+    /// Counts failed writes to the sinks after the first in `n_errors`
+    /// (e.g. one of the writer's counters) instead.
+    ///
+    pub(crate) fn counter(mut self, n_errors: Arc<AtomicUsize>) -> Self {
+        self.n_errors = n_errors;
+        self
+    }
+
+    /// Number of failed writes to the sinks after the first.
+    ///
+    pub fn n_errors(&self) -> usize {
+        self.n_errors.load(Ordering::Relaxed)
+    }
+}
+
+impl Sink for TeeSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        let (primary, rest) = self.sinks.split_first().expect("at least one sink");
+        let result = primary.write(body);
+        for (i, sink) in rest.iter().enumerate() {
+            if let Err(e) = sink.write(body) {
+                self.n_errors.fetch_add(1, Ordering::Relaxed);
+                crate::warn_throttled!(self.logger, Duration::from_secs(10),
+                    "InfluxWriter: failed to write batch to tee sink: {}", e; "tee" => i + 1);
+            }
+        }
+        result
+    }
+}
+
+/// How `LoadBalancedSink` distributes batches across its endpoints.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    /// each batch goes to the next endpoint in turn
+    RoundRobin,
+    /// each line goes to an endpoint chosen by a hash of its measurement
+    /// key, so a given measurement is always written to the same endpoint
+    /// (while it is healthy). Batches are split up accordingly.
+    KeyHash,
+}
+
+/// How long an endpoint is skipped after a failed write, by default.
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(10);
+
+struct Endpoint {
+    sink: Box<dyn Sink>,
+    n_errors: AtomicUsize,
+    failed_at: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn is_healthy(&self, cooldown: Duration) -> bool {
+        match *self.failed_at.lock().unwrap() {
+            Some(failed_at) => failed_at.elapsed() >= cooldown,
+            None => true,
+        }
+    }
+
+    fn write(&self, body: &str) -> Result<(), Error> {
+        let result = self.sink.write(body);
+        match result {
+            Ok(()) => *self.failed_at.lock().unwrap() = None,
+            // the server rejected the data, which says nothing about its health
+            Err(Error::Transport { status: Some(status), .. }) if status < 500 => {}
+            Err(_) => {
+                self.n_errors.fetch_add(1, Ordering::Relaxed);
+                *self.failed_at.lock().unwrap() = Some(Instant::now());
+            }
+        }
+        result
+    }
+}
+
+/// Distributes batches across several sinks (e.g. the nodes behind an
+/// influx-relay setup), see `InfluxWriterBuilder::load_balance`.
+///
+/// An endpoint that fails (with a connection error or 5xx response) is
+/// skipped for a cooldown period (default: 10 seconds), so the writer's
+/// retry of the batch goes to another endpoint. If every endpoint is
+/// unhealthy, batches are sent to the endpoint that would otherwise have
+/// been chosen.
+///
+/// With `Balance::KeyHash`, if the write to one endpoint fails, the whole
+/// batch is retried, so lines already written to other endpoints are
+/// written again (which influxdb treats as an overwrite of the same points).
+///
+pub struct LoadBalancedSink {
+    endpoints: Vec<Endpoint>,
+    balance: Balance,
+    cooldown: Duration,
+    next: AtomicUsize,
+}
+
+impl LoadBalancedSink {
+    /// Panics if `sinks` is empty.
+    ///
+    pub fn new(sinks: Vec<Box<dyn Sink>>, balance: Balance) -> Self {
+        assert!(!sinks.is_empty(), "LoadBalancedSink requires at least one sink");
+        let endpoints = sinks.into_iter()
+            .map(|sink| Endpoint { sink, n_errors: AtomicUsize::new(0), failed_at: Mutex::new(None) })
+            .collect();
+        LoadBalancedSink { endpoints, balance, cooldown: DEFAULT_COOLDOWN, next: AtomicUsize::new(0) }
+    }
+
+    /// How long an endpoint is skipped after a failed write.
+    ///
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Number of failed writes to each endpoint (in the order the sinks
+    /// were given to `new`), not counting rejected batches.
+    ///
+    pub fn n_errors(&self) -> Vec<usize> {
+        self.endpoints.iter().map(|e| e.n_errors.load(Ordering::Relaxed)).collect()
+    }
+
+    /// Returns the first healthy endpoint starting at `start`, or `start`
+    /// if none are healthy.
+    ///
+    fn pick(&self, start: usize) -> usize {
+        let n = self.endpoints.len();
+        (0..n).map(|offset| (start + offset) % n)
+            .find(|&i| self.endpoints[i].is_healthy(self.cooldown))
+            .unwrap_or(start % n)
+    }
+}
+
+impl Sink for LoadBalancedSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        match self.balance {
+            Balance::RoundRobin => {
+                let i = self.pick(self.next.fetch_add(1, Ordering::Relaxed));
+                self.endpoints[i].write(body)
+            }
+
+            Balance::KeyHash => {
+                let n = self.endpoints.len();
+                let groups = group_lines(body, n, |key| {
+                    let mut hasher = DefaultHasher::new();
+                    key.hash(&mut hasher);
+                    self.pick((hasher.finish() % n as u64) as usize)
+                });
+                write_groups(self.endpoints.iter().map(|e| move |body: &str| e.write(body)), &groups)
+            }
+        }
+    }
+}
+
+/// Sends each measurement to the sink routed to its key, or to a default
+/// sink, e.g. to write measurements to different databases (see
+/// `InfluxWriterBuilder::route`).
+///
+/// As with `Balance::KeyHash`, if the write to one sink fails, the whole
+/// batch is retried, including lines already written to other sinks.
+///
+pub struct RoutingSink {
+    /// `sinks[0]` is the default
+    sinks: Vec<Arc<dyn Sink>>,
+    routes: RwLock<HashMap<String, usize>>,
+}
+
+impl RoutingSink {
+    pub fn new(default: Arc<dyn Sink>) -> Self {
+        RoutingSink { sinks: vec![default], routes: RwLock::new(HashMap::new()) }
+    }
+
+    /// Send measurements with `key` to `sink`. Keys routed to the same
+    /// `Arc` share a single write per batch.
+    ///
+    pub fn route(mut self, key: &str, sink: Arc<dyn Sink>) -> Self {
+        let i = match self.sinks.iter().position(|s| Arc::ptr_eq(s, &sink)) {
+            Some(i) => i,
+            None => {
+                self.sinks.push(sink);
+                self.sinks.len() - 1
+            }
+        };
+        if let Ok(routes) = self.routes.get_mut() { routes.insert(key.to_string(), i); }
+        self
+    }
+
+    /// Send measurements with `key` to `sink` from now on, where `sink` is
+    /// the default or one already routed to (e.g. to move a key between
+    /// databases at runtime, see `InfluxWriterBuilder::watch_config`).
+    /// Returns false, changing nothing, for any other sink.
+    ///
+    pub fn set_route(&self, key: &str, sink: &Arc<dyn Sink>) -> bool {
+        match self.sinks.iter().position(|s| Arc::ptr_eq(s, sink)) {
+            Some(i) => {
+                if let Ok(mut routes) = self.routes.write() { routes.insert(key.to_string(), i); }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Sink for RoutingSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        let routes = self.routes.read().unwrap_or_else(|e| e.into_inner());
+        let groups = group_lines(body, self.sinks.len(), |key| routes.get(key).cloned().unwrap_or(0));
+        drop(routes);
+        write_groups(self.sinks.iter().map(|s| move |body: &str| s.write(body)), &groups)
+    }
+}
+
+/// Splits batches larger than `max_bytes` on line boundaries and writes the
+/// pieces to `inner` in parallel (at most `concurrency` at a time), so a
+/// very large flush doesn't go out as one giant request. See
+/// `InfluxWriterBuilder::max_request_bytes`.
+///
+/// If any piece fails, the whole batch is retried, including pieces that
+/// were written successfully.
+///
+pub struct ChunkedSink {
+    inner: Arc<dyn Sink>,
+    max_bytes: usize,
+    concurrency: usize,
+}
+
+impl ChunkedSink {
+    pub fn new(inner: Arc<dyn Sink>, max_bytes: usize, concurrency: usize) -> Self {
+        ChunkedSink { inner, max_bytes, concurrency: concurrency.max(1) }
+    }
+}
+
+impl Sink for ChunkedSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        if body.len() <= self.max_bytes {
+            return self.inner.write(body)
+        }
+        let mut result = Ok(());
+        for pieces in split_lines(body, self.max_bytes).chunks(self.concurrency) {
+            let results: Vec<Result<(), Error>> = std::thread::scope(|scope| {
+                let handles: Vec<_> = pieces.iter()
+                    .map(|piece| scope.spawn(move || self.inner.write(piece)))
+                    .collect();
+                handles.into_iter()
+                    .map(|h| h.join().unwrap_or_else(|_| Err(Error::Shutdown("chunk write panicked".to_string()))))
+                    .collect()
+            });
+            for r in results {
+                if let Err(e) = r {
+                    if result.is_ok() { result = Err(e) }
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Splits the lines of `body` into `n` groups, by the index `f` returns
+/// for each line's measurement key.
+///
+fn group_lines<F: Fn(&str) -> usize>(body: &str, n: usize, f: F) -> Vec<String> {
+    let mut groups: Vec<String> = vec![String::new(); n];
+    for line in body.lines().filter(|line| !line.is_empty()) {
+        let group = &mut groups[f(line_key(line))];
+        if !group.is_empty() { group.push('\n'); }
+        group.push_str(line);
+    }
+    groups
+}
+
+/// Writes each non-empty group with the corresponding writer, returning
+/// the first error (after attempting every group).
+///
+fn write_groups<I, W>(writers: I, groups: &[String]) -> Result<(), Error>
+    where I: Iterator<Item = W>,
+          W: Fn(&str) -> Result<(), Error>
+{
+    let mut result = Ok(());
+    for (write, group) in writers.zip(groups.iter()) {
+        if group.is_empty() { continue }
+        if let Err(e) = write(group) {
+            if result.is_ok() { result = Err(e) }
+        }
+    }
+    result
+}
+
+/// Keeps the measurements written in the last `retention` in memory, so
+/// tests and local tools can inspect what was sent without an influxdb
+/// server. Clones share the same store, so keep a clone to query after
+/// passing one to `InfluxWriterBuilder::sink`:
+///
+/// ```
+/// use std::time::Duration;
+/// use influx_writer::{InfluxWriter, MemorySink, OwnedMeasurement, OwnedValue};
+///
+/// let mem = MemorySink::new(Duration::from_secs(600));
+/// let influx = InfluxWriter::builder("localhost", "test").sink(mem.clone()).build();
+/// influx.send(OwnedMeasurement::new("fills").add_tag("venue", "x").add_field("qty", OwnedValue::Integer(3))).unwrap();
+/// influx.close(Duration::from_secs(5)).unwrap();
+///
+/// let fills = mem.filter(|p| p.key == "fills" && p.tag("venue") == Some("x"));
+/// assert_eq!(fills.len(), 1);
+/// assert_eq!(fills[0].field("qty"), Some("3i"));
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct MemorySink {
+    points: Arc<Mutex<VecDeque<MemoryPoint>>>,
+    retention: Duration,
+}
+
+/// A measurement held by `MemorySink`, parsed back from line protocol.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryPoint {
+    pub key: String,
+    pub tags: Vec<(String, String)>,
+    /// field values as serialized, e.g. `1i`, `1.5`, `"text"`
+    pub fields: Vec<(String, String)>,
+    /// nanoseconds since the unix epoch
+    pub timestamp: i64,
+}
+
+impl MemoryPoint {
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|kv| kv.0 == key).map(|kv| kv.1.as_str())
+    }
+
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|kv| kv.0 == key).map(|kv| kv.1.as_str())
+    }
+
+    /// Parses a line of line protocol (without escapes in field values
+    /// being undone). Returns `None` if the line is malformed.
+    ///
+    pub fn parse(line: &str) -> Option<Self> {
+        let (series, rest) = split_unescaped(line, ' ', false)?;
+        let (fields, timestamp) = match split_unescaped(rest, ' ', true) {
+            Some((fields, timestamp)) => (fields, timestamp.trim().parse().ok()?),
+            None => (rest, crate::now()),
+        };
+        let mut series = split_all_unescaped(series, ',', false).into_iter();
+        let key = unescape(series.next()?);
+        let pair = |kv: &str, quoted: bool, unescape_value: bool| {
+            split_unescaped(kv, '=', quoted).map(|(k, v)| {
+                (unescape(k), if unescape_value { unescape(v) } else { v.to_string() })
+            })
+        };
+        let tags = series.map(|kv| pair(kv, false, true)).collect::<Option<Vec<_>>>()?;
+        let fields = split_all_unescaped(fields, ',', true).into_iter()
+            .map(|kv| pair(kv, true, false))
+            .collect::<Option<Vec<_>>>()?;
+        Some(MemoryPoint { key, tags, fields, timestamp })
+    }
+}
+
+impl MemorySink {
+    pub fn new(retention: Duration) -> Self {
+        MemorySink { points: Arc::new(Mutex::new(VecDeque::new())), retention }
+    }
+
+    /// Every retained point, oldest first.
+    ///
+    pub fn points(&self) -> Vec<MemoryPoint> {
+        self.filter(|_| true)
+    }
+
+    /// The retained points for which `f` returns `true`, oldest first.
+    ///
+    pub fn filter<F: Fn(&MemoryPoint) -> bool>(&self, f: F) -> Vec<MemoryPoint> {
+        self.points.lock().unwrap().iter().filter(|p| f(p)).cloned().collect()
+    }
+
+    /// Discards every retained point.
+    ///
+    pub fn clear(&self) {
+        self.points.lock().unwrap().clear();
+    }
+}
+
+impl Sink for MemorySink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        let mut new_points = Vec::new();
+        for line in body.lines().filter(|line| !line.is_empty()) {
+            match MemoryPoint::parse(line) {
+                Some(point) => new_points.push(point),
+                None => return Err(Error::Transport { status: Some(400), message: format!("unable to parse '{}'", line) }),
+            }
+        }
+        let retention = i64::try_from(self.retention.as_nanos()).unwrap_or(i64::MAX);
+        let cutoff = crate::now().saturating_sub(retention);
+        let mut points = self.points.lock().unwrap();
+        points.extend(new_points.into_iter().filter(|p| p.timestamp >= cutoff));
+        // points usually arrive in time order, but batches can be retried
+        points.retain(|p| p.timestamp >= cutoff);
+        Ok(())
+    }
+}
+
+/// labels -> (value, when it was written)
+type PrometheusSeries = BTreeMap<String, (String, Instant)>;
+
+/// Keeps the latest value of every (measurement key, tags, field) written
+/// to it, to be scraped by prometheus in its text exposition format, so the
+/// same measurements can feed both influxdb (with `InfluxWriterBuilder::tee`)
+/// and prometheus.
+///
+/// Each numeric or boolean field becomes a gauge named "{key}_{field}"
+/// (with characters prometheus doesn't allow replaced by underscores), and
+/// tags become labels. String fields are dropped. Pushing with prometheus
+/// remote write is not supported.
+///
+/// # Examples
+///
+/// ```no_run
+/// use influx_writer::{InfluxWriter, PrometheusSink};
+///
+/// let prometheus = PrometheusSink::new();
+/// prometheus.serve("0.0.0.0:9100").unwrap();
+/// let influx = InfluxWriter::builder("localhost", "test").tee(prometheus).build();
+/// ```
+///
+#[derive(Debug, Clone, Default)]
+pub struct PrometheusSink {
+    /// by name
+    series: Arc<Mutex<BTreeMap<String, PrometheusSeries>>>,
+    expire_after: Option<Duration>,
+}
+
+impl PrometheusSink {
+    pub fn new() -> Self { Self::default() }
+
+    /// Stop exposing series that haven't been written for `age` (default:
+    /// series are exposed until the process exits).
+    ///
+    pub fn expire_after(mut self, age: Duration) -> Self {
+        self.expire_after = Some(age);
+        self
+    }
+
+    /// The current values, in the prometheus text exposition format.
+    ///
+    pub fn render(&self) -> String {
+        let mut series = self.series.lock().unwrap();
+        if let Some(age) = self.expire_after {
+            let now = Instant::now();
+            for values in series.values_mut() {
+                values.retain(|_, (_, written)| now.duration_since(*written) < age);
+            }
+            series.retain(|_, values| !values.is_empty());
+        }
+        let mut out = String::new();
+        for (name, values) in series.iter() {
+            out.push_str(&format!("# TYPE {} gauge\n", name));
+            for (labels, (value, _)) in values {
+                out.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+        }
+        out
+    }
+
+    /// Starts an http listener on `addr` serving `render` at `/metrics`,
+    /// for the life of the process.
+    ///
+    pub fn serve(&self, addr: &str) -> Result<(), Error> {
+        use hyper::server::{Request, Response as ServerResponse, Server};
+        use hyper::uri::RequestUri;
+
+        let server = Server::http(addr)
+            .map_err(|e| Error::Config(format!("failed to bind prometheus listener to {}: {}", addr, e)))?;
+        let sink = self.clone();
+        let handler = move |req: Request, mut resp: ServerResponse| {
+            match req.uri {
+                RequestUri::AbsolutePath(ref path) if path.split('?').next() == Some("/metrics") => {
+                    let _ = resp.send(sink.render().as_bytes());
+                }
+                _ => {
+                    *resp.status_mut() = StatusCode::NotFound;
+                    let _ = resp.send(b"not found");
+                }
+            }
+        };
+        let mut listening = server.handle_threads(handler, 2).map_err(Error::from)?;
+        // detaches the listener, as with the admin listener
+        let _ = listening.close();
+        Ok(())
+    }
+}
+
+/// `s` with every character prometheus doesn't allow in a metric or label
+/// name replaced by an underscore.
+///
+fn prometheus_name(s: &str) -> String {
+    let mut name: String = s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+impl Sink for PrometheusSink {
+    fn write(&self, body: &str) -> Result<(), Error> {
+        let now = Instant::now();
+        let mut series = self.series.lock().unwrap();
+        for mut point in body.lines().filter_map(MemoryPoint::parse) {
+            point.tags.sort();
+            let labels = if point.tags.is_empty() {
+                String::new()
+            } else {
+                let labels: Vec<String> = point.tags.iter()
+                    .map(|(k, v)| format!("{}=\"{}\"", prometheus_name(k), v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+                    .collect();
+                format!("{{{}}}", labels.join(","))
+            };
+            for (field, value) in &point.fields {
+                if let Some(value) = numeric_value(value) {
+                    let name = prometheus_name(&format!("{}_{}", point.key, field));
+                    series.entry(name).or_default().insert(labels.clone(), (value.to_string(), now));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits `s` at the first unescaped `sep` (outside of double quotes, if
+/// `quoted`).
+///
+fn split_unescaped(s: &str, sep: char, quoted: bool) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    let mut in_quotes = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' if quoted => in_quotes = !in_quotes,
+            c if c == sep && !in_quotes => return Some((&s[..i], &s[i + c.len_utf8()..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn split_all_unescaped(mut s: &str, sep: char, quoted: bool) -> Vec<&str> {
+    let mut out = Vec::new();
+    while let Some((head, tail)) = split_unescaped(s, sep, quoted) {
+        out.push(head);
+        s = tail;
+    }
+    out.push(s);
+    out
+}
+
+/// Removes the backslashes escaping commas, spaces, equals signs and
+/// backslashes in keys and tag values.
+///
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next)) if next == ',' || next == ' ' || next == '=' || next == '\\' => {
+                out.push(next);
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// The measurement key of a line of line protocol (everything up to the
+/// first unescaped comma or space).
+///
+fn line_key(line: &str) -> &str {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' | ' ' => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits `body` on line boundaries into pieces of at most `max_bytes`
+/// (not counting the newline separating pieces). A single line longer than
+/// `max_bytes` is returned as a piece of its own.
+///
+pub(crate) fn split_lines(body: &str, max_bytes: usize) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    for line in body.split('\n') {
+        let line_start = line.as_ptr() as usize - body.as_ptr() as usize;
+        let line_end = line_start + line.len();
+        if end > start && line_end - start > max_bytes {
+            out.push(&body[start..end]);
+            start = line_start;
+        }
+        end = line_end;
+    }
+    if end > start {
+        out.push(&body[start..end]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn it_converts_line_protocol_to_statsd_metrics() {
+        let sink = StatsdSink::new("127.0.0.1:8125", 1400).unwrap()
+            .metric_type("req.n", StatsdType::Counter)
+            .metric_type("req.latency", StatsdType::Timer);
+        let body = "req,path=/a\\ b n=3i,latency=1.5,ok=t,msg=\"x\" 1\nmem used=10u";
+        assert_eq!(sink.serialize(body), "req.n:3|c\nreq.latency:1.5|ms\nreq.ok:1|g\nmem.used:10|g");
+        let sink = sink.datadog_tags(true).metric_type("pos.n", StatsdType::Counter);
+        assert_eq!(sink.serialize("req,path=/a\\ b,host=x n=3i 1"), "req.n:3|c|#path:/a_b,host:x");
+        // negative gauges are zeroed first, so they aren't read as a decrement
+        assert_eq!(sink.serialize("pos,host=x v=-5i,n=-2i 1"), "pos.v:0|g|#host:x\npos.v:-5|g|#host:x\npos.n:-2|c|#host:x");
+    }
+
+    #[test]
+    fn it_exposes_the_latest_value_of_each_series_to_prometheus() {
+        let sink = PrometheusSink::new();
+        sink.write("req,path=/a,host=x n=1i,ok=t,msg=\"x\" 1\nreq,path=/a,host=x n=2i 2\nreq,path=/b n=3i 3\nmem.used v=1.5").unwrap();
+        assert_eq!(sink.render(), "# TYPE mem_used_v gauge\nmem_used_v 1.5\n\
+            # TYPE req_n gauge\nreq_n{host=\"x\",path=\"/a\"} 2\nreq_n{path=\"/b\"} 3\n\
+            # TYPE req_ok gauge\nreq_ok{host=\"x\",path=\"/a\"} 1\n");
+        let sink = sink.expire_after(Duration::from_secs(0));
+        assert_eq!(sink.render(), "");
+    }
+
+    #[test]
+    fn it_converts_line_protocol_to_graphite_paths() {
+        let body = "req,path=/a.b,host=x n=3i,ok=f,msg=\"x\" 1500000000000000000\nmem used=1.5 2000000000";
+        let sink = GraphiteSink::new("127.0.0.1:2003");
+        assert_eq!(sink.serialize(body), "req.x./a_b.n 3 1500000000\nreq.x./a_b.ok 0 1500000000\nmem.used 1.5 2\n");
+        let sink = sink.template("servers.{tag:host}.{key}.{field}");
+        assert_eq!(sink.serialize(body), "servers.x.req.n 3 1500000000\nservers.x.req.ok 0 1500000000\nservers.mem.used 1.5 2\n");
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[test]
+    fn it_fails_mqtt_writes_instead_of_blocking_when_the_broker_is_down() {
+        // nothing listening, so queued messages are never taken off the queue
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let sink = MqttSink::new("127.0.0.1", port, MqttTopic::Single("m".to_string())).unwrap();
+        let results: Vec<_> = (0..MqttSink::QUEUE_CAPACITY + 1).map(|i| sink.write(&format!("m n={}i", i))).collect();
+        assert!(results[..MqttSink::QUEUE_CAPACITY].iter().all(Result::is_ok));
+        match results[MqttSink::QUEUE_CAPACITY] {
+            Err(Error::Transport { status: None, ref message }) => assert!(message.contains("queue is full"), "{}", message),
+            ref other => panic!("unexpected: {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[test]
+    fn it_splits_batches_into_mqtt_messages_by_key() {
+        let body = "a,host=x n=1i 1\nb\\ c n=2i 2\na n=3i 3";
+        assert_eq!(MqttTopic::Single("m".to_string()).messages(body), vec![("m".to_string(), body.to_string())]);
+        assert_eq!(MqttTopic::Single("m".to_string()).messages(""), vec![]);
+        assert_eq!(MqttTopic::PerKey("influx".to_string()).messages(body), vec![
+            ("influx/a".to_string(), "a,host=x n=1i 1\na n=3i 3".to_string()),
+            ("influx/b c".to_string(), "b\\ c n=2i 2".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn it_writes_to_every_sink_of_a_tee() {
+        let a = MemorySink::new(Duration::from_secs(60));
+        let b = MemorySink::new(Duration::from_secs(60));
+        let tee = TeeSink::new(vec![Arc::new(a.clone()), Arc::new(b.clone())]);
+        let now = crate::now();
+        tee.write(&format!("a n=1i {}\nb n=2i {}", now, now)).unwrap();
+        assert_eq!(a.points().len(), 2);
+        assert_eq!(b.points().len(), 2);
+    }
+
+    #[test]
+    fn it_returns_only_the_first_sink_result_of_a_tee() {
+        struct Failing;
+        impl Sink for Failing {
+            fn write(&self, _: &str) -> Result<(), Error> {
+                Err(Error::Transport { status: None, message: "down".to_string() })
+            }
+        }
+        let a = MemorySink::new(Duration::from_secs(60));
+        let tee = TeeSink::new(vec![Arc::new(a.clone()), Arc::new(Failing)]);
+        let now = crate::now();
+        tee.write(&format!("a n=1i {}", now)).unwrap();
+        tee.write(&format!("a n=2i {}", now + 1)).unwrap();
+        assert_eq!(a.points().len(), 2);
+        assert_eq!(tee.n_errors(), 2);
+
+        let tee = TeeSink::new(vec![Arc::new(Failing), Arc::new(a.clone())]);
+        assert!(tee.write(&format!("a n=3i {}", now + 2)).is_err());
+        assert_eq!(a.points().len(), 3);
+        assert_eq!(tee.n_errors(), 0);
+    }
+
+    #[test]
+    fn it_appends_batches_to_a_file() {
+        let path = std::env::temp_dir().join(format!("influx-writer-file-sink-test-{}.lp", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sink = FileSink::new(&path).unwrap();
+        sink.write("a n=1i 1\nb n=2i 2").unwrap();
+        sink.write("").unwrap();
+        sink.write("c n=3i 3\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a n=1i 1\nb n=2i 2\nc n=3i 3\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn it_rotates_the_file_by_size_and_keeps_the_newest_segments() {
+        let dir = std::env::temp_dir().join(format!("influx-writer-file-rotation-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("points.lp");
+        let sink = FileSink::new(&path).unwrap().rotate(20, Duration::from_secs(3600)).max_segments(2);
+        for i in 0..4 {
+            sink.write(&format!("a n={}i 1", i)).unwrap();
+            sink.write(&format!("b n={}i 2", i)).unwrap();
+        }
+        let segments = sink.segments().unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(fs::read_to_string(&segments[0]).unwrap(), "a n=1i 1\nb n=1i 2\n");
+        assert_eq!(fs::read_to_string(&segments[1]).unwrap(), "a n=2i 1\nb n=2i 2\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a n=3i 1\nb n=3i 2\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "snappy")]
+    #[test]
+    fn it_compresses_rotated_files() {
+        let dir = std::env::temp_dir().join(format!("influx-writer-file-compression-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("points.lp");
+        let sink = FileSink::new(&path).unwrap().rotate(20, Duration::from_secs(3600))
+            .compress_rotated(FileCompression::Snappy)
+            .max_segments(1);
+        for i in 0..3 {
+            sink.write(&format!("a n={}i 1", i)).unwrap();
+            sink.write(&format!("b n={}i 2", i)).unwrap();
+        }
+        let segments = sink.segments().unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].extension().unwrap(), "sz");
+        assert_eq!(compression::read_to_string(&segments[0], None).unwrap(), "a n=1i 1\nb n=1i 2\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn it_splits_a_body_into_pieces_on_line_boundaries() {
+        let body = "aaaa\nbbbb\ncccc\ndd";
+        assert_eq!(split_lines(body, 100), vec![body]);
+        assert_eq!(split_lines(body, 9), vec!["aaaa\nbbbb", "cccc\ndd"]);
+        assert_eq!(split_lines(body, 4), vec!["aaaa", "bbbb", "cccc", "dd"]);
+        assert_eq!(split_lines(body, 2), vec!["aaaa", "bbbb", "cccc", "dd"]);
+        assert!(split_lines("", 10).is_empty());
+    }
+
+    /// records the bodies it is sent, failing while `down` is set
+    #[derive(Clone, Default)]
+    struct Node {
+        bodies: Arc<Mutex<Vec<String>>>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl Sink for Node {
+        fn write(&self, body: &str) -> Result<(), Error> {
+            if self.down.load(Ordering::Relaxed) {
+                return Err(Error::Transport { status: Some(503), message: "down".to_string() })
+            }
+            self.bodies.lock().unwrap().push(body.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_skips_an_unhealthy_endpoint_until_the_cooldown_passes() {
+        let (a, b) = (Node::default(), Node::default());
+        let sink = LoadBalancedSink::new(vec![Box::new(a.clone()), Box::new(b.clone())], Balance::RoundRobin)
+            .cooldown(Duration::from_millis(50));
+        sink.write("1").unwrap();
+        sink.write("2").unwrap();
+        assert_eq!(*a.bodies.lock().unwrap(), vec!["1"]);
+        assert_eq!(*b.bodies.lock().unwrap(), vec!["2"]);
+
+        a.down.store(true, Ordering::Relaxed);
+        assert!(sink.write("3").is_err());
+        a.down.store(false, Ordering::Relaxed);
+        sink.write("3").unwrap();
+        sink.write("4").unwrap();
+        assert_eq!(*a.bodies.lock().unwrap(), vec!["1"]);
+        assert_eq!(*b.bodies.lock().unwrap(), vec!["2", "3", "4"]);
+        assert_eq!(sink.n_errors(), vec![1, 0]);
+
+        std::thread::sleep(Duration::from_millis(60));
+        sink.write("5").unwrap();
+        sink.write("6").unwrap();
+        assert_eq!(a.bodies.lock().unwrap().len() + b.bodies.lock().unwrap().len(), 6);
+        assert_eq!(a.bodies.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn it_splits_a_batch_across_endpoints_by_measurement_key() {
+        assert_eq!(line_key("cpu,host=a usage=1 1"), "cpu");
+        assert_eq!(line_key("c\\ p\\,u usage=1 1"), "c\\ p\\,u");
+        let nodes: Vec<Node> = (0..4).map(|_| Node::default()).collect();
+        let sink = LoadBalancedSink::new(nodes.iter().map(|n| Box::new(n.clone()) as Box<dyn Sink>).collect(),
+                                         Balance::KeyHash);
+        let body = (0..40).map(|i| format!("m{},host=a n={}i 1", i % 8, i)).collect::<Vec<_>>().join("\n");
+        sink.write(&body).unwrap();
+        sink.write(&body).unwrap();
+        let mut n_lines = 0;
+        let mut seen: Vec<String> = Vec::new();
+        for node in &nodes {
+            let bodies = node.bodies.lock().unwrap();
+            let mut keys: Vec<String> = bodies.iter().flat_map(|b| b.lines()).map(|l| line_key(l).to_string()).collect();
+            n_lines += keys.len();
+            keys.sort();
+            keys.dedup();
+            // every line for a given key ends up on the same endpoint
+            assert!(keys.iter().all(|key| !seen.contains(key)));
+            seen.extend(keys);
+        }
+        assert_eq!(seen.len(), 8);
+        assert_eq!(n_lines, 80);
+    }
+
+    #[test]
+    fn it_routes_measurements_by_key() {
+        let (default, other) = (Node::default(), Node::default());
+        let other_sink: Arc<dyn Sink> = Arc::new(other.clone());
+        let sink = RoutingSink::new(Arc::new(default.clone()))
+            .route("b", Arc::clone(&other_sink))
+            .route("c", Arc::clone(&other_sink));
+        sink.write("a n=1i 1\nb n=2i 2\nc n=3i 3\na n=4i 4").unwrap();
+        assert_eq!(*default.bodies.lock().unwrap(), vec!["a n=1i 1\na n=4i 4"]);
+        assert_eq!(*other.bodies.lock().unwrap(), vec!["b n=2i 2\nc n=3i 3"]);
+
+        assert!(sink.set_route("a", &other_sink));
+        assert!(!sink.set_route("a", &(Arc::new(Node::default()) as Arc<dyn Sink>)));
+        sink.write("a n=5i 5").unwrap();
+        assert_eq!(other.bodies.lock().unwrap().last().unwrap(), "a n=5i 5");
+    }
+
+    #[test]
+    fn it_writes_a_large_batch_in_pieces() {
+        let node = Node::default();
+        let sink = ChunkedSink::new(Arc::new(node.clone()), 9, 2);
+        sink.write("aaaa\nbbbb").unwrap();
+        sink.write("cccc\ndddd\neeee\nffff\ngggg").unwrap();
+        let mut bodies = node.bodies.lock().unwrap().clone();
+        assert_eq!(bodies[0], "aaaa\nbbbb");
+        bodies[1..].sort();
+        assert_eq!(bodies[1..], ["cccc\ndddd", "eeee\nffff", "gggg"]);
+
+        node.down.store(true, Ordering::Relaxed);
+        assert!(sink.write("cccc\ndddd\neeee").is_err());
+    }
+
+    #[test]
+    fn it_keeps_recent_points_in_memory() {
+        let mem = MemorySink::new(Duration::from_secs(60));
+        let t = crate::now();
+        let old = t - 120 * 1_000_000_000;
+        mem.clone().write(&format!("a\\ b,venue=x\\,y n=1i,s=\"c, d=e\" {}\nb n=1.5 {}\nold n=1i {}", t, t, old)).unwrap();
+        let points = mem.points();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].key, "a b");
+        assert_eq!(points[0].tag("venue"), Some("x,y"));
+        assert_eq!(points[0].field("n"), Some("1i"));
+        assert_eq!(points[0].field("s"), Some("\"c, d=e\""));
+        assert_eq!(points[0].timestamp, t);
+        assert_eq!(mem.filter(|p| p.key == "b")[0].field("n"), Some("1.5"));
+        assert!(mem.write("malformed").is_err());
+        mem.clear();
+        assert!(mem.points().is_empty());
+    }
+
+    #[test]
+    fn it_sends_a_batch_over_udp_in_pieces() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        let sink = UdpSink::new(&addr, 9).unwrap();
+        sink.write("aaaa\nbbbb\ncccc").unwrap();
+        let mut buf = [0u8; 64];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"aaaa\nbbbb");
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"cccc");
+    }
+
+    #[test]
+    fn it_skips_udp_datagrams_that_fail_without_failing_the_batch() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        // larger than any udp datagram can be
+        let huge = format!("b s=\"{}\"", "x".repeat(70_000));
+        let sink = UdpSink::new(&addr, 70_010).unwrap();
+        sink.write(&format!("a n=1i\n{}\nc n=3i", huge)).unwrap();
+        assert_eq!(sink.n_errors(), 1);
+        let mut buf = [0u8; 64];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"a n=1i");
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"c n=3i");
+        // with nothing sent, the batch can be retried
+        assert!(sink.write(&huge).is_err());
+        assert_eq!(sink.n_errors(), 2);
+    }
+
+    /// Strategies for the round trip tests. Text includes unicode, and the
+    /// characters line protocol escapes (spaces, commas) or quotes, but not
+    /// backslashes, which the serializer doesn't escape, or newlines, which
+    /// line protocol can't hold. Names have no "=", which the serializer
+    /// doesn't escape in tag keys or field names.
+    #[cfg(feature = "string-tags")]
+    mod round_trip {
+        use std::str::FromStr;
+        use chrono::{TimeZone, Utc};
+        use decimal::d128;
+        use proptest::prelude::*;
+        use uuid::Uuid;
+        use crate::{serialize_owned, OwnedMeasurement, OwnedValue};
+        use super::MemoryPoint;
+
+        /// what `escape_tag` leaves of a name: it removes, rather than
+        /// escapes, spaces, commas and quotes
+        fn stripped(name: &str) -> String {
+            name.replace([' ', ',', '"'], "")
+        }
+
+        fn name() -> impl Strategy<Value = String> {
+            "[a-zA-Z0-9_.:/ ,\"\\-éß日本語🚀]{1,10}".prop_filter("nothing left once stripped", |s| !stripped(s).is_empty())
+        }
+
+        fn text() -> impl Strategy<Value = String> {
+            "[a-zA-Z0-9_.:/ ,=\"\\-éß日本語🚀]{0,16}"
+        }
+
+        fn timestamp() -> impl Strategy<Value = i64> {
+            prop_oneof![Just(0), Just(-1), Just(i64::MIN), Just(i64::MAX), any::<i64>()]
+        }
+
+        fn value() -> impl Strategy<Value = OwnedValue> {
+            use proptest::num::f64::{NEGATIVE, NORMAL, POSITIVE, SUBNORMAL, ZERO};
+
+            prop_oneof![
+                text().prop_map(OwnedValue::String),
+                (POSITIVE | NEGATIVE | NORMAL | SUBNORMAL | ZERO).prop_map(OwnedValue::Float),
+                any::<i64>().prop_map(OwnedValue::Integer),
+                any::<bool>().prop_map(OwnedValue::Boolean),
+                (any::<i32>(), -20i32..20).prop_map(|(m, e)| OwnedValue::D128(d128::from_str(&format!("{}E{}", m, e)).unwrap())),
+                any::<u128>().prop_map(|u| OwnedValue::Uuid(Uuid::from_u128(u))),
+                timestamp().prop_map(|nanos| OwnedValue::DateTime(Utc.timestamp_nanos(nanos))),
+            ]
+        }
+
+        fn measurement() -> impl Strategy<Value = OwnedMeasurement> {
+            let tags = prop::collection::vec((name(), text()), 0..4);
+            let fields = prop::collection::vec((name(), value()), 1..5);
+            (name(), tags, fields, timestamp()).prop_map(|(key, tags, fields, timestamp)| {
+                let mut m = OwnedMeasurement::new(key).set_timestamp(timestamp);
+                for (k, v) in tags {
+                    m = m.add_tag_string(k, v);
+                }
+                for (k, v) in fields {
+                    m = m.add_field(k, v);
+                }
+                m
+            })
+        }
+
+        /// `parsed` (a field value as serialized) parsed back as the same
+        /// type as `value`
+        fn parse_like(value: &OwnedValue, parsed: &str) -> Option<OwnedValue> {
+            let quoted = || parsed.strip_prefix('"').and_then(|v| v.strip_suffix('"'));
+            let integer = || parsed.strip_suffix('i').and_then(|v| v.parse::<i64>().ok());
+            Some(match value {
+                OwnedValue::String(_) => OwnedValue::String(quoted()?.replace("\\\"", "\"")),
+                OwnedValue::Float(_) => OwnedValue::Float(parsed.parse().ok()?),
+                OwnedValue::Integer(_) => OwnedValue::Integer(integer()?),
+                OwnedValue::Boolean(_) => OwnedValue::Boolean(match parsed { "t" => true, "f" => false, _ => return None }),
+                OwnedValue::D128(_) => OwnedValue::D128(d128::from_str(parsed).ok()?),
+                OwnedValue::Uuid(_) => OwnedValue::Uuid(Uuid::parse_str(quoted()?).ok()?),
+                OwnedValue::DateTime(_) => OwnedValue::DateTime(Utc.timestamp_nanos(integer()?)),
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn serialized_measurements_parse_back_to_the_same_values(m in measurement()) {
+                let mut line = String::new();
+                serialize_owned(&m, &mut line);
+                let point = MemoryPoint::parse(&line);
+                prop_assert!(point.is_some(), "failed to parse {:?}", line);
+                let point = point.unwrap();
+
+                prop_assert_eq!(&point.key, &stripped(&m.key));
+                let tags: Vec<(String, String)> = m.tags.iter().map(|(k, v)| (stripped(k), v.clone())).collect();
+                prop_assert_eq!(&point.tags, &tags);
+                prop_assert_eq!(point.fields.len(), m.fields.len(), "{:?}", line);
+                for ((k, v), (parsed_k, parsed_v)) in m.fields.iter().zip(point.fields.iter()) {
+                    prop_assert_eq!(parsed_k, &stripped(k));
+                    prop_assert_eq!(parse_like(v, parsed_v), Some(v.clone()), "{:?}", line);
+                }
+                prop_assert_eq!(Some(point.timestamp), m.timestamp);
+            }
+        }
+    }
+}
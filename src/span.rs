@@ -0,0 +1,98 @@
+//! Timing guards that log entry/exit and send a duration measurement, see
+//! `InfluxWriter::span`.
+
+use std::cell::RefCell;
+use std::time::Instant;
+use slog::Logger;
+use crate::{InfluxWriter, OwnedMeasurement, OwnedValue};
+
+thread_local! {
+    /// names of the spans open on the current thread, innermost last
+    static OPEN: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Times a section of code, created with `InfluxWriter::span`.
+///
+/// When dropped, logs the elapsed time and sends a "span" measurement with
+/// tags "name" and "parent" (the enclosing span on the same thread, if
+/// any) and fields "duration_ns" and "depth" (0 for an outermost span).
+///
+#[must_use]
+pub struct Span<'a> {
+    influx: &'a InfluxWriter,
+    name: &'static str,
+    parent: Option<&'static str>,
+    depth: usize,
+    logger: Logger,
+    start: Instant,
+}
+
+impl<'a> Span<'a> {
+    pub(crate) fn enter(influx: &'a InfluxWriter, name: &'static str, logger: &Logger) -> Self {
+        let (parent, depth) = OPEN.with(|open| {
+            let mut open = open.borrow_mut();
+            let parent = open.last().cloned();
+            open.push(name);
+            (parent, open.len() - 1)
+        });
+        let logger = logger.new(o!("span" => name));
+        trace!(logger, "entering span"; "parent" => parent, "depth" => depth);
+        Span { influx, name, parent, depth, logger, start: Instant::now() }
+    }
+
+    /// Logger with the span's name (as "span"), for logging within the span.
+    ///
+    pub fn logger(&self) -> &Logger { &self.logger }
+}
+
+impl Drop for Span<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        OPEN.with(|open| {
+            let mut open = open.borrow_mut();
+            // spans are normally dropped innermost first, but needn't be
+            if let Some(i) = open.iter().rposition(|name| *name == self.name) {
+                open.remove(i);
+            }
+        });
+        debug!(self.logger, "exiting span"; "elapsed" => ?elapsed, "depth" => self.depth);
+        let mut m = OwnedMeasurement::new("span")
+            .add_tag("name", self.name)
+            .add_field("duration_ns", OwnedValue::Integer(elapsed.as_nanos() as i64))
+            .add_field("depth", OwnedValue::Integer(self.depth as i64));
+        if let Some(parent) = self.parent {
+            m = m.add_tag("parent", parent);
+        }
+        let _ = self.influx.send(m);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::bounded;
+    use slog::Drain;
+    use super::*;
+
+    #[test]
+    fn it_sends_a_duration_measurement_for_nested_spans() {
+        let (tx, rx) = bounded(8);
+        let influx = InfluxWriter::test_writer(tx);
+        let logger = Logger::root(slog::Discard.fuse(), o!());
+        {
+            let _outer = influx.span("outer", &logger);
+            {
+                let _inner = influx.span("inner", &logger);
+            }
+            let inner = rx.try_recv().unwrap().unwrap();
+            assert_eq!(inner.key, "span");
+            assert_eq!(inner.get_tag("name"), Some("inner"));
+            assert_eq!(inner.get_tag("parent"), Some("outer"));
+            assert_eq!(inner.get_field("depth"), Some(&OwnedValue::Integer(1)));
+        }
+        let outer = rx.try_recv().unwrap().unwrap();
+        assert_eq!(outer.get_tag("name"), Some("outer"));
+        assert_eq!(outer.get_tag("parent"), None);
+        assert_eq!(outer.get_field("depth"), Some(&OwnedValue::Integer(0)));
+        assert!(OPEN.with(|open| open.borrow().is_empty()));
+    }
+}
@@ -0,0 +1,168 @@
+//! On-disk queue of batches that could not be written, enabled with
+//! `InfluxWriterBuilder::spool_dir`.
+//!
+//! Each batch is stored (as line protocol) in its own file, named by
+//! sequence number so that batches are replayed oldest first, including
+//! batches left over from a previous run. Files are compressed if the
+//! writer has a `spool_compression`, and read according to their
+//! extension, so a spool can be reopened with a different compression.
+//! They're encrypted if the writer has an `encryption_key`.
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::compression::{self, FileCompression};
+use crate::encryption::EncryptionKey;
+
+const EXTENSION: &str = "lp";
+
+#[derive(Debug)]
+pub(crate) struct Spool {
+    dir: PathBuf,
+    files: VecDeque<PathBuf>,
+    next_seq: u64,
+    compression: Option<FileCompression>,
+    key: Option<EncryptionKey>,
+}
+
+impl Spool {
+    /// Opens the spool at `dir`, creating the directory if it does not exist.
+    /// Fails if it holds batches compressed in a way that isn't enabled.
+    ///
+    pub fn open(dir: &Path, compression: Option<FileCompression>, key: Option<EncryptionKey>) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut files: Vec<(u64, PathBuf)> = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let seq = path.file_name()
+                .and_then(|name| name.to_str())
+                .map(compression::strip_extension)
+                .and_then(|name| name.strip_suffix(EXTENSION))
+                .and_then(|name| name.strip_suffix('.'))
+                .and_then(|seq| seq.parse().ok());
+            if let Some(seq) = seq {
+                FileCompression::of_path(&path).map_err(|msg| io::Error::new(io::ErrorKind::Unsupported, msg))?;
+                files.push((seq, path));
+            }
+        }
+        files.sort_unstable();
+        let next_seq = files.last().map(|(seq, _)| seq + 1).unwrap_or(0);
+        let files = files.into_iter().map(|(_, path)| path).collect();
+        Ok(Spool { dir: dir.to_path_buf(), files, next_seq, compression, key })
+    }
+
+    fn path(&self, seq: u64) -> PathBuf {
+        match self.compression {
+            Some(compression) => self.dir.join(format!("{:020}.{}.{}", seq, EXTENSION, compression.extension())),
+            None => self.dir.join(format!("{:020}.{}", seq, EXTENSION)),
+        }
+    }
+
+    /// Number of spooled batches.
+    ///
+    pub fn len(&self) -> usize { self.files.len() }
+
+    /// Writes `batch` to the end of the spool.
+    ///
+    pub fn push(&mut self, batch: &str) -> io::Result<()> {
+        let path = self.path(self.next_seq);
+        // write to a temporary file first, so a partially written batch is
+        // never replayed
+        let tmp = path.with_extension("tmp");
+        let compressed = match self.compression {
+            Some(compression) => Cow::Owned(compression.compress(batch.as_bytes())?),
+            None => Cow::Borrowed(batch.as_bytes()),
+        };
+        match self.key {
+            Some(ref key) => fs::write(&tmp, key.encrypt(&compressed)?)?,
+            None => fs::write(&tmp, compressed)?,
+        }
+        fs::rename(&tmp, &path)?;
+        self.next_seq += 1;
+        self.files.push_back(path);
+        Ok(())
+    }
+
+    /// Removes and returns the oldest batch in the spool.
+    ///
+    pub fn pop(&mut self) -> Option<io::Result<String>> {
+        let path = self.files.pop_front()?;
+        Some(compression::read_to_string(&path, self.key.as_ref()).and_then(|batch| {
+            fs::remove_file(&path)?;
+            Ok(batch)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_replays_spooled_batches_in_order_across_reopen() {
+        let dir = std::env::temp_dir().join(format!("influx-writer-spool-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        {
+            let mut spool = Spool::open(&dir, None, None).unwrap();
+            assert_eq!(spool.len(), 0);
+            spool.push("a n=1i 1").unwrap();
+            spool.push("b n=2i 2").unwrap();
+        }
+        let mut spool = Spool::open(&dir, None, None).unwrap();
+        assert_eq!(spool.len(), 2);
+        spool.push("c n=3i 3").unwrap();
+        assert_eq!(spool.pop().unwrap().unwrap(), "a n=1i 1");
+        assert_eq!(spool.pop().unwrap().unwrap(), "b n=2i 2");
+        assert_eq!(spool.pop().unwrap().unwrap(), "c n=3i 3");
+        assert!(spool.pop().is_none());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn it_compresses_spooled_batches_and_replays_them_after_reopening_uncompressed() {
+        let dir = std::env::temp_dir().join(format!("influx-writer-spool-zstd-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        {
+            let mut spool = Spool::open(&dir, None, None).unwrap();
+            spool.push("a n=1i 1").unwrap();
+        }
+        {
+            let mut spool = Spool::open(&dir, Some(FileCompression::Zstd), None).unwrap();
+            spool.push("b n=2i 2").unwrap();
+        }
+        let mut names: Vec<String> = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![format!("{:020}.lp", 0), format!("{:020}.lp.zst", 1)]);
+        let mut spool = Spool::open(&dir, None, None).unwrap();
+        spool.push("c n=3i 3").unwrap();
+        assert_eq!(spool.pop().unwrap().unwrap(), "a n=1i 1");
+        assert_eq!(spool.pop().unwrap().unwrap(), "b n=2i 2");
+        assert_eq!(spool.pop().unwrap().unwrap(), "c n=3i 3");
+        assert!(spool.pop().is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn it_encrypts_spooled_batches() {
+        let dir = std::env::temp_dir().join(format!("influx-writer-spool-encryption-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let key = EncryptionKey::new([7; 32]);
+        {
+            let mut spool = Spool::open(&dir, None, Some(key.clone())).unwrap();
+            spool.push("a n=1i 1").unwrap();
+        }
+        let path = dir.join(format!("{:020}.lp", 0));
+        assert!(fs::read(&path).unwrap().starts_with(crate::encryption::MAGIC));
+        assert!(Spool::open(&dir, None, None).unwrap().pop().unwrap().is_err());
+        let mut spool = Spool::open(&dir, None, Some(key)).unwrap();
+        assert_eq!(spool.pop().unwrap().unwrap(), "a n=1i 1");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,114 @@
+//! Rate-limited logging, for error paths that can fire many times a second
+//! (see `error_throttled!`).
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    static ref START: Instant = Instant::now();
+}
+
+/// Tracks when a call site last logged, and how many times it has been
+/// suppressed since. Used by `error_throttled!` and friends, which keep one
+/// in a `static` at each call site.
+///
+#[derive(Debug, Default)]
+pub struct Throttle {
+    /// nanos since `START` (plus one, so 0 means never)
+    last: AtomicU64,
+    n_suppressed: AtomicUsize,
+}
+
+impl Throttle {
+    pub const fn new() -> Self {
+        Throttle { last: AtomicU64::new(0), n_suppressed: AtomicUsize::new(0) }
+    }
+
+    /// Returns the number of calls suppressed since the last one allowed,
+    /// if at least `interval` has passed since then, otherwise `None`.
+    ///
+    pub fn check(&self, interval: Duration) -> Option<usize> {
+        let now = START.elapsed().as_nanos() as u64 + 1;
+        let last = self.last.load(Ordering::Relaxed);
+        if (last == 0 || now.saturating_sub(last) >= interval.as_nanos() as u64)
+            && self.last.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+        {
+            return Some(self.n_suppressed.swap(0, Ordering::Relaxed))
+        }
+        self.n_suppressed.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+}
+
+/// Logs through the slog macro `$lvl` at most once per `$interval` from
+/// this call site, adding the number of records suppressed in between as
+/// "n_suppressed".
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate influx_writer;
+/// use std::time::Duration;
+/// # use slog::Drain;
+///
+/// fn main() {
+///     let logger = slog::Logger::root(slog::Discard.fuse(), slog::o!());
+///     for i in 0..1000 {
+///         // logged once, rather than 1000 times
+///         log_throttled!(error, logger, Duration::from_secs(1), "order rejected: {}", i; "venue" => "x");
+///     }
+/// }
+/// ```
+///
+#[macro_export]
+macro_rules! log_throttled {
+    ($lvl:ident, $l:expr, $interval:expr, $($args:tt)+) => {{
+        static THROTTLE: $crate::Throttle = $crate::Throttle::new();
+        if let Some(n_suppressed) = THROTTLE.check($interval) {
+            let logger = $l.new(::slog::o!("n_suppressed" => n_suppressed));
+            ::slog::$lvl!(logger, $($args)+);
+        }
+    }}
+}
+
+/// `error!`, logged at most once per `$interval` from this call site (see
+/// `log_throttled!`).
+///
+#[macro_export]
+macro_rules! error_throttled {
+    ($l:expr, $interval:expr, $($args:tt)+) => { $crate::log_throttled!(error, $l, $interval, $($args)+) }
+}
+
+/// `warn!`, logged at most once per `$interval` from this call site (see
+/// `log_throttled!`).
+///
+#[macro_export]
+macro_rules! warn_throttled {
+    ($l:expr, $interval:expr, $($args:tt)+) => { $crate::log_throttled!(warn, $l, $interval, $($args)+) }
+}
+
+/// `info!`, logged at most once per `$interval` from this call site (see
+/// `log_throttled!`).
+///
+#[macro_export]
+macro_rules! info_throttled {
+    ($l:expr, $interval:expr, $($args:tt)+) => { $crate::log_throttled!(info, $l, $interval, $($args)+) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_allows_one_call_per_interval_and_counts_the_rest() {
+        let throttle = Throttle::new();
+        let interval = Duration::from_millis(50);
+        assert_eq!(throttle.check(interval), Some(0));
+        assert_eq!(throttle.check(interval), None);
+        assert_eq!(throttle.check(interval), None);
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(throttle.check(interval), Some(2));
+        assert_eq!(throttle.check(interval), None);
+    }
+}
@@ -0,0 +1,100 @@
+//! TLS support for https endpoints (requires the "tls" feature).
+//!
+//! hyper 0.10 leaves TLS to implementors of its `SslClient` trait; this
+//! provides one backed by `native-tls`.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use hyper::net::{HttpStream, HttpsConnector, NetworkStream, SslClient};
+use hyper::client::Client;
+use native_tls::{Certificate, TlsConnector};
+use crate::Error;
+use crate::resolve::Resolving;
+
+/// Builds a `Client` capable of https requests, optionally trusting the
+/// (pem or der encoded) CA certificate at `ca_cert` in addition to the
+/// system's root certificates, and connecting through `resolving` if given.
+///
+pub(crate) fn https_client(ca_cert: Option<&Path>, resolving: Option<&Resolving>) -> Result<Client, Error> {
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = ca_cert {
+        let bytes = fs::read(path)
+            .map_err(|e| Error::Config(format!("failed to read ca cert {}: {}", path.display(), e)))?;
+        let cert = Certificate::from_pem(&bytes)
+            .or_else(|_| Certificate::from_der(&bytes))
+            .map_err(|e| Error::Config(format!("invalid ca cert {}: {}", path.display(), e)))?;
+        builder.add_root_certificate(cert);
+    }
+    let connector = builder.build()
+        .map_err(|e| Error::Config(format!("failed to initialize tls: {}", e)))?;
+    match resolving {
+        Some(resolving) => {
+            Ok(crate::pooled_client(HttpsConnector::with_connector(NativeTlsClient(connector), resolving.clone()), Some(resolving)))
+        }
+        None => Ok(crate::pooled_client(HttpsConnector::with_connector(NativeTlsClient(connector), crate::host::connect), None)),
+    }
+}
+
+struct NativeTlsClient(TlsConnector);
+
+impl SslClient for NativeTlsClient {
+    type Stream = TlsStream;
+
+    fn wrap_client(&self, stream: HttpStream, host: &str) -> hyper::Result<TlsStream> {
+        match self.0.connect(crate::host::unbracket(host), stream) {
+            Ok(stream) => Ok(TlsStream(Arc::new(Mutex::new(stream)))),
+            Err(e) => Err(hyper::Error::Ssl(Box::new(e))),
+        }
+    }
+}
+
+/// `NetworkStream` must be `Clone`, hence the `Arc<Mutex<_>>`.
+#[derive(Clone)]
+pub(crate) struct TlsStream(Arc<Mutex<native_tls::TlsStream<HttpStream>>>);
+
+impl TlsStream {
+    fn lock(&self) -> MutexGuard<'_, native_tls::TlsStream<HttpStream>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for TlsStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TlsStream(_)")
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+impl NetworkStream for TlsStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.lock().get_mut().peer_addr()
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.lock().get_ref().set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.lock().get_ref().set_write_timeout(dur)
+    }
+}
@@ -0,0 +1,184 @@
+//! Reloading runtime settings from a file, see
+//! `InfluxWriterBuilder::watch_config`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use slog::{FilterLevel, Logger};
+use crate::admin::Control;
+use crate::{Error, RoutingSink, Sink, WriterConfig};
+
+/// The routing sink, to move keys between the databases it writes to.
+///
+pub(crate) struct Router {
+    pub sink: Arc<RoutingSink>,
+    /// database -> its sink
+    pub targets: HashMap<String, Arc<dyn Sink>>,
+    /// see `SerializeOptions::key_prefix`
+    pub key_prefix: String,
+}
+
+/// The settings in a config file. Settings left out of the file are left
+/// as they are.
+///
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Settings {
+    pub log_level: Option<FilterLevel>,
+    pub paused: Option<bool>,
+    pub disabled_keys: Option<Vec<String>>,
+    pub buffer_size: Option<usize>,
+    pub flush_interval: Option<Duration>,
+    pub sampling: Vec<(String, u32)>,
+    pub routes: Vec<(String, String)>,
+}
+
+/// Parses `key = value` lines, skipping blank lines and comments
+/// (starting with "#").
+///
+pub(crate) fn parse(text: &str) -> Result<Settings, String> {
+    let mut settings = Settings::default();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue }
+        let invalid = |what: &str| format!("line {}: {}: {}", i + 1, what, line);
+        let (key, value) = line.split_once('=').ok_or_else(|| invalid("expected key = value"))?;
+        let (key, value) = (key.trim(), value.trim());
+        match key {
+            "log_level" => settings.log_level = Some(FilterLevel::from_str(value).map_err(|_| invalid("invalid log level"))?),
+            "paused" => settings.paused = Some(value.parse().map_err(|_| invalid("expected true or false"))?),
+            "disabled_keys" => {
+                settings.disabled_keys = Some(value.split(',').map(str::trim).filter(|k| !k.is_empty()).map(String::from).collect());
+            }
+            "buffer_size" => settings.buffer_size = Some(value.parse().map_err(|_| invalid("expected a number"))?),
+            "flush_interval_ms" => {
+                settings.flush_interval = Some(Duration::from_millis(value.parse().map_err(|_| invalid("expected a number"))?));
+            }
+            _ => match key.split_once('.') {
+                Some(("sample", k)) => settings.sampling.push((k.to_string(), value.parse().map_err(|_| invalid("expected a number"))?)),
+                Some(("route", k)) => settings.routes.push((k.to_string(), value.to_string())),
+                _ => return Err(invalid("unknown setting")),
+            }
+        }
+    }
+    Ok(settings)
+}
+
+/// Applies `settings`, logging each setting that changed.
+///
+pub(crate) fn apply(settings: &Settings, ctl: &Control, router: Option<&Router>, config: &RwLock<WriterConfig>, logger: &Logger) {
+    let before = config.read().map(|config| config.clone()).unwrap_or_default();
+    if let Some(level) = settings.log_level { ctl.set_level(level) }
+    if let Some(paused) = settings.paused { ctl.set_paused(paused) }
+    if let Some(ref keys) = settings.disabled_keys { ctl.set_disabled_keys(keys) }
+    if let Some(n) = settings.buffer_size { ctl.set_buffer_size(n) }
+    if let Some(interval) = settings.flush_interval { ctl.set_flush_interval(interval) }
+    for (key, n) in &settings.sampling {
+        ctl.set_sample_rate(key, *n);
+    }
+    for (key, db) in &settings.routes {
+        let target = router.and_then(|router| router.targets.get(db).map(|target| (router, target)));
+        match target {
+            Some((router, target)) => {
+                // as written by the serializer
+                let line_key = format!("{}{}", crate::escape_tag(&router.key_prefix), crate::escape_tag(key));
+                router.sink.set_route(&line_key, target);
+                if let Ok(mut config) = config.write() {
+                    config.routes.retain(|(k, _)| k != key);
+                    config.routes.push((key.clone(), db.clone()));
+                    config.routes.sort();
+                }
+            }
+            None => {
+                warn!(logger, "InfluxWriter: config file routes to a database the writer wasn't built with, ignored";
+                    "key" => key, "db" => db);
+            }
+        }
+    }
+    ctl.update_config();
+    if let Ok(after) = config.read() {
+        for (setting, old, new) in before.diff(&after) {
+            info!(logger, "InfluxWriter: config changed"; "setting" => setting, "old" => old, "new" => new);
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Applies the settings in `path` now, returning an error if it can't be
+/// read or parsed, then polls it every `interval`, applying them again
+/// whenever it is modified, until `ctl` is dropped.
+///
+pub(crate) fn watch(path: PathBuf, interval: Duration, ctl: &Arc<Control>, router: Option<Router>, config: Arc<RwLock<WriterConfig>>, logger: &Logger) -> Result<(), Error> {
+    let load = |path: &Path| -> Result<Settings, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        parse(&text)
+    };
+    let settings = load(&path).map_err(|e| Error::Config(format!("invalid config file {}: {}", path.display(), e)))?;
+    apply(&settings, ctl, router.as_ref(), &config, logger);
+    let logger = logger.new(o!("thread" => "InfluxWriter:config", "path" => path.display().to_string()));
+    let ctl: Weak<Control> = Arc::downgrade(ctl);
+    let mut last = modified(&path);
+    thread::Builder::new().name("inflx-config".to_string()).spawn(move || {
+        loop {
+            thread::sleep(interval);
+            let ctl = match ctl.upgrade() {
+                Some(ctl) => ctl,
+                None => return,
+            };
+            let now = modified(&path);
+            if now == last { continue }
+            last = now;
+            match load(&path) {
+                Ok(settings) => apply(&settings, &ctl, router.as_ref(), &config, &logger),
+                Err(e) => warn!(logger, "InfluxWriter: failed to reload config file, keeping current settings: {}", e),
+            }
+        }
+    }).map_err(Error::Spawn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Counters, MemorySink};
+    use super::*;
+
+    #[test]
+    fn it_parses_and_applies_a_config_file() {
+        let text = "# comment\nlog_level = warn\n\nbuffer_size = 16\nflush_interval_ms = 250\nsample.ticks = 10\nroute.fills = archive\nroute.x = nope\nroute.big fills = archive\n";
+        let settings = parse(text).unwrap();
+        assert_eq!(settings.log_level, Some(FilterLevel::Warning));
+        assert_eq!(settings.sampling, vec![("ticks".to_string(), 10)]);
+        assert!(parse("buffer_size = lots").unwrap_err().starts_with("line 1:"));
+        assert!(parse("colour = red").is_err());
+
+        let config = Arc::new(RwLock::new(WriterConfig { buffer_size: 4096, ..Default::default() }));
+        let (ctl, _) = Control::new(Arc::new(Counters::default()), Arc::clone(&config));
+        let archived = MemorySink::new(Duration::from_secs(60));
+        let archive: Arc<dyn Sink> = Arc::new(archived.clone());
+        let router = Router {
+            sink: Arc::new(RoutingSink::new(Arc::new(MemorySink::new(Duration::from_secs(60)))).route("other", Arc::clone(&archive))),
+            targets: vec![("archive".to_string(), archive)].into_iter().collect(),
+            key_prefix: String::new(),
+        };
+        let logger = Logger::root(slog::Discard, o!());
+        apply(&settings, &ctl, Some(&router), &config, &logger);
+        assert_eq!(ctl.buffer_size(), 16);
+        assert_eq!(ctl.flush_interval(), Duration::from_millis(250));
+        let mut version = 0;
+        assert_eq!(ctl.sampling_since(&mut version).unwrap().get("ticks"), Some(&10));
+        assert!(ctl.sampling_since(&mut version).is_none());
+        let config = config.read().unwrap();
+        assert_eq!(config.log_level, "warning");
+        assert_eq!(config.routes, vec![("big fills".to_string(), "archive".to_string()), ("fills".to_string(), "archive".to_string())]);
+        // keys are matched as serialized, i.e. with the space removed
+        let now = crate::now();
+        router.sink.write(&format!("bigfills n=1i {}\nfills n=2i {}", now, now)).unwrap();
+        assert_eq!(archived.points().len(), 2);
+    }
+}
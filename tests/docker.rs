@@ -0,0 +1,176 @@
+//! Integration tests that run the full write pipeline against a real
+//! influxdb server, started in a throwaway docker container.
+//!
+//! Requires a working `docker` command. Run with:
+//!
+//! ```console
+//! cargo test --features docker-tests --test docker
+//! ```
+//!
+//! The image can be overridden with the `INFLUX_WRITER_TEST_IMAGE` env
+//! var (default: `influxdb:1.8`).
+
+#![cfg(feature = "docker-tests")]
+
+#[macro_use]
+extern crate influx_writer;
+
+use std::io::Read;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use hyper::client::Client;
+use hyper::status::StatusCode;
+use hyper::Url;
+use influx_writer::{InfluxWriter, OwnedMeasurement, OwnedValue};
+
+const DEFAULT_IMAGE: &str = "influxdb:1.8";
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A running influxdb container, removed on drop.
+struct InfluxContainer {
+    id: String,
+    /// "127.0.0.1:<port>", the host port mapped to the container's 8086
+    addr: String,
+}
+
+impl InfluxContainer {
+    fn start() -> Self {
+        let image = std::env::var("INFLUX_WRITER_TEST_IMAGE").unwrap_or_else(|_| DEFAULT_IMAGE.to_string());
+        let out = Command::new("docker")
+            .args(&["run", "-d", "--rm", "-p", "127.0.0.1::8086", &image])
+            .output()
+            .expect("failed to run docker");
+        assert!(out.status.success(), "docker run failed: {}", String::from_utf8_lossy(&out.stderr));
+        let id = String::from_utf8_lossy(&out.stdout).trim().to_string();
+
+        // 8086 is mapped to a random host port, since a local server may
+        // already be bound to 8086
+        let out = Command::new("docker")
+            .args(&["port", &id, "8086/tcp"])
+            .output()
+            .expect("failed to run docker port");
+        let addr = String::from_utf8_lossy(&out.stdout).lines().next().unwrap_or("").trim().to_string();
+        let container = InfluxContainer { id, addr };
+        assert!(!container.addr.is_empty(), "failed to get host port of container {}", container.id);
+        container.wait_until_ready();
+        container
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    fn wait_until_ready(&self) {
+        let client = Client::new();
+        let start = Instant::now();
+        loop {
+            match client.get(&self.url("/ping")).send() {
+                Ok(resp) if resp.status == StatusCode::NoContent => return,
+                _ if start.elapsed() > STARTUP_TIMEOUT => panic!("influxdb container not ready after {:?}", STARTUP_TIMEOUT),
+                _ => thread::sleep(Duration::from_millis(250)),
+            }
+        }
+    }
+
+    /// Runs `q` against the server's `/query` endpoint, returning the
+    /// response body (json).
+    fn query(&self, db: &str, q: &str) -> serde_json::Value {
+        let url = Url::parse_with_params(&self.url("/query"), &[("db", db), ("q", q), ("epoch", "ns")]).unwrap();
+        let client = Client::new();
+        let mut resp = client.post(url).send().expect("query request failed");
+        let mut body = String::new();
+        resp.read_to_string(&mut body).unwrap();
+        assert_eq!(resp.status, StatusCode::Ok, "query failed: {}", body);
+        serde_json::from_str(&body).expect("failed to parse query response")
+    }
+
+    fn create_database(&self, db: &str) {
+        self.query(db, &format!("CREATE DATABASE {}", db));
+    }
+
+    /// Returns the rows of the first series in the result of `q`.
+    fn rows(&self, db: &str, q: &str) -> Vec<Vec<serde_json::Value>> {
+        let resp = self.query(db, q);
+        resp["results"][0]["series"][0]["values"]
+            .as_array()
+            .map(|rows| {
+                rows.iter()
+                    .map(|row| row.as_array().cloned().unwrap_or_default())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for InfluxContainer {
+    fn drop(&mut self) {
+        let _ = Command::new("docker").args(&["rm", "-f", &self.id]).output();
+    }
+}
+
+#[test]
+fn it_writes_measurements_and_reads_them_back() {
+    let influx_db = InfluxContainer::start();
+    influx_db.create_database("docker_test");
+
+    let influx = InfluxWriter::new(&influx_db.addr, "docker_test");
+    let start = 1_600_000_000_000_000_000i64;
+    for i in 0..100i64 {
+        measure!(influx, docker_test_meas, t(color, "red"), i(n, i), f(x, i as f64 / 2.0), tm(start + i));
+    }
+    let m = OwnedMeasurement::new("docker_test_meas")
+        .add_tag("color", "blue")
+        .add_field("n", OwnedValue::Integer(-1))
+        .add_field("s", OwnedValue::String("has \"quotes\", commas and spaces".to_string()))
+        .set_timestamp(start + 1_000);
+    influx.send(m).unwrap();
+    drop(influx); // flushes the remaining buffer
+
+    let rows = influx_db.rows("docker_test", "SELECT count(n) FROM docker_test_meas WHERE color = 'red'");
+    assert_eq!(rows[0][1], 100);
+
+    let rows = influx_db.rows("docker_test", "SELECT n, x FROM docker_test_meas WHERE color = 'red' ORDER BY time DESC LIMIT 1");
+    assert_eq!(rows[0][0], start + 99);
+    assert_eq!(rows[0][1], 99);
+    assert_eq!(rows[0][2], 49.5);
+
+    let rows = influx_db.rows("docker_test", "SELECT n, s FROM docker_test_meas WHERE color = 'blue'");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0][1], -1);
+    assert_eq!(rows[0][2], "has \"quotes\", commas and spaces");
+}
+
+#[test]
+fn it_sends_batches_larger_than_the_buffer_size() {
+    let influx_db = InfluxContainer::start();
+    influx_db.create_database("docker_test_large");
+
+    let influx = InfluxWriter::new(&influx_db.addr, "docker_test_large");
+    let start = 1_600_000_000_000_000_000i64;
+    let n_points = 10_000i64;
+    for i in 0..n_points {
+        measure!(influx, docker_test_large, i(n, i), tm(start + i));
+    }
+    drop(influx);
+
+    let rows = influx_db.rows("docker_test_large", "SELECT count(n), sum(n) FROM docker_test_large");
+    assert_eq!(rows[0][1], n_points);
+    assert_eq!(rows[0][2], n_points * (n_points - 1) / 2);
+}
+
+#[test]
+fn it_creates_the_database_on_start() {
+    let influx_db = InfluxContainer::start();
+
+    let influx = InfluxWriter::builder(&influx_db.addr, "docker_test_created")
+        .create_database(true)
+        .check_on_start(true)
+        .try_build()
+        .unwrap();
+    measure!(influx, docker_test_created, i(n, 1));
+    drop(influx);
+
+    let rows = influx_db.rows("docker_test_created", "SELECT count(n) FROM docker_test_created");
+    assert_eq!(rows[0][1], 1);
+}
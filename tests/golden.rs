@@ -0,0 +1,159 @@
+//! Compares serialized output against the line protocol recorded in
+//! `tests/golden/serialize_owned.txt`, so changes to the serializer can't
+//! silently change what goes over the wire.
+//!
+//! Each case in the golden file is a `# <name>` line followed by the
+//! expected serialized line. If a change to the serializer is intentional,
+//! regenerate the file with
+//!
+//! ```console
+//! UPDATE_GOLDEN=1 cargo test --test golden
+//! ```
+//!
+//! and review the diff (ideally, write the new file to a test server
+//! with `influx -import` to confirm the server parses it as intended).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use decimal::d128;
+use uuid::Uuid;
+use influx_writer::{OwnedMeasurement, OwnedValue, serialize_owned};
+
+const T: i64 = 1_600_000_000_123_456_789;
+
+fn cases() -> Vec<(&'static str, OwnedMeasurement)> {
+    vec![
+        ("integer_field",
+            OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1)).set_timestamp(T)),
+
+        ("negative_integer_field",
+            OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(-42)).set_timestamp(T)),
+
+        ("float_fields",
+            OwnedMeasurement::new("test")
+                .add_field("a", OwnedValue::Float(1.5))
+                .add_field("b", OwnedValue::Float(-0.000123))
+                .add_field("c", OwnedValue::Float(12345678.9))
+                .add_field("d", OwnedValue::Float(1.0))
+                .set_timestamp(T)),
+
+        ("boolean_fields",
+            OwnedMeasurement::new("test")
+                .add_field("yes", OwnedValue::Boolean(true))
+                .add_field("no", OwnedValue::Boolean(false))
+                .set_timestamp(T)),
+
+        ("string_field",
+            OwnedMeasurement::new("test").add_field("s", OwnedValue::String("hello world".into())).set_timestamp(T)),
+
+        ("string_field_with_quotes",
+            OwnedMeasurement::new("test").add_field("s", OwnedValue::String(r#"say "hi""#.into())).set_timestamp(T)),
+
+        ("string_field_with_escaped_quotes",
+            OwnedMeasurement::new("test").add_field("s", OwnedValue::String(r#"already \"escaped\""#.into())).set_timestamp(T)),
+
+        ("d128_field",
+            OwnedMeasurement::new("test").add_field("d", OwnedValue::D128(d128::from_str("42.42").unwrap())).set_timestamp(T)),
+
+        ("uuid_field",
+            OwnedMeasurement::new("test")
+                .add_field("u", OwnedValue::Uuid(Uuid::from_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap()))
+                .set_timestamp(T)),
+
+        ("tags",
+            OwnedMeasurement::new("test")
+                .add_tag("exchange", "plnx")
+                .add_tag("ticker", "xmr_btc")
+                .add_field("n", OwnedValue::Integer(1))
+                .set_timestamp(T)),
+
+        ("tag_value_with_space_and_comma",
+            OwnedMeasurement::new("test")
+                .add_tag("msg", "a b,c")
+                .add_field("n", OwnedValue::Integer(1))
+                .set_timestamp(T)),
+
+        ("tag_key_with_space_and_comma",
+            OwnedMeasurement::new("test")
+                .add_tag("a b,c", "x")
+                .add_field("n", OwnedValue::Integer(1))
+                .set_timestamp(T)),
+
+        ("key_with_space_comma_and_quote",
+            OwnedMeasurement::new("te st,\"x\"").add_field("n", OwnedValue::Integer(1)).set_timestamp(T)),
+
+        ("non_finite_fields_skipped",
+            OwnedMeasurement::new("test")
+                .add_field("nan", OwnedValue::Float(f64::NAN))
+                .add_field("x", OwnedValue::Float(2.5))
+                .add_field("inf", OwnedValue::Float(f64::INFINITY))
+                .set_timestamp(T)),
+
+        ("all_fields_non_finite",
+            OwnedMeasurement::new("test").add_field("nan", OwnedValue::Float(f64::NAN)).set_timestamp(T)),
+
+        ("no_fields",
+            OwnedMeasurement::new("test").set_timestamp(T)),
+
+        ("no_timestamp",
+            OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1))),
+
+        ("negative_timestamp",
+            OwnedMeasurement::new("test").add_field("n", OwnedValue::Integer(1)).set_timestamp(-1)),
+
+        ("mixed",
+            OwnedMeasurement::new("trade")
+                .add_tag("exchange", "bmex")
+                .add_tag("side", "bid")
+                .add_field("price", OwnedValue::Float(9876.5))
+                .add_field("amount", OwnedValue::D128(d128::from_str("0.25").unwrap()))
+                .add_field("n", OwnedValue::Integer(3))
+                .add_field("maker", OwnedValue::Boolean(true))
+                .add_field("note", OwnedValue::String("ok".into()))
+                .set_timestamp(T)),
+    ]
+}
+
+fn golden_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/serialize_owned.txt")
+}
+
+fn parse_golden(s: &str) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    let mut lines = s.lines();
+    while let Some(line) = lines.next() {
+        if let Some(name) = line.strip_prefix("# ") {
+            let expected = lines.next().expect("golden file: case name without a line");
+            out.insert(name.to_string(), expected.to_string());
+        }
+    }
+    out
+}
+
+#[test]
+fn serialized_output_matches_golden_file() {
+    let actual: Vec<(&str, String)> = cases().into_iter()
+        .map(|(name, m)| {
+            let mut buf = String::new();
+            serialize_owned(&m, &mut buf);
+            (name, buf)
+        }).collect();
+
+    if std::env::var("UPDATE_GOLDEN").is_ok() {
+        let mut out = String::new();
+        for (name, line) in &actual {
+            out.push_str(&format!("# {}\n{}\n", name, line));
+        }
+        fs::write(golden_path(), out).unwrap();
+        return
+    }
+
+    let golden = parse_golden(&fs::read_to_string(golden_path()).unwrap());
+    assert_eq!(golden.len(), actual.len(), "golden file and test cases are out of sync");
+    for (name, line) in &actual {
+        let expected = golden.get(*name).unwrap_or_else(|| panic!("no golden line for case {}", name));
+        assert_eq!(line, expected, "case {}", name);
+    }
+}